@@ -2,43 +2,96 @@ use anyhow::{Ok, Result};
 use std::collections::HashMap;
 use std::{fs::File, io::Write};
 // use distro_pioneer::types::config::Config;
-use distro_pioneer::types::config::{Config, Content, StringOr};
+use distro_pioneer::types::config::{
+    AliasSpec, AptPackage, CommandEntry, CommandSpec, Completion, CompletionWords, Condition,
+    Config, Content, EnvEntry, EnvSpec, EnvrcEntry, EnvrcSpec, StringOr,
+};
 
 fn main() -> Result<()> {
     {
         let mut config = Config::default();
 
         // sw
-        config.install.apt = Some(vec!["sw1".into(), "sw2".into()]);
+        config.install.apt = Some(vec![
+            StringOr::String("sw1".into()),
+            StringOr::Object(AptPackage {
+                name: "sw2".into(),
+                version: Some("1.2.*".into()),
+                hold: Some(true),
+                ..Default::default()
+            }),
+        ]);
 
         // rc
         config.install.envrc = Some(vec![
-            Content::File("rc1".into()),
-            Content::Raw("rc3 content".into()),
+            EnvrcEntry::Content(Content::File("rc1".into())),
+            EnvrcEntry::Content(Content::Raw("rc3 content".into())),
+            EnvrcEntry::Spec(Box::new(EnvrcSpec {
+                content: Content::File("nvm.sh".into()),
+                lazy: Some(vec!["nvm".into(), "node".into()]),
+                when: None,
+            })),
         ]);
 
         let mut env = HashMap::new();
 
-        env.insert("ENVA".into(), "A".into());
-        env.insert("ENVB".into(), "B".into());
+        env.insert("ENVA".into(), EnvEntry::Value(StringOr::String("A".into())));
+        env.insert("ENVB".into(), EnvEntry::Value(StringOr::String("B".into())));
+        env.insert(
+            "ENVC".into(),
+            EnvEntry::Spec(Box::new(EnvSpec {
+                value: StringOr::String("C".into()),
+                when: Some(Condition {
+                    arch: Some(vec!["x86_64".into()]),
+                    ..Default::default()
+                }),
+            })),
+        );
 
         config.install.env = Some(env);
 
         let mut command = HashMap::new();
-        command.insert("command1".into(), StringOr::String("echo command1".into()));
+        command.insert(
+            "command1".into(),
+            CommandEntry::Content(StringOr::String("echo command1".into())),
+        );
         command.insert(
             "command2".into(),
-            StringOr::Object(Content::Raw("echo command2".into())),
+            CommandEntry::Content(StringOr::Object(Content::Raw("echo command2".into()))),
         );
         command.insert(
             "command3".into(),
-            StringOr::Object(Content::File("path/to/command3".into())),
+            CommandEntry::Content(StringOr::Object(Content::File("path/to/command3".into()))),
+        );
+        command.insert(
+            "command4".into(),
+            CommandEntry::Spec(Box::new(CommandSpec {
+                content: StringOr::String("echo command4 $@".into()),
+                completion: Some(Completion::Words(CompletionWords {
+                    subcommands: Some(vec!["start".into(), "stop".into()]),
+                    flags: Some(vec!["--help".into()]),
+                })),
+                when: Some(Condition {
+                    arch: Some(vec!["x86_64".into()]),
+                    ..Default::default()
+                }),
+            })),
         );
 
         config.install.command = Some(command);
 
         let mut alias = HashMap::new();
-        alias.insert("cargo".into(), "echo cargo".into());
+        alias.insert("cargo".into(), StringOr::String("echo cargo".into()));
+        alias.insert(
+            "explorer".into(),
+            StringOr::Object(AliasSpec {
+                command: "explorer.exe .".into(),
+                when: Some(Condition {
+                    kernel: Some("*WSL2*".into()),
+                    ..Default::default()
+                }),
+            }),
+        );
         config.install.alias = Some(alias);
 
         let toml = toml::to_string(&config)?;
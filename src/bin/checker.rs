@@ -1,22 +1,34 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, ensure};
 use clap::{Parser, command};
-use distro_pioneer::types::config::Config;
+use distro_pioneer::{
+    condition::Host,
+    deploy::{deployer::Deployer, validator::Validator},
+    loader::ConfigLoader,
+    runner::{CommandRunner, SystemRunner},
+    template::Template,
+};
 use glob::glob;
-use std::fs;
+use std::collections::HashMap;
 use std::path::PathBuf;
+use std::rc::Rc;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// 需要检查的文件
-    #[arg(name = "toml file")]
+    /// 需要检查的文件，支持toml、yaml、json和jsonc格式，默认检查当前目录下的所有toml文件
+    #[arg(name = "config file")]
     configs: Vec<PathBuf>,
+
+    /// 忽略配置中不认识的字段，默认报错
+    #[arg(long)]
+    lenient: bool,
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
 
-    let all_toml = if args.configs.len() == 0 {
+    /* 目录中的其他json/yaml文件不一定是配置，如configlist.schema.json，需要明确指定 */
+    let all_configs = if args.configs.is_empty() {
         glob("*.toml")
             .context("Fail to find toml file")?
             .filter_map(Result::ok)
@@ -25,15 +37,70 @@ fn main() -> Result<()> {
         args.configs
     };
 
-    for config_file in all_toml {
-        let config = fs::read_to_string(&config_file).context(format!(
-            "Fail to read file: {}",
-            config_file.to_string_lossy()
-        ))?;
+    let runner: Rc<dyn CommandRunner> = Rc::new(SystemRunner);
+    let host = Host::detect(runner.clone());
+    let deploy_dir = Deployer::deploy_dir(runner.as_ref())?;
+    let mut validator = Validator::new(runner);
+
+    for config_file in all_configs {
+        let configs = match ConfigLoader::load_list(&config_file, !args.lenient) {
+            Ok(configs) => configs,
+            Err(err) => {
+                validator.report(format!("{err:#}"));
+                continue;
+            }
+        };
+
+        for config in configs {
+            let origin = format!(
+                "{} in {}",
+                config.infomation.name,
+                config_file.to_string_lossy()
+            );
+
+            for (location, when) in config.conditions() {
+                if let Err(err) = when.validate() {
+                    validator.report(format!(
+                        "{origin}: invaild condition at {location}: {err:#}"
+                    ));
+                }
+            }
+
+            for input in config.inputs.iter().flatten() {
+                if let Err(err) = input.validate() {
+                    validator.report(format!("{origin}: invaild input: {err:#}"));
+                }
+            }
+
+            /* 检查引用的变量是否都有定义，输入使用默认值代替 */
+            let inputs: HashMap<_, _> = config
+                .inputs
+                .iter()
+                .flatten()
+                .map(|input| {
+                    (
+                        input.name.clone(),
+                        input.default.clone().unwrap_or_default(),
+                    )
+                })
+                .collect();
+            let rendered =
+                Template::from_config(&config, &config_file, &deploy_dir, &host, &inputs)
+                    .and_then(|template| template.render_config(&config));
+
+            /* 变量渲染失败时无法继续检查各项 */
+            match rendered {
+                Ok(rendered) => validator.validate(&rendered, &config_file),
+                Err(err) => validator.report(format!("{origin}: {err:#}")),
+            }
+        }
+    }
 
-        let _: Config = toml::from_str(&config)
-            .context(format!("File {} is invaild", config_file.to_string_lossy()))?;
+    let problems = validator.problems();
+    for problem in problems {
+        eprintln!("{problem}");
     }
+    ensure!(problems.is_empty(), "Found {} problems", problems.len());
 
     Ok(())
 }
@@ -1,10 +1,11 @@
 use anyhow::{Context, Result, anyhow};
 use clap::{ArgGroup, Parser, command};
 use distro_pioneer::{
-    builder::{base64_encode, build_target, unique_string},
-    types::config::Config,
+    builder::{apt_bundle, base64_encode, build_target, unique_string},
+    loader::{ConfigFormat, ConfigLoader},
+    runner::SystemRunner,
 };
-use std::{fs, path::PathBuf};
+use std::{fs, path::PathBuf, rc::Rc};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -27,13 +28,25 @@ struct Args {
     #[arg(short, default_value_t = true)]
     release: bool,
 
-    /// 需要一起打包到script的配置文件，至少提供一个
-    #[arg(short, required = true, name=".toml", num_args = 1..)]
+    /// 需要一起打包到script的配置文件，至少提供一个，支持toml、yaml、json和jsonc格式
+    #[arg(short, required = true, name = "config", num_args = 1..)]
     configs: Vec<PathBuf>,
 
+    /// 只打包这些名字的配置(以及它们requires的配置)，可以重复或用逗号分隔，默认打包全部
+    #[arg(long, value_name = "NAME", value_delimiter = ',')]
+    only: Vec<String>,
+
     /// 输出脚本的文件名
     #[arg(short, default_value = "installer.sh")]
     output: PathBuf,
+
+    /// 在本机下载所有apt软件包及其依赖并打包到脚本中，用于无法联网的机器，本机需要与目标机器是相同的发行版本
+    #[arg(long)]
+    offline_apt: bool,
+
+    /// 忽略配置中不认识的字段，默认报错，打包的脚本安装时也会忽略它们
+    #[arg(long)]
+    lenient: bool,
 }
 
 fn main() -> Result<()> {
@@ -50,31 +63,56 @@ fn main() -> Result<()> {
 
     let base64 = base64_encode(bin)?;
 
-    let mut configs = Vec::new();
-
-    for config_file in &args.configs {
-        let config_str = fs::read_to_string(config_file).context(format!(
-            "Fail to read file: {}",
-            config_file.to_string_lossy()
-        ))?;
-
-        let config: Config = toml::from_str(config_str.as_str())?;
+    let mut loaded = Vec::new();
+    for (index, config_file) in args.configs.iter().enumerate() {
+        for config in ConfigLoader::load_list(config_file, !args.lenient)? {
+            loaded.push((index, config));
+        }
+    }
+    let selected = ConfigLoader::select(loaded, &args.only, |(_, config)| config)?;
+
+    let mut embeds = Vec::new();
+    for (index, config_file) in args.configs.iter().enumerate() {
+        let configs: Vec<_> = selected
+            .iter()
+            .filter(|(file, _)| *file == index)
+            .map(|(_, config)| config.clone())
+            .collect();
+
+        if configs.is_empty() {
+            continue;
+        }
 
-        configs.push(config);
+        /* 引用了其他配置或只选中了部分配置时打包展开后的配置，目标机器上不需要被引用的配置文件 */
+        let content = ConfigLoader::embed(config_file, &configs)?;
+        let name = match configs.as_slice() {
+            [config] => config.infomation.name.clone(),
+            _ => config_file
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+        };
+        embeds.push((ConfigFormat::from_path(config_file)?, name, content));
     }
 
+    let configs: Vec<_> = selected
+        .into_iter()
+        .map(|(index, config)| (args.configs[index].clone(), config))
+        .collect();
+
     let eof = unique_string();
 
     let mut mk_configs = "config_files=()\n".to_string();
-    for config in configs {
-        let msg = format!("echo making {}.toml...", config.infomation.name);
+    for (format, name, content) in &embeds {
+        let msg = format!("echo making {name}.{}...", format.extension());
         let mk_file = format!(
-            "config_file=$(mktemp --suffix=.{}.toml)",
-            config.infomation.name
+            "config_file=$(mktemp --suffix=.{name}.{})",
+            format.extension()
         );
         let config_file = format!(
             "cat > ${{config_file}} <<'{eof}'\n{}\n{eof}",
-            toml::to_string_pretty(&config)?
+            content.trim_end()
         );
         let append_configs = "config_files=(${config_files[@]} $config_file)";
 
@@ -85,21 +123,46 @@ fn main() -> Result<()> {
     }
 
     let mk_bin = {
-        let mk_file = format!("bin_exe=$(mktemp --suffix=.bin)");
+        let mk_file = "bin_exe=$(mktemp --suffix=.bin)";
         let decode = format!("base64 -d > ${{bin_exe}} <<'{eof}'\n{}\n{eof}", base64);
         let chmod = "chmod +x ${bin_exe}";
         format!("\n{}\n{}\n{}\n", mk_file, decode, chmod)
     };
 
-    let run_bin = "RUST_LOG=info ${bin_exe} install ${config_files[@]}";
+    let mk_bundle = match args.offline_apt {
+        true => {
+            let mk_dir = "apt_bundle=$(mktemp -d)";
+            let decode = format!(
+                "base64 -d <<'{eof}' | tar -xz -C ${{apt_bundle}}\n{}\n{eof}",
+                apt_bundle(&configs, Rc::new(SystemRunner))?
+            );
+            /* apt以_apt用户读取本地软件源 */
+            let chmod = "chmod 755 ${apt_bundle}";
+            format!("\n{}\n{}\n{}\n", mk_dir, decode, chmod)
+        }
+        false => String::new(),
+    };
+
+    let mut install_args = String::new();
+    if args.offline_apt {
+        install_args.push_str(" --apt-bundle ${apt_bundle}");
+    }
+    if args.lenient {
+        install_args.push_str(" --lenient");
+    }
+    let run_bin = format!("RUST_LOG=info ${{bin_exe}} install{install_args} ${{config_files[@]}}");
     let pass_run = format!("test $# -eq 0 && {run_bin} || ${{bin_exe}} ${{@:1}}");
 
     let rm_bin = "rm ${bin_exe}";
     let rm_configs = "rm ${config_files[@]}";
+    let rm_bundle = match args.offline_apt {
+        true => "rm -rf ${apt_bundle}",
+        false => "",
+    };
 
     let script = format!(
-        "#!/bin/bash\n{}\n{}\n{}\n{}\n{}\n",
-        mk_configs, mk_bin, pass_run, rm_bin, rm_configs,
+        "#!/bin/bash\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n",
+        mk_configs, mk_bin, mk_bundle, pass_run, rm_bin, rm_configs, rm_bundle,
     );
 
     fs::write(&args.output, script).context(format!(
@@ -1,9 +1,18 @@
 use anyhow::{Context, Result};
 use clap::{Parser, command};
-use distro_pioneer::{deploy::deployer::Deployer, log::log_init};
+use distro_pioneer::{
+    deploy::deployer::{Deployer, RefreshPolicy},
+    input::Answers,
+    log::log_init,
+    runner::{CommandRunner, SystemRunner},
+    secret::SecretKey,
+};
 use log::info;
 use std::fs;
-use std::path::PathBuf;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -13,10 +22,73 @@ enum Args {
         /// 用于pioneer的配置文件列表
         #[arg(required = true, num_args = 1..)]
         configs: Vec<PathBuf>,
+
+        /// 只选择这些名字的配置(以及它们requires的配置)，可以重复或用逗号分隔，默认选择全部
+        #[arg(long, value_name = "NAME", value_delimiter = ',')]
+        only: Vec<String>,
+
+        /// 总是执行apt update
+        #[arg(long, conflicts_with = "no_refresh")]
+        refresh: bool,
+
+        /// 不执行apt update，直接使用本地的软件包列表
+        #[arg(long)]
+        no_refresh: bool,
+
+        /// 等待其他进程(如unattended-upgrades)释放dpkg锁的最长秒数
+        #[arg(long, value_name = "SECONDS", default_value_t = 300)]
+        lock_timeout: u64,
+
+        /// installer_builder打包的离线apt软件包目录，会优先从中安装
+        #[arg(long, value_name = "DIR")]
+        apt_bundle: Option<PathBuf>,
+
+        /// 提供配置中输入值的应答文件，按配置名分组，用于非交互运行
+        #[arg(long, value_name = "FILE")]
+        answers: Option<PathBuf>,
+
+        /// 解密配置中secret内容的密钥文件，默认读取$PIONEER_SECRET_KEY或在终端中询问口令
+        #[arg(long, value_name = "FILE")]
+        key_file: Option<PathBuf>,
+
+        /// 忽略配置中不认识的字段，默认报错
+        #[arg(long)]
+        lenient: bool,
     },
 
     /// 卸载删除所有已安装的items
     Uninstall {},
+
+    /// 统计已部署的每个配置的rc脚本在shell启动时的加载耗时
+    ProfileShell {},
+
+    /// 加密文件或标准输入的内容，输出可以写在配置中`{ secret = "..." }`的密文
+    Encrypt {
+        /// 需要加密的文件，默认读取标准输入
+        file: Option<PathBuf>,
+
+        /// 加密用的密钥文件，默认读取$PIONEER_SECRET_KEY或在终端中询问口令
+        #[arg(long, value_name = "FILE")]
+        key_file: Option<PathBuf>,
+    },
+
+    /// 生成随机密钥，保存到文件后用于`--key-file`
+    Keygen {},
+
+    /// 列出配置的部署顺序和部署条件的检查结果，不安装任何内容
+    Plan {
+        /// 用于pioneer的配置文件列表
+        #[arg(required = true, num_args = 1..)]
+        configs: Vec<PathBuf>,
+
+        /// 只选择这些名字的配置(以及它们requires的配置)，可以重复或用逗号分隔，默认选择全部
+        #[arg(long, value_name = "NAME", value_delimiter = ',')]
+        only: Vec<String>,
+
+        /// 忽略配置中不认识的字段，默认报错
+        #[arg(long)]
+        lenient: bool,
+    },
 }
 
 fn main() -> Result<()> {
@@ -25,17 +97,79 @@ fn main() -> Result<()> {
     let args = Args::parse();
 
     match &args {
-        Args::Install { configs } => install(configs)?,
+        Args::Install {
+            configs,
+            only,
+            refresh,
+            no_refresh,
+            lock_timeout,
+            apt_bundle,
+            answers,
+            key_file,
+            lenient,
+        } => {
+            let policy = match (refresh, no_refresh) {
+                (true, _) => RefreshPolicy::Always,
+                (_, true) => RefreshPolicy::Never,
+                _ => RefreshPolicy::Auto,
+            };
+            install(
+                load(configs, only, answers.as_deref(), !lenient)?,
+                policy,
+                Duration::from_secs(*lock_timeout),
+                apt_bundle.as_deref(),
+                key_file.as_deref(),
+            )?
+        }
         Args::Uninstall {} => uninstall()?,
+        Args::ProfileShell {} => profile_shell()?,
+        Args::Encrypt { file, key_file } => encrypt(file.as_deref(), key_file.as_deref())?,
+        Args::Keygen {} => println!("{}", SecretKey::generate()?),
+        Args::Plan {
+            configs,
+            only,
+            lenient,
+        } => plan(configs, only, !lenient)?,
     }
 
     Ok(())
 }
 
-fn install(configs: &[PathBuf]) -> Result<()> {
+/// 读取配置文件并创建部署器
+fn load(
+    configs: &[PathBuf],
+    only: &[String],
+    answers: Option<&Path>,
+    strict: bool,
+) -> Result<Deployer> {
     info!(target: "install", "config files: \n{}", configs.iter().map(|path|path.to_string_lossy()).collect::<Vec<_>>().join("\n"));
 
-    let deployer = Deployer::from_list(configs)?;
+    let answers = match answers {
+        Some(file) => Answers::from_file(file)?,
+        None => Answers::default(),
+    };
+
+    Deployer::from_selected(configs, only, &answers, strict)
+}
+
+fn install(
+    deployer: Deployer,
+    policy: RefreshPolicy,
+    lock_timeout: Duration,
+    apt_bundle: Option<&Path>,
+    key_file: Option<&Path>,
+) -> Result<()> {
+    let mut deployer = deployer
+        .with_refresh(policy)
+        .with_lock_timeout(lock_timeout);
+
+    if let Some(file) = key_file {
+        deployer = deployer.with_key_file(file);
+    }
+
+    if let Some(dir) = apt_bundle {
+        deployer = deployer.with_apt_bundle(dir);
+    }
 
     deployer.deploy()?;
 
@@ -44,7 +178,12 @@ fn install(configs: &[PathBuf]) -> Result<()> {
 }
 
 fn uninstall() -> Result<()> {
-    let deploy_dir = Deployer::deploy_dir();
+    let home = SystemRunner.home_dir()?;
+    let deploy_dir = Deployer::deploy_dir(&SystemRunner)?;
+    Deployer::remove_installed_debs(Rc::new(SystemRunner))
+        .context("Fail to remove installed deb packages")?;
+    Deployer::remove_installed_files(&deploy_dir, &[]).context("Fail to remove installed files")?;
+
     info!(target: "uninstall", "removing {}", deploy_dir.to_string_lossy());
 
     fs::remove_dir_all(&deploy_dir)
@@ -52,9 +191,62 @@ fn uninstall() -> Result<()> {
 
     info!(target: "uninstall", "unseting bashrc");
 
-    Deployer::unset_bashrc().context("Fail to unset .bashrc")?;
+    Deployer::unset_bashrc(&home).context("Fail to unset .bashrc")?;
 
     info!(target: "uninstall", "all done");
 
     Ok(())
 }
+
+fn profile_shell() -> Result<()> {
+    let profile = Deployer::profile_shell(&SystemRunner)?;
+    let total: Duration = profile.iter().map(|(_, elapsed)| *elapsed).sum();
+
+    println!("{:>10}  config", "time(ms)");
+    for (name, elapsed) in &profile {
+        println!("{:>10.3}  {name}", elapsed.as_secs_f64() * 1000.0);
+    }
+    println!("{:>10.3}  total", total.as_secs_f64() * 1000.0);
+
+    Ok(())
+}
+
+fn encrypt(file: Option<&Path>, key_file: Option<&Path>) -> Result<()> {
+    let plain = match file {
+        Some(file) => fs::read(file).context(format!("Fail to read {}", file.to_string_lossy()))?,
+        None => {
+            let mut plain = Vec::new();
+            std::io::stdin().read_to_end(&mut plain)?;
+            plain
+        }
+    };
+
+    let secret_key = SecretKey::default();
+    if let Some(file) = key_file {
+        secret_key.set_key_file(file.to_path_buf());
+    }
+
+    println!("{}", secret_key.encrypt(plain)?);
+    Ok(())
+}
+
+fn plan(configs: &[PathBuf], only: &[String], strict: bool) -> Result<()> {
+    for plan in Deployer::plan(configs, only, strict)? {
+        let action = match &plan.skip {
+            Some(reason) => format!("skip ({reason})"),
+            None => "deploy".to_string(),
+        };
+
+        println!(
+            "{}: {action}  [{}]",
+            plan.config_name,
+            plan.config_file.to_string_lossy()
+        );
+
+        if let Some(when) = &plan.when {
+            println!("    when {when}");
+        }
+    }
+
+    Ok(())
+}
@@ -1,10 +1,16 @@
-use anyhow::{Result, ensure};
+use crate::http::download;
+use crate::op::bundle::AptBundle;
+use crate::runner::CommandRunner;
+use crate::types::config::{AptPackage, Config, Content, PackageName};
+use anyhow::{Context, Result, bail, ensure};
 use std::fs;
 use std::hash::{DefaultHasher, Hash, Hasher};
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::rc::Rc;
 use std::time::{SystemTime, UNIX_EPOCH};
-use tempfile::NamedTempFile;
+use tempfile::{NamedTempFile, TempDir};
+use url::Url;
 
 pub fn build_target(package_name: &str, target: &str, release: bool) -> Result<PathBuf> {
     let mut build_cmd = Command::new(env!("CARGO"));
@@ -64,3 +70,68 @@ pub fn unique_string() -> String {
 
     format!("{:x}", hash)
 }
+
+/// 下载所有配置中会通过apt安装的软件包及其依赖，打包为base64编码的tar.gz
+///
+/// `configs`中每个配置附带它所在的配置文件，包括`apt`、`packages`以及`deb`的依赖
+pub fn apt_bundle(configs: &[(PathBuf, Config)], runner: Rc<dyn CommandRunner>) -> Result<String> {
+    let mut packages: Vec<AptPackage> = Vec::new();
+    let mut debs = Vec::new();
+    let download_dir = TempDir::new()?;
+
+    for (config_file, config) in configs {
+        let install = &config.install;
+        packages.extend(install.apt.iter().flatten().map(AptPackage::from));
+
+        /* 目标机器使用apt时，packages中的软件包也通过apt安装 */
+        packages.extend(install.packages.iter().flatten().map(|package| {
+            let PackageName {
+                name,
+                alternatives,
+                distro,
+                ..
+            } = PackageName::from(package);
+            AptPackage {
+                name,
+                alternatives,
+                distro,
+                ..Default::default()
+            }
+        }));
+
+        let config_path = config_file.parent().unwrap_or(Path::new("."));
+        for (index, deb) in install.deb.iter().flatten().enumerate() {
+            let deb_file = match deb.content() {
+                Content::File(path) => config_path.join(path),
+                Content::Url(url) => {
+                    let deb_file = download_dir.path().join(format!("{index}.deb"));
+                    fs::write(&deb_file, download(&Url::parse(url)?)?)?;
+                    deb_file
+                }
+                content => bail!("deb package must be a file or an url: {content:?}"),
+            };
+
+            let (name, depends) = AptBundle::inspect_deb(runner.as_ref(), &deb_file)?;
+            debs.push(name);
+            packages.extend(depends);
+        }
+    }
+
+    /* .deb文件本身不在软件源中，不需要下载 */
+    packages.retain(|package| !debs.contains(&package.name));
+
+    let bundle_dir = TempDir::new()?;
+    AptBundle::build(&packages, bundle_dir.path(), runner.clone())
+        .context("Fail to build apt bundle")?;
+
+    let tarball = NamedTempFile::new()?;
+    let mut cmd = Command::new("tar");
+    cmd.arg("-czf")
+        .arg(tarball.path())
+        .arg("-C")
+        .arg(bundle_dir.path())
+        .arg(".");
+    runner.run(cmd).context("Fail to pack apt bundle")?;
+
+    base64_encode(tarball.path())
+}
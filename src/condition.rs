@@ -0,0 +1,553 @@
+use crate::op::os::OsRelease;
+use crate::runner::CommandRunner;
+use crate::types::config::{
+    AliasSpec, AptPackage, Condition, Config, DebconfSpec, PackageName, StringOr,
+};
+use anyhow::{Context, Result, ensure};
+use glob::Pattern;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// 当前主机的信息，用于判断部署条件，只读取文件，不运行shell
+pub struct Host {
+    os: Option<OsRelease>,
+    arch: &'static str,
+    kernel: String,
+    hostname: String,
+    container: bool,
+    runner: Rc<dyn CommandRunner>,
+}
+
+impl Host {
+    const CONTAINER_MARKS: [&str; 2] = ["/.dockerenv", "/run/.containerenv"];
+    const CONTAINER_CGROUPS: [&str; 4] = ["docker", "kubepods", "lxc", "containerd"];
+
+    pub fn detect(runner: Rc<dyn CommandRunner>) -> Self {
+        let read = |path: &str| {
+            fs::read_to_string(path)
+                .map(|content| content.trim().to_string())
+                .unwrap_or_default()
+        };
+
+        let container = Self::CONTAINER_MARKS
+            .iter()
+            .any(|mark| Path::new(mark).exists())
+            || std::env::var_os("container").is_some()
+            || Self::CONTAINER_CGROUPS
+                .iter()
+                .any(|name| read("/proc/1/cgroup").contains(name));
+
+        Self {
+            os: runner.os_release().ok(),
+            arch: std::env::consts::ARCH,
+            kernel: read("/proc/sys/kernel/osrelease"),
+            hostname: read("/proc/sys/kernel/hostname"),
+            container,
+            runner,
+        }
+    }
+
+    pub fn os(&self) -> Option<&OsRelease> {
+        self.os.as_ref()
+    }
+
+    pub fn arch(&self) -> &'static str {
+        self.arch
+    }
+
+    /// 统一dpkg和rust对架构的不同写法
+    fn normalize_arch(arch: &str) -> &str {
+        match arch {
+            "amd64" | "x86-64" => "x86_64",
+            "arm64" => "aarch64",
+            "armhf" | "armel" => "arm",
+            "i386" | "i686" => "x86",
+            "ppc64el" => "powerpc64",
+            other => other,
+        }
+    }
+
+    /// 当前用户的主目录，由`CommandRunner`提供
+    pub fn home_dir(&self) -> Result<PathBuf> {
+        self.runner.home_dir()
+    }
+
+    fn expand_home(&self, path: &Path) -> Result<PathBuf> {
+        match path.strip_prefix("~") {
+            Ok(rest) => Ok(self.home_dir()?.join(rest)),
+            Err(_) => Ok(path.to_path_buf()),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Host {
+    /// `runner`所在的x86_64主机，不读取当前系统的信息，用于测试
+    pub(crate) fn fake(runner: Rc<dyn CommandRunner>) -> Self {
+        Self {
+            os: runner.os_release().ok(),
+            arch: "x86_64",
+            kernel: "6.1.0-13-amd64".into(),
+            hostname: "lab-01".into(),
+            container: false,
+            runner,
+        }
+    }
+}
+
+impl Condition {
+    /// 条件在`host`上不满足时返回原因
+    pub fn mismatch(&self, host: &Host) -> Option<String> {
+        let matches = |pattern: &str, value: &str| {
+            Pattern::new(pattern).is_ok_and(|pattern| pattern.matches(value))
+        };
+
+        if let Some(distros) = &self.distro {
+            let ids: Vec<_> = host.os.iter().flat_map(OsRelease::ids).collect();
+            if !distros.iter().any(|distro| ids.contains(&distro.as_str())) {
+                return Some(format!(
+                    "distro is {}, expected one of {}",
+                    ids.first().unwrap_or(&"unknown"),
+                    distros.join(", ")
+                ));
+            }
+        }
+
+        if let Some(version) = &self.version {
+            let versions: Vec<_> = host
+                .os
+                .iter()
+                .flat_map(|os| [&os.version_id, &os.version_codename])
+                .flatten()
+                .collect();
+            if !versions.iter().any(|value| matches(version, value)) {
+                return Some(format!(
+                    "distro version is {}, expected {version}",
+                    versions
+                        .iter()
+                        .map(|value| value.as_str())
+                        .collect::<Vec<_>>()
+                        .join("/")
+                ));
+            }
+        }
+
+        if let Some(arches) = &self.arch
+            && !arches
+                .iter()
+                .any(|arch| Host::normalize_arch(arch) == host.arch)
+        {
+            return Some(format!(
+                "arch is {}, expected one of {}",
+                host.arch,
+                arches.join(", ")
+            ));
+        }
+
+        if let Some(kernel) = &self.kernel
+            && !matches(kernel, &host.kernel)
+        {
+            return Some(format!("kernel is {}, expected {kernel}", host.kernel));
+        }
+
+        if let Some(hostname) = &self.hostname
+            && !matches(hostname, &host.hostname)
+        {
+            return Some(format!(
+                "hostname is {}, expected {hostname}",
+                host.hostname
+            ));
+        }
+
+        for (name, value) in self.env.iter().flatten() {
+            match std::env::var(name) {
+                Ok(actual) if matches(value, &actual) => {}
+                Ok(actual) => return Some(format!("${name} is {actual}, expected {value}")),
+                Err(_) => return Some(format!("${name} is not set")),
+            }
+        }
+
+        for command in self.command.iter().flatten() {
+            if host.runner.which(command).is_err() {
+                return Some(format!("command {command} is not found in PATH"));
+            }
+        }
+
+        for file in self.file.iter().flatten() {
+            match host.expand_home(file) {
+                Ok(path) if path.exists() => {}
+                Ok(_) => return Some(format!("{} does not exist", file.to_string_lossy())),
+                Err(err) => return Some(format!("{}: {err:#}", file.to_string_lossy())),
+            }
+        }
+
+        if let Some(container) = self.container
+            && container != host.container
+        {
+            return Some(match host.container {
+                true => "running in a container".to_string(),
+                false => "not running in a container".to_string(),
+            });
+        }
+
+        for condition in self.all.iter().flatten() {
+            if let Some(reason) = condition.mismatch(host) {
+                return Some(reason);
+            }
+        }
+
+        /* 任一条件满足时collect得到None */
+        if let Some(reasons) = self.any.as_ref().and_then(|conditions| {
+            conditions
+                .iter()
+                .map(|condition| condition.mismatch(host))
+                .collect::<Option<Vec<_>>>()
+        }) {
+            return Some(format!(
+                "none of the conditions match: {}",
+                reasons.join("; ")
+            ));
+        }
+
+        if let Some(condition) = &self.not
+            && condition.mismatch(host).is_none()
+        {
+            return Some(format!("not ({}) is false", condition.describe()));
+        }
+
+        None
+    }
+
+    /// 条件的可读描述，用于`pioneer plan`
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+
+        if let Some(distros) = &self.distro {
+            parts.push(format!("distro in [{}]", distros.join(", ")));
+        }
+        if let Some(version) = &self.version {
+            parts.push(format!("version {version}"));
+        }
+        if let Some(arches) = &self.arch {
+            parts.push(format!("arch in [{}]", arches.join(", ")));
+        }
+        if let Some(kernel) = &self.kernel {
+            parts.push(format!("kernel {kernel}"));
+        }
+        if let Some(hostname) = &self.hostname {
+            parts.push(format!("hostname {hostname}"));
+        }
+        for (name, value) in self.env.iter().flatten() {
+            parts.push(format!("${name} {value}"));
+        }
+        for command in self.command.iter().flatten() {
+            parts.push(format!("command {command}"));
+        }
+        for file in self.file.iter().flatten() {
+            parts.push(format!("file {}", file.to_string_lossy()));
+        }
+        if let Some(container) = self.container {
+            parts.push(format!("container {container}"));
+        }
+        if let Some(conditions) = &self.all {
+            let all: Vec<_> = conditions.iter().map(Condition::describe).collect();
+            parts.push(format!("all ({})", all.join("; ")));
+        }
+        if let Some(conditions) = &self.any {
+            let any: Vec<_> = conditions.iter().map(Condition::describe).collect();
+            parts.push(format!("any ({})", any.join("; ")));
+        }
+        if let Some(condition) = &self.not {
+            parts.push(format!("not ({})", condition.describe()));
+        }
+
+        parts.join(" and ")
+    }
+
+    /// 静态检查条件是否合法，不读取主机信息
+    pub fn validate(&self) -> Result<()> {
+        ensure!(*self != Condition::default(), "Condition is empty");
+
+        let check_pattern = |field: &str, pattern: &str| {
+            Pattern::new(pattern).context(format!("{field}: invalid pattern {pattern}"))
+        };
+        let check_list = |field: &str, list: &Option<Vec<String>>| {
+            ensure!(
+                list.as_ref().is_none_or(|list| !list.is_empty()),
+                "{field}: list is empty"
+            );
+            Ok(())
+        };
+
+        check_list("distro", &self.distro)?;
+        check_list("arch", &self.arch)?;
+        check_list("command", &self.command)?;
+
+        for (field, pattern) in [
+            ("version", &self.version),
+            ("kernel", &self.kernel),
+            ("hostname", &self.hostname),
+        ] {
+            if let Some(pattern) = pattern {
+                check_pattern(field, pattern)?;
+            }
+        }
+
+        for (name, value) in self.env.iter().flatten() {
+            check_pattern(&format!("env.{name}"), value)?;
+        }
+
+        for (field, conditions) in [("all", &self.all), ("any", &self.any)] {
+            if let Some(conditions) = conditions {
+                ensure!(!conditions.is_empty(), "{field}: list is empty");
+                for condition in conditions {
+                    condition.validate().context(field.to_string())?;
+                }
+            }
+        }
+
+        if let Some(condition) = &self.not {
+            condition.validate().context("not")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Config {
+    /// 配置中所有的部署条件，附带条件所在的位置，如`install.alias.ll`
+    pub fn conditions(&self) -> Vec<(String, &Condition)> {
+        let install = &self.install;
+        let mut conditions = Vec::new();
+
+        if let Some(when) = &self.infomation.when {
+            conditions.push(("infomation.when".to_string(), when));
+        }
+
+        for package in install.packages.iter().flatten() {
+            if let StringOr::Object(PackageName {
+                name,
+                when: Some(when),
+                ..
+            }) = package
+            {
+                conditions.push((format!("install.packages.{name}"), when));
+            }
+        }
+
+        for package in install.apt.iter().flatten() {
+            if let StringOr::Object(AptPackage {
+                name,
+                when: Some(when),
+                ..
+            }) = package
+            {
+                conditions.push((format!("install.apt.{name}"), when));
+            }
+        }
+
+        for (name, repo) in install.apt_repo.iter().flatten() {
+            if let Some(when) = &repo.when {
+                conditions.push((format!("install.apt_repo.{name}"), when));
+            }
+        }
+
+        for (question, answer) in install.debconf.iter().flatten() {
+            if let StringOr::Object(DebconfSpec {
+                when: Some(when), ..
+            }) = answer
+            {
+                conditions.push((format!("install.debconf.{question}"), when));
+            }
+        }
+
+        for (index, deb) in install.deb.iter().flatten().enumerate() {
+            if let Some(when) = deb.when() {
+                conditions.push((format!("install.deb[{index}]"), when));
+            }
+        }
+
+        for (backend, list) in [
+            ("dnf", &install.dnf),
+            ("pacman", &install.pacman),
+            ("apk", &install.apk),
+            ("zypper", &install.zypper),
+        ] {
+            for package in list.iter().flatten() {
+                if let StringOr::Object(PackageName {
+                    name,
+                    when: Some(when),
+                    ..
+                }) = package
+                {
+                    conditions.push((format!("install.{backend}.{name}"), when));
+                }
+            }
+        }
+
+        for (name, alias) in install.alias.iter().flatten() {
+            if let StringOr::Object(AliasSpec {
+                when: Some(when), ..
+            }) = alias
+            {
+                conditions.push((format!("install.alias.{name}"), when));
+            }
+        }
+
+        for (name, command) in install.command.iter().flatten() {
+            if let Some(when) = command.when() {
+                conditions.push((format!("install.command.{name}"), when));
+            }
+        }
+
+        for (name, env) in install.env.iter().flatten() {
+            if let Some(when) = env.when() {
+                conditions.push((format!("install.env.{name}"), when));
+            }
+        }
+
+        for (index, rc) in install.envrc.iter().flatten().enumerate() {
+            if let Some(when) = rc.when() {
+                conditions.push((format!("install.envrc[{index}]"), when));
+            }
+        }
+
+        for (path, file) in install.files.iter().flatten() {
+            if let Some(when) = file.when() {
+                conditions.push((format!("install.files.{}", path.to_string_lossy()), when));
+            }
+        }
+
+        conditions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::fake::FakeApt;
+    use tempfile::TempDir;
+
+    fn host(home: &Path) -> Host {
+        Host::fake(Rc::new(FakeApt::new().with_home(home)))
+    }
+
+    fn condition(toml: &str) -> Condition {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn arch_is_normalized() {
+        let home = TempDir::new().unwrap();
+        let host = host(home.path());
+
+        assert_eq!(condition(r#"arch = ["amd64"]"#).mismatch(&host), None);
+        assert_eq!(condition(r#"arch = ["x86_64"]"#).mismatch(&host), None);
+        assert_eq!(
+            condition(r#"arch = ["arm64", "armhf"]"#).mismatch(&host),
+            Some("arch is x86_64, expected one of arm64, armhf".into())
+        );
+    }
+
+    #[test]
+    fn versions_and_kernels_match_globs() {
+        let home = TempDir::new().unwrap();
+        let host = host(home.path());
+
+        for matched in [
+            r#"version = "1*""#,
+            r#"version = "bookworm""#,
+            r#"kernel = "6.1.*""#,
+            r#"hostname = "lab-*""#,
+            r#"distro = ["ubuntu", "debian"]"#,
+        ] {
+            assert_eq!(condition(matched).mismatch(&host), None, "{matched}");
+        }
+
+        assert_eq!(
+            condition(r#"version = "11""#).mismatch(&host),
+            Some("distro version is 12/bookworm, expected 11".into())
+        );
+        assert_eq!(
+            condition(r#"kernel = "*WSL2*""#).mismatch(&host),
+            Some("kernel is 6.1.0-13-amd64, expected *WSL2*".into())
+        );
+    }
+
+    #[test]
+    fn nested_conditions() {
+        let home = TempDir::new().unwrap();
+        let host = host(home.path());
+
+        let matched = condition(
+            r#"
+any = [{ arch = ["arm64"] }, { all = [{ distro = ["debian"] }, { version = "12" }] }]
+not = { container = true }
+"#,
+        );
+        assert_eq!(matched.mismatch(&host), None);
+
+        let none = condition(r#"any = [{ arch = ["arm64"] }, { distro = ["fedora"] }]"#);
+        assert_eq!(
+            none.mismatch(&host),
+            Some("none of the conditions match: arch is x86_64, expected one of arm64; distro is debian, expected one of fedora".into())
+        );
+
+        let negated = condition(r#"not = { all = [{ distro = ["debian"] }] }"#);
+        assert_eq!(
+            negated.mismatch(&host),
+            Some("not (all (distro in [debian])) is false".into())
+        );
+    }
+
+    #[test]
+    fn files_are_relative_to_the_runner_home() {
+        let home = TempDir::new().unwrap();
+        fs::write(home.path().join("marker"), "").unwrap();
+        let host = host(home.path());
+
+        assert_eq!(condition(r#"file = ["~/marker"]"#).mismatch(&host), None);
+        assert_eq!(
+            condition(r#"file = ["~/missing"]"#).mismatch(&host),
+            Some("~/missing does not exist".into())
+        );
+    }
+
+    #[test]
+    fn describe_conditions() {
+        let when = condition(
+            r#"
+distro = ["debian"]
+version = "12"
+container = false
+any = [{ arch = ["amd64"] }, { kernel = "*WSL2*" }]
+"#,
+        );
+
+        assert_eq!(
+            when.describe(),
+            "distro in [debian] and version 12 and container false and any (arch in [amd64]; kernel *WSL2*)"
+        );
+    }
+
+    #[test]
+    fn validate_conditions() {
+        assert!(condition(r#"distro = ["debian"]"#).validate().is_ok());
+
+        for (invalid, message) in [
+            ("", "Condition is empty"),
+            ("any = [{}]", "any: Condition is empty"),
+            ("not = {}", "not: Condition is empty"),
+            ("distro = []", "distro: list is empty"),
+            ("all = []", "all: list is empty"),
+            (r#"kernel = "[""#, "kernel: invalid pattern ["),
+        ] {
+            let err = condition(invalid).validate().unwrap_err();
+            assert!(
+                format!("{err:#}").starts_with(message),
+                "{invalid}: {err:#}"
+            );
+        }
+    }
+}
@@ -23,9 +23,7 @@ impl Alias {
 }
 
 impl InstallItem for Alias {
-    fn check(&self) -> anyhow::Result<()> {
-        info!(target: "Alias", "Checking alias {}", self.name);
-
+    fn validate(&self) -> anyhow::Result<()> {
         let re = Regex::new(r"^[a-zA-Z_.][a-zA-Z0-9_.-]*$").unwrap();
         anyhow::ensure!(
             !self.name.is_empty() && !self.name.contains('=') && re.is_match(&self.name),
@@ -34,6 +32,11 @@ impl InstallItem for Alias {
         Ok(())
     }
 
+    fn check(&self) -> anyhow::Result<()> {
+        info!(target: "Alias", "Checking alias {}", self.name);
+        self.validate()
+    }
+
     fn install(&self) -> anyhow::Result<Installed> {
         info!(target: "Alias", "Installing {}...", self.name);
         Ok(Installed::Rc {
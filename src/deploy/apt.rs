@@ -1,45 +1,149 @@
 use crate::{
     deploy::{InstallItem, Installed},
-    op::apt::AptOp,
+    op::{
+        apt::{AptOp, AptStatus},
+        package::Packages,
+    },
+    types::config::AptPackage,
 };
+use anyhow::{Context, anyhow, ensure};
+use derive_more::Debug;
+use glob::Pattern;
 use log::*;
+use regex::Regex;
+use std::rc::Rc;
 
 #[derive(Debug)]
 pub struct Apt {
-    sw_name: String,
+    package: AptPackage,
+
+    #[debug(ignore)]
+    packages: Rc<Packages>,
 }
 
 impl Apt {
-    pub fn from_sw<T>(sw: T) -> Self
+    pub fn from_package<T>(package: T, packages: Rc<Packages>) -> Self
     where
-        T: Into<String>,
+        T: Into<AptPackage>,
     {
-        Self { sw_name: sw.into() }
+        Self {
+            package: package.into(),
+            packages,
+        }
+    }
+
+    /// 按`distro`和`alternatives`找出当前系统中可用的包名
+    fn resolve(&self, apt: &AptOp) -> anyhow::Result<AptPackage> {
+        let candidates = self.packages.candidates(
+            &self.package.name,
+            self.package.alternatives.as_ref(),
+            self.package.distro.as_ref(),
+        );
+
+        /* 只有一个名字时交给check_package报告具体的错误 */
+        if let [name] = &candidates[..] {
+            return Ok(AptPackage {
+                name: name.clone(),
+                ..self.package.clone()
+            });
+        }
+
+        for name in &candidates {
+            if !matches!(apt.status(name)?, AptStatus::NotFound) {
+                return Ok(AptPackage {
+                    name: name.clone(),
+                    ..self.package.clone()
+                });
+            }
+        }
+
+        Err(anyhow!(
+            "Con not find any of {} on {}",
+            candidates.join(", "),
+            self.packages.distro()
+        ))
     }
 }
 
 impl InstallItem for Apt {
+    fn validate(&self) -> anyhow::Result<()> {
+        /* debian的包名规则，可以带上`:arch`后缀 */
+        let re = Regex::new(r"^[a-z0-9][a-z0-9+.-]+(:[a-z0-9-]+)?$").unwrap();
+        let package = &self.package;
+
+        for name in std::iter::once(&package.name)
+            .chain(package.alternatives.iter().flatten())
+            .chain(package.distro.iter().flat_map(|distro| distro.values()))
+        {
+            ensure!(
+                re.is_match(name),
+                "{name:?} is not a valid apt package name"
+            );
+        }
+
+        if let Some(version) = &package.version {
+            ensure!(
+                !version.is_empty() && !version.contains(char::is_whitespace),
+                "{:?} is not a valid version of {}",
+                version,
+                package.name
+            );
+            Pattern::new(version).context(format!("Invalid version: {version}"))?;
+        }
+
+        if let Some(release) = &package.target_release {
+            ensure!(
+                !release.trim().is_empty(),
+                "target_release of {} is empty",
+                package.name
+            );
+        }
+
+        Ok(())
+    }
+
     fn check(&self) -> anyhow::Result<()> {
-        info!(target: "APT", "Checking package {}...", self.sw_name);
-        AptOp::try_get()?
-            .lock()
-            .unwrap()
-            .as_ref()
-            .unwrap()
-            .check(&self.sw_name)
+        info!(target: "APT", "Checking package {}...", self.package.name);
+        self.validate()?;
+        /* apt本身初始化失败时直接报错，只有软件包找不到时才考虑待添加的软件源 */
+        let resolved = self.packages.with_apt(|apt| {
+            Ok(self.resolve(apt).and_then(|package| {
+                let version = apt.check_package(&package)?;
+                Ok((package, version))
+            }))
+        })?;
+
+        let (package, version) = match resolved {
+            Ok(resolved) => resolved,
+            Err(err) => match self.packages.pending_apt_source(&self.package.name) {
+                Some(repo) => {
+                    warn!(target: "APT", "{err:#}, it will be checked again after adding apt repository {repo}");
+                    return Ok(());
+                }
+                None => return Err(err),
+            },
+        };
+
+        if package.name != self.package.name {
+            info!(target: "APT", "{} resolved to {} on {}", self.package.name, package.name, self.packages.distro());
+        }
+
+        if let Some(version) = version {
+            info!(target: "APT", "{} resolved to version {version}", package.name);
+        }
+
+        Ok(())
     }
 
     fn install(&self) -> anyhow::Result<Installed> {
-        info!(target: "APT", "Installing {}...", self.sw_name);
-        AptOp::try_get()?
-            .lock()
-            .unwrap()
-            .as_ref()
-            .unwrap()
-            .install(&self.sw_name)?;
-
-        Ok(Installed::Apt {
-            name: self.sw_name.clone(),
-        })
+        info!(target: "APT", "Queueing {}...", self.package.name);
+        let name = self.packages.with_apt(|apt| {
+            let package = self.resolve(apt)?;
+            let version = apt.check_package(&package)?;
+            apt.queue(&package, version)?;
+            Ok(package.name)
+        })?;
+
+        Ok(Installed::Apt { name })
     }
 }
@@ -0,0 +1,194 @@
+use crate::{
+    deploy::{InstallItem, Installed},
+    http::download,
+    op::{file::FileOp, package::Packages},
+    types::config::{self, Content},
+};
+use anyhow::{Context, bail, ensure};
+use derive_more::Debug;
+use log::info;
+use regex::Regex;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    rc::Rc,
+};
+use tempfile::NamedTempFile;
+use url::Url;
+
+#[derive(Debug)]
+pub struct AptRepo {
+    name: String,
+    repo: config::AptRepo,
+    config_path: PathBuf,
+
+    #[debug(ignore)]
+    packages: Rc<Packages>,
+}
+
+impl AptRepo {
+    const SOURCES_DIR: &str = "/etc/apt/sources.list.d";
+    const KEYRINGS_DIR: &str = "/etc/apt/keyrings";
+
+    pub fn from_repo<N, P>(
+        name: N,
+        repo: &config::AptRepo,
+        config_path: P,
+        packages: Rc<Packages>,
+    ) -> Self
+    where
+        N: Into<String>,
+        P: AsRef<Path>,
+    {
+        Self {
+            name: name.into(),
+            repo: repo.clone(),
+            config_path: config_path.as_ref().to_path_buf(),
+            packages,
+        }
+    }
+
+    fn sources_file(&self) -> PathBuf {
+        Path::new(Self::SOURCES_DIR).join(format!("{}.sources", self.name))
+    }
+
+    fn keyring_file(&self) -> PathBuf {
+        Path::new(Self::KEYRINGS_DIR).join(format!("{}.gpg", self.name))
+    }
+
+    fn sources(&self) -> String {
+        let mut sources = vec![
+            format!(
+                "# This file is auto-generated by {}. Do not modify it to avoid invalidation.",
+                env!("CARGO_PKG_NAME")
+            ),
+            "Types: deb".to_string(),
+            format!("URIs: {}", self.repo.uris.join(" ")),
+            format!("Suites: {}", self.repo.suites.join(" ")),
+        ];
+
+        if let Some(components) = &self.repo.components {
+            sources.push(format!("Components: {}", components.join(" ")));
+        }
+
+        if let Some(architectures) = &self.repo.architectures {
+            sources.push(format!("Architectures: {}", architectures.join(" ")));
+        }
+
+        sources.push(format!(
+            "Signed-By: {}",
+            self.keyring_file().to_string_lossy()
+        ));
+
+        sources.join("\n") + "\n"
+    }
+
+    /// 读取签名公钥，ascii armor格式的公钥通过`gpg --dearmor`转换为二进制格式
+    fn keyring(&self) -> anyhow::Result<Vec<u8>> {
+        let key = match &self.repo.key {
+            Content::Raw(content) => content.as_bytes().to_vec(),
+            Content::File(path) => {
+                let path = self.config_path.join(path);
+                fs::read(&path).context(format!("Fail to read file {}", path.to_string_lossy()))?
+            }
+            Content::Url(url) => download(&Url::parse(url)?)?.to_vec(),
+            Content::Secret(_) => bail!("secret content is only supported in env and files"),
+        };
+
+        if !key.starts_with(b"-----BEGIN PGP") {
+            return Ok(key);
+        }
+
+        let armored = NamedTempFile::new()?;
+        fs::write(armored.path(), &key)?;
+        let dearmored = NamedTempFile::new()?;
+
+        let mut cmd = Command::new("gpg");
+        cmd.arg("--batch")
+            .arg("--yes")
+            .arg("--dearmor")
+            .arg("-o")
+            .arg(dearmored.path())
+            .arg(armored.path());
+
+        self.packages
+            .runner()
+            .run(cmd)
+            .context("Fail to dearmor signing key")?;
+
+        Ok(fs::read(dearmored.path())?)
+    }
+}
+
+impl InstallItem for AptRepo {
+    fn validate(&self) -> anyhow::Result<()> {
+        let re = Regex::new(r"^[a-zA-Z0-9_][a-zA-Z0-9_.-]*$").unwrap();
+        ensure!(
+            re.is_match(&self.name),
+            "Repository name only allows letters, numbers, underscores(_), dot(.), and hyphens(-)."
+        );
+
+        ensure!(!self.repo.uris.is_empty(), "uris of {} is empty", self.name);
+        for uri in &self.repo.uris {
+            Url::parse(uri).context(format!("{uri} is not a valid url"))?;
+        }
+
+        ensure!(
+            !self.repo.suites.is_empty(),
+            "suites of {} is empty",
+            self.name
+        );
+
+        match &self.repo.key {
+            Content::Raw(_) => {}
+            Content::File(path) => {
+                let path = self.config_path.join(path);
+                ensure!(
+                    FileOp::is_file(&path),
+                    "{} is not a file",
+                    path.to_string_lossy()
+                );
+            }
+            Content::Url(url) => {
+                Url::parse(url).context(format!("{url} is not a valid url"))?;
+            }
+            Content::Secret(_) => bail!("secret content is only supported in env and files"),
+        }
+
+        Ok(())
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        info!(target: "AptRepo", "Checking apt repository {}...", self.name);
+        self.validate()
+    }
+
+    fn install(&self) -> anyhow::Result<Installed> {
+        info!(target: "AptRepo", "Adding apt repository {}...", self.name);
+
+        let keyring = NamedTempFile::new()?;
+        fs::write(keyring.path(), self.keyring()?)?;
+        FileOp::install_as_root(
+            self.packages.runner(),
+            keyring.path(),
+            self.keyring_file(),
+            0o644,
+        )?;
+
+        let sources = NamedTempFile::new()?;
+        fs::write(sources.path(), self.sources())?;
+        FileOp::install_as_root(
+            self.packages.runner(),
+            sources.path(),
+            self.sources_file(),
+            0o644,
+        )?;
+
+        self.packages.apt_source_added(&self.name);
+
+        Ok(Installed::File {
+            file: self.sources_file(),
+        })
+    }
+}
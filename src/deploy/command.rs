@@ -1,6 +1,6 @@
 use std::path::{Path, PathBuf};
 
-use anyhow::{Context, Ok, ensure};
+use anyhow::{Context, Ok, bail, ensure};
 use log::info;
 use regex::Regex;
 
@@ -41,9 +41,7 @@ impl Command {
 }
 
 impl InstallItem for Command {
-    fn check(&self) -> anyhow::Result<()> {
-        info!(target: "Command", "Checking command {}", self.name);
-
+    fn validate(&self) -> anyhow::Result<()> {
         let re = Regex::new(r"^[a-zA-Z_][a-zA-Z0-9_-]*$").unwrap();
         anyhow::ensure!(
             !self.name.is_empty() && !self.name.contains('=') && re.is_match(&self.name),
@@ -65,9 +63,21 @@ impl InstallItem for Command {
                     path.to_string_lossy()
                 );
             }
-            StringOr::Object(Content::Url(_)) => todo!(),
+            StringOr::Object(Content::Url(_)) => {
+                bail!("url content of commands is not supported yet")
+            }
+            StringOr::Object(Content::Secret(_)) => {
+                bail!("secret content is only supported in env and files")
+            }
         }
 
+        Ok(())
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        info!(target: "Command", "Checking command {}", self.name);
+        self.validate()?;
+
         ensure!(
             FileOp::is_file(&self.install_file) || !FileOp::is_dir(&self.install_file),
             "{} is a directory",
@@ -102,7 +112,12 @@ impl InstallItem for Command {
                     self.install_file.to_string_lossy()
                 ))?;
             }
-            StringOr::Object(Content::Url(_)) => todo!(),
+            StringOr::Object(Content::Url(_)) => {
+                bail!("url content of commands is not supported yet")
+            }
+            StringOr::Object(Content::Secret(_)) => {
+                bail!("secret content is only supported in env and files")
+            }
         }
 
         Ok(Installed::Path {
@@ -0,0 +1,199 @@
+use crate::{
+    deploy::{InstallItem, Installed},
+    http::download,
+    op::file::FileOp,
+    types::config::{self, CompletionWords, Content, StringOr},
+};
+use anyhow::{Context, bail, ensure};
+use log::info;
+use regex::Regex;
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+use url::Url;
+
+#[derive(Debug)]
+pub struct Completion {
+    name: String,
+    completion: config::Completion,
+    config_path: PathBuf,
+    install_file: PathBuf,
+}
+
+impl Completion {
+    pub fn from_completion<N, P1, P2>(
+        name: N,
+        completion: &config::Completion,
+        config_path: P1,
+        completion_dir: P2,
+    ) -> Self
+    where
+        N: Into<String>,
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        let name = name.into();
+        Self {
+            install_file: completion_dir.as_ref().join(&name),
+            name,
+            completion: completion.clone(),
+            config_path: config_path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// bash-completion按需加载补全脚本的用户目录，与bash-completion的查找顺序一致，默认在`home`下
+    pub fn completion_dir<P: AsRef<Path>>(home: P) -> PathBuf {
+        let data_dir = || {
+            env::var_os("XDG_DATA_HOME")
+                .map(PathBuf::from)
+                .filter(|dir| dir.is_absolute())
+                .unwrap_or_else(|| home.as_ref().join(".local/share"))
+        };
+        let user_dir = env::var_os("BASH_COMPLETION_USER_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| data_dir().join("bash-completion"));
+
+        user_dir.join("completions")
+    }
+
+    fn words_script(&self, words: &CompletionWords) -> String {
+        let subcommands = words.subcommands.clone().unwrap_or_default().join(" ");
+        let flags = words.flags.clone().unwrap_or_default().join(" ");
+        let func = format!("_pioneer_{}", self.name.replace('-', "_"));
+
+        format!(
+            r#"# This file is auto-generated by {}. Do not modify it to avoid invalidation.
+{func}() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}"
+    local words="{flags}"
+    if [ "$COMP_CWORD" -eq 1 ]; then
+        words="{subcommands} $words"
+    fi
+    COMPREPLY=($(compgen -W "$words" -- "$cur"))
+}}
+complete -o default -F {func} {}
+"#,
+            env!("CARGO_PKG_NAME"),
+            self.name
+        )
+    }
+}
+
+impl InstallItem for Completion {
+    fn validate(&self) -> anyhow::Result<()> {
+        match &self.completion {
+            config::Completion::Script(StringOr::String(_))
+            | config::Completion::Script(StringOr::Object(Content::Raw(_))) => {}
+            config::Completion::Script(StringOr::Object(Content::File(path))) => {
+                let path = self.config_path.join(path);
+                ensure!(
+                    FileOp::is_file(&path),
+                    "{} is not a file",
+                    path.to_string_lossy()
+                );
+            }
+            config::Completion::Script(StringOr::Object(Content::Url(url))) => {
+                Url::parse(url).context(format!("{url} is not a valid url"))?;
+            }
+            config::Completion::Script(StringOr::Object(Content::Secret(_))) => {
+                bail!("secret content is only supported in env and files")
+            }
+            config::Completion::Words(words) => {
+                let re = Regex::new(r"^[^\s'`$]+$").unwrap();
+                for word in words.subcommands.iter().chain(words.flags.iter()).flatten() {
+                    ensure!(
+                        re.is_match(word),
+                        "Completion word {word:?} must not be empty or contain whitespace, quotes or $"
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        info!(target: "Completion", "Checking completion of {}", self.name);
+        self.validate()?;
+
+        ensure!(
+            !FileOp::is_dir(&self.install_file),
+            "{} is a directory",
+            self.install_file.to_string_lossy()
+        );
+
+        Ok(())
+    }
+
+    fn install(&self) -> anyhow::Result<Installed> {
+        info!(target: "Completion", "Installing completion of {}...", self.name);
+
+        let context = format!(
+            "Fail to install completion {}",
+            self.install_file.to_string_lossy()
+        );
+
+        match &self.completion {
+            config::Completion::Script(StringOr::String(content))
+            | config::Completion::Script(StringOr::Object(Content::Raw(content))) => {
+                FileOp::write(&self.install_file, content, None).context(context)?;
+            }
+            config::Completion::Script(StringOr::Object(Content::File(path))) => {
+                FileOp::copy(self.config_path.join(path), &self.install_file).context(context)?;
+            }
+            config::Completion::Script(StringOr::Object(Content::Url(url))) => {
+                let content = download(&Url::parse(url)?)?;
+                FileOp::write(&self.install_file, content, None).context(context)?;
+            }
+            config::Completion::Script(StringOr::Object(Content::Secret(_))) => {
+                bail!("secret content is only supported in env and files")
+            }
+            config::Completion::Words(words) => {
+                FileOp::write(&self.install_file, self.words_script(words), None)
+                    .context(context)?;
+            }
+        }
+
+        Ok(Installed::Completion {
+            file: self.install_file.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn words_script_completes_subcommands_first() {
+        let words = CompletionWords {
+            subcommands: Some(vec!["start".into(), "stop".into()]),
+            flags: Some(vec!["--help".into(), "-v".into()]),
+        };
+        let completion = Completion::from_completion(
+            "my-tool",
+            &config::Completion::Words(words.clone()),
+            "/config",
+            "/completions",
+        );
+
+        assert_eq!(
+            completion.words_script(&words),
+            format!(
+                r#"# This file is auto-generated by {}. Do not modify it to avoid invalidation.
+_pioneer_my_tool() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}"
+    local words="--help -v"
+    if [ "$COMP_CWORD" -eq 1 ]; then
+        words="start stop $words"
+    fi
+    COMPREPLY=($(compgen -W "$words" -- "$cur"))
+}}
+complete -o default -F _pioneer_my_tool my-tool
+"#,
+                env!("CARGO_PKG_NAME")
+            )
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use crate::{
+    deploy::{InstallItem, Installed},
+    http::download,
+    op::{file::FileOp, package::Packages},
+    types::config::Content,
+};
+use anyhow::{Context, anyhow, ensure};
+use derive_more::Debug;
+use log::info;
+use ring::digest;
+use std::{
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+use url::Url;
+
+#[derive(Debug)]
+pub struct Deb {
+    content: Content,
+    config_path: PathBuf,
+    download_dir: PathBuf,
+
+    #[debug(ignore)]
+    packages: Rc<Packages>,
+}
+
+impl Deb {
+    pub fn from_content<P1, P2>(
+        content: &Content,
+        config_path: P1,
+        download_dir: P2,
+        packages: Rc<Packages>,
+    ) -> Self
+    where
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        Self {
+            content: content.clone(),
+            config_path: config_path.as_ref().to_path_buf(),
+            download_dir: download_dir.as_ref().to_path_buf(),
+            packages,
+        }
+    }
+
+    /// .deb文件的本地路径，url会被下载到部署目录下，文件名包含url的哈希，url改变时重新下载
+    fn deb_file(&self) -> anyhow::Result<PathBuf> {
+        match &self.content {
+            Content::Raw(_) | Content::Secret(_) => {
+                Err(anyhow!("deb package must be a file or an url"))
+            }
+            Content::File(path) => {
+                let path = self.config_path.join(path);
+                ensure!(
+                    FileOp::is_file(&path),
+                    "{} is not a file",
+                    path.to_string_lossy()
+                );
+                Ok(path.canonicalize()?)
+            }
+            Content::Url(url) => {
+                let url = Url::parse(url).context(format!("{url} is not a valid url"))?;
+                let file_name = url
+                    .path_segments()
+                    .and_then(|mut segments| segments.next_back())
+                    .filter(|name| name.ends_with(".deb"))
+                    .unwrap_or("package.deb");
+                let hash: String = digest::digest(&digest::SHA256, url.as_str().as_bytes())
+                    .as_ref()[..8]
+                    .iter()
+                    .map(|byte| format!("{byte:02x}"))
+                    .collect();
+                let deb_file = self.download_dir.join(format!("{hash}-{file_name}"));
+
+                if !FileOp::is_file(&deb_file) {
+                    info!(target: "Deb", "Downloading {url}...");
+                    FileOp::write(&deb_file, download(&url)?, None)?;
+                }
+
+                Ok(deb_file)
+            }
+        }
+    }
+}
+
+impl InstallItem for Deb {
+    fn validate(&self) -> anyhow::Result<()> {
+        match &self.content {
+            Content::Raw(_) | Content::Secret(_) => {
+                Err(anyhow!("deb package must be a file or an url"))
+            }
+            Content::File(path) => {
+                let path = self.config_path.join(path);
+                ensure!(
+                    FileOp::is_file(&path),
+                    "{} is not a file",
+                    path.to_string_lossy()
+                );
+                Ok(())
+            }
+            Content::Url(url) => {
+                let parsed = Url::parse(url).context(format!("{url} is not a valid url"))?;
+                ensure!(
+                    matches!(parsed.scheme(), "http" | "https"),
+                    "{url} is not a http or https url"
+                );
+                Ok(())
+            }
+        }
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        info!(target: "Deb", "Checking deb package {:?}...", self.content);
+        self.validate()?;
+
+        /* 检查阶段不下载，url指向的.deb文件在安装时下载后再检查 */
+        if let Content::Url(url) = &self.content {
+            info!(target: "Deb", "{url} will be downloaded on install");
+            return Ok(());
+        }
+
+        let deb_file = self.deb_file()?;
+        let deb = self.packages.with_apt(|apt| apt.inspect_deb(&deb_file))?;
+
+        info!(target: "Deb", "{} is {} {} ({})", deb_file.to_string_lossy(), deb.name, deb.version, deb.arch);
+        Ok(())
+    }
+
+    fn install(&self) -> anyhow::Result<Installed> {
+        info!(target: "Deb", "Queueing deb package {:?}...", self.content);
+        let deb_file = self.deb_file()?;
+
+        let (deb, existed) = self.packages.with_apt(|apt| {
+            let deb = apt.inspect_deb(&deb_file)?;
+            let existed = apt.is_installed(&deb.name);
+            apt.queue_deb(&deb_file, &deb);
+            Ok((deb, existed))
+        })?;
+
+        /* 部署之前就已安装的软件包不属于pioneer，卸载时不能移除 */
+        if existed {
+            info!(target: "Deb", "{} was installed before, it will be kept on uninstall", deb.name);
+            return Ok(Installed::Apt { name: deb.name });
+        }
+
+        Ok(Installed::Deb { name: deb.name })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::op::package::Backend;
+    use crate::runner::fake::FakeApt;
+    use tempfile::TempDir;
+
+    fn deb(content: Content, dir: &Path, fake: Rc<FakeApt>) -> Deb {
+        Deb::from_content(
+            &content,
+            dir,
+            dir.join("deb"),
+            Rc::new(Packages::new(Some(Backend::Apt), fake)),
+        )
+    }
+
+    #[test]
+    fn installed_deb_is_kept_on_uninstall() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(
+            dir.path().join("hello.deb"),
+            "Package: hello\nVersion: 1.0\nArchitecture: amd64\n",
+        )
+        .unwrap();
+        let fake = Rc::new(FakeApt::new().with_installed("hello", "1.0"));
+        let deb = deb(Content::File("hello.deb".into()), dir.path(), fake);
+
+        deb.check().unwrap();
+        let installed = deb.install().unwrap();
+
+        assert!(
+            matches!(&installed, Installed::Apt { name } if name == "hello"),
+            "{installed:?}"
+        );
+    }
+
+    #[test]
+    fn url_is_not_downloaded_on_check() {
+        let dir = TempDir::new().unwrap();
+        let fake = Rc::new(FakeApt::new());
+        let deb = deb(
+            Content::Url("https://example.invalid/hello_1.0_amd64.deb".into()),
+            dir.path(),
+            fake.clone(),
+        );
+
+        deb.check().unwrap();
+
+        assert!(!dir.path().join("deb").exists());
+        assert!(fake.commands().is_empty());
+    }
+}
@@ -0,0 +1,59 @@
+use crate::{
+    deploy::{InstallItem, Installed},
+    op::{apt::DebconfSelection, package::Packages},
+};
+use derive_more::Debug;
+use log::info;
+use std::rc::Rc;
+
+#[derive(Debug)]
+pub struct Debconf {
+    question: String,
+
+    /* 答案可能是password类型 */
+    #[debug(ignore)]
+    answer: String,
+
+    #[debug(ignore)]
+    packages: Rc<Packages>,
+}
+
+impl Debconf {
+    pub fn from_pair<Q, A>(question: Q, answer: A, packages: Rc<Packages>) -> Self
+    where
+        Q: Into<String>,
+        A: Into<String>,
+    {
+        Self {
+            question: question.into(),
+            answer: answer.into(),
+            packages,
+        }
+    }
+}
+
+impl InstallItem for Debconf {
+    fn validate(&self) -> anyhow::Result<()> {
+        DebconfSelection::from_pair(&self.question, &self.answer)?;
+        Ok(())
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        info!(target: "Debconf", "Checking debconf selection {}...", self.question);
+        self.validate()?;
+
+        self.packages.runner().which("debconf-set-selections")?;
+        Ok(())
+    }
+
+    fn install(&self) -> anyhow::Result<Installed> {
+        info!(target: "Debconf", "Preseeding {}...", self.question);
+        let selection = DebconfSelection::from_pair(&self.question, &self.answer)?;
+
+        self.packages.with_apt(|apt| apt.preseed(&selection))?;
+
+        Ok(Installed::Debconf {
+            question: self.question.clone(),
+        })
+    }
+}
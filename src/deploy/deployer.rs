@@ -1,26 +1,42 @@
 use super::command;
+use crate::condition::Host;
 use crate::deploy::InstallItem;
 use crate::deploy::Installed;
 use crate::deploy::alias::Alias;
 use crate::deploy::apt::Apt;
+use crate::deploy::apt_repo::AptRepo;
+use crate::deploy::completion::Completion;
+use crate::deploy::deb::Deb;
+use crate::deploy::debconf::Debconf;
 use crate::deploy::env::Env;
 use crate::deploy::envrc::Envrc;
 use crate::deploy::file::File;
+use crate::deploy::package::Package;
+use crate::deploy::state::State;
+use crate::input::Answers;
+use crate::loader::ConfigLoader;
+pub use crate::op::apt::RefreshPolicy;
 use crate::op::file::FileOp;
-use crate::types::config::Config;
+use crate::op::package::{Backend, Packages};
+use crate::runner::{CommandRunner, SystemRunner};
+use crate::secret::SecretKey;
+use crate::template::Template;
+use crate::types::config::{AliasSpec, AptPackage, Condition, Config, DebconfSpec, PackageName};
 use anyhow::Ok;
 use anyhow::anyhow;
+use anyhow::bail;
 use anyhow::ensure;
 use anyhow::{Context, Result};
 use derive_more::Debug;
-use dirs::home_dir;
 use log::debug;
 use log::info;
 use regex::Regex;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::rc::Rc;
+use std::time::Duration;
 
 const DEPLOY_DIR: &str = ".distro";
 
@@ -28,12 +44,34 @@ const DEPLOY_DIR: &str = ".distro";
 #[allow(unused)]
 pub struct Deployer {
     config_list: Vec<ConfigInfo>,
+    /// 用户主目录，由`CommandRunner`提供
+    home: PathBuf,
     install_dir: PathBuf,
 
+    /// 每个配置的输入值，部署完成后保存到状态中
+    inputs: HashMap<String, HashMap<String, String>>,
+
+    #[debug(ignore)]
+    packages: Rc<Packages>,
+
+    #[debug(ignore)]
+    secret_key: Rc<SecretKey>,
+
     #[debug(ignore)]
     installers: Vec<InstallInfo>,
 }
 
+/// `Deployer::plan`中一个配置的部署计划
+#[derive(Debug)]
+pub struct Plan {
+    pub config_name: String,
+    pub config_file: PathBuf,
+    /// 结构化部署条件的描述
+    pub when: Option<String>,
+    /// 不部署的原因，为None时会部署
+    pub skip: Option<String>,
+}
+
 #[derive(Debug)]
 struct ConfigInfo {
     file: PathBuf,
@@ -43,59 +81,93 @@ struct ConfigInfo {
 #[derive(Debug)]
 struct InstallInfo {
     config_file: PathBuf,
+    config_name: String,
     install_items: Box<dyn InstallItem>,
 }
 
 impl Deployer {
     const BEGIN_TAG: &str = "# Config Start";
     const END_TAG: &str = "# Config End";
+    const ALLRC: &str = "allrc";
 
     pub fn from_list<P: AsRef<Path>>(list_paths: &[P]) -> Result<Self> {
-        let mut config_list = Vec::new();
+        Self::from_selected(list_paths, &[], &Answers::default(), true)
+    }
 
-        list_paths.iter().try_for_each(|path| {
-            let content = fs::read_to_string(path)
-                .context(format!("Fail to read {}", path.as_ref().to_string_lossy()))?;
+    /// 只部署配置文件中名为`only`的配置，以及它们`requires`的配置，`answers`提供配置中输入的值
+    ///
+    /// `strict`时配置中有不认识的字段会报错
+    pub fn from_selected<P: AsRef<Path>>(
+        list_paths: &[P],
+        only: &[String],
+        answers: &Answers,
+        strict: bool,
+    ) -> Result<Self> {
+        Self::from_selected_with(list_paths, only, answers, strict, Rc::new(SystemRunner))
+    }
 
-            let config: Config = toml::from_str(&content).context(format!(
-                "File {} is invaild",
-                path.as_ref().to_string_lossy()
-            ))?;
+    /// 使用指定的命令执行方式创建部署器，测试时可以传入`FakeApt`等假的实现
+    pub fn from_list_with<P: AsRef<Path>>(
+        list_paths: &[P],
+        runner: Rc<dyn CommandRunner>,
+    ) -> Result<Self> {
+        Self::from_selected_with(list_paths, &[], &Answers::default(), true, runner)
+    }
 
-            config_list.push(ConfigInfo {
-                file: path.as_ref().to_path_buf(),
-                config,
-            });
-            Ok(())
-        })?;
+    pub fn from_selected_with<P: AsRef<Path>>(
+        list_paths: &[P],
+        only: &[String],
+        answers: &Answers,
+        strict: bool,
+        runner: Rc<dyn CommandRunner>,
+    ) -> Result<Self> {
+        let config_list = Self::load_configs(list_paths, only, strict)?;
 
-        let install_path = Self::deploy_dir();
+        let home = runner.home_dir()?;
+        let install_path = Self::deploy_dir(runner.as_ref())?;
+        let completion_dir = Completion::completion_dir(&home);
+        let saved = State::load(&install_path)
+            .context("Fail to load deploy state")?
+            .inputs;
+        let mut inputs = HashMap::new();
+        let packages = Rc::new(Packages::detect(runner.clone()));
+        let host = Host::detect(runner.clone());
+        let secret_key = Rc::new(SecretKey::default());
 
         let mut installers = Vec::new();
 
+        /* 不满足部署条件的配置，依赖它们的配置也不会部署 */
+        let mut skipped = HashSet::new();
+
         for config_info in &config_list {
             let config = &config_info.config;
             let config_path = config_info.file.parent().unwrap_or(Path::new("."));
 
-            if let Some(command) = &config.infomation.install_while {
-                info!(target: "Deployer", "Checking the precondition for {}: {command}", config.infomation.name);
-
-                let check = Command::new("bash").arg("-c").arg(command).status();
-
-                if !check.is_ok_and(|code| code.success()) {
-                    info!(target: "Deployer", "Checking fail: {command}, next...");
-                    continue;
-                }
+            if let Some(reason) = Self::skip_reason(config, &skipped, runner.as_ref(), &host) {
+                info!(target: "Deployer", "Skip {}: {reason}, next...", config.infomation.name);
+                skipped.insert(config.infomation.name.clone());
+                continue;
             }
 
+            let values = answers.resolve(config, saved.get(&config.infomation.name))?;
+            let config =
+                &Template::from_config(config, &config_info.file, &install_path, &host, &values)?
+                    .render_config(config)?;
+            inputs.insert(config.infomation.name.clone(), values);
+
             let new_installers: Vec<_> = Self::dispath_config(
                 config,
                 config_path,
                 install_path.join(&config.infomation.name),
+                &completion_dir,
+                &packages,
+                &secret_key,
+                &host,
             )
             .into_iter()
             .map(|installer| InstallInfo {
                 config_file: config_info.file.clone(),
+                config_name: config.infomation.name.clone(),
                 install_items: installer,
             })
             .collect();
@@ -107,11 +179,176 @@ impl Deployer {
 
         Ok(Self {
             config_list,
+            home,
             install_dir: install_path.to_path_buf(),
+            inputs,
+            packages,
+            secret_key,
             installers,
         })
     }
 
+    /// 读取配置文件，选出`only`中的配置，并按`requires`排序
+    fn load_configs<P: AsRef<Path>>(
+        list_paths: &[P],
+        only: &[String],
+        strict: bool,
+    ) -> Result<Vec<ConfigInfo>> {
+        let mut config_list = Vec::new();
+
+        list_paths.iter().try_for_each(|path| {
+            for config in ConfigLoader::load_list(path, strict)? {
+                config_list.push(ConfigInfo {
+                    file: path.as_ref().to_path_buf(),
+                    config,
+                });
+            }
+            Ok(())
+        })?;
+
+        let config_list = ConfigLoader::select(config_list, only, |info| &info.config)?;
+
+        Self::sort_configs(config_list)
+    }
+
+    /// 配置不需要部署时返回原因，依次检查依赖、`when`和`install_while`
+    fn skip_reason(
+        config: &Config,
+        skipped: &HashSet<String>,
+        runner: &dyn CommandRunner,
+        host: &Host,
+    ) -> Option<String> {
+        let info = &config.infomation;
+
+        if let Some(dependency) = info
+            .requires
+            .iter()
+            .flatten()
+            .find(|name| skipped.contains(*name))
+        {
+            return Some(format!("requires {dependency} which is skipped"));
+        }
+
+        if let Some(reason) = info.when.as_ref().and_then(|when| when.mismatch(host)) {
+            return Some(format!("when: {reason}"));
+        }
+
+        if let Some(command) = &info.install_while {
+            info!(target: "Deployer", "Checking the precondition for {}: {command}", info.name);
+
+            let mut check = Command::new("bash");
+            check.arg("-c").arg(command);
+
+            if !runner.success(check) {
+                return Some(format!("install_while fails: {command}"));
+            }
+        }
+
+        None
+    }
+
+    /// 按顺序列出每个配置是否会部署，以及不部署的原因，不做任何修改
+    pub fn plan<P: AsRef<Path>>(
+        list_paths: &[P],
+        only: &[String],
+        strict: bool,
+    ) -> Result<Vec<Plan>> {
+        let runner: Rc<dyn CommandRunner> = Rc::new(SystemRunner);
+        let host = Host::detect(runner.clone());
+        let mut skipped = HashSet::new();
+        let mut plans = Vec::new();
+
+        for config_info in Self::load_configs(list_paths, only, strict)? {
+            let config = &config_info.config;
+            let skip = Self::skip_reason(config, &skipped, runner.as_ref(), &host);
+
+            if skip.is_some() {
+                skipped.insert(config.infomation.name.clone());
+            }
+
+            plans.push(Plan {
+                config_name: config.infomation.name.clone(),
+                config_file: config_info.file.clone(),
+                when: config.infomation.when.as_ref().map(Condition::describe),
+                skip,
+            });
+        }
+
+        Ok(plans)
+    }
+
+    /// 按`requires`对配置排序，依赖的配置在前，没有依赖关系的配置保持命令行中的顺序
+    fn sort_configs(config_list: Vec<ConfigInfo>) -> Result<Vec<ConfigInfo>> {
+        let names: Vec<&str> = config_list
+            .iter()
+            .map(|info| info.config.infomation.name.as_str())
+            .collect();
+
+        let mut requires = Vec::new();
+        for info in &config_list {
+            let mut indexes = Vec::new();
+
+            for name in info.config.infomation.requires.iter().flatten() {
+                let index = names.iter().position(|exist| exist == name).ok_or(anyhow!(
+                    "{} in {} requires {name}, but it is not in the config list",
+                    info.config.infomation.name,
+                    info.file.to_string_lossy()
+                ))?;
+                indexes.push(index);
+            }
+
+            requires.push(indexes);
+        }
+
+        let mut order: Vec<usize> = Vec::new();
+        while order.len() < config_list.len() {
+            let next = (0..config_list.len()).find(|index| {
+                !order.contains(index) && requires[*index].iter().all(|dep| order.contains(dep))
+            });
+
+            match next {
+                Some(index) => order.push(index),
+                None => {
+                    let remaining: Vec<_> = (0..config_list.len())
+                        .filter(|index| !order.contains(index))
+                        .map(|index| names[index])
+                        .collect();
+                    bail!("Configs require each other: {}", remaining.join(", "));
+                }
+            }
+        }
+
+        let mut config_list: Vec<_> = config_list.into_iter().map(Some).collect();
+        Ok(order
+            .into_iter()
+            .filter_map(|index| config_list[index].take())
+            .collect())
+    }
+
+    /// 设置apt update的策略，默认只在软件包列表过期时更新
+    pub fn with_refresh(self, policy: RefreshPolicy) -> Self {
+        self.packages.set_apt_refresh(policy);
+        self
+    }
+
+    /// 设置等待dpkg锁的最长时间，默认5分钟
+    pub fn with_lock_timeout(self, timeout: Duration) -> Self {
+        self.packages.set_apt_lock_timeout(timeout);
+        self
+    }
+
+    /// 解密配置中secret内容的密钥文件，默认从环境变量读取或在终端中询问
+    pub fn with_key_file<P: AsRef<Path>>(self, file: P) -> Self {
+        self.secret_key.set_key_file(file.as_ref().to_path_buf());
+        self
+    }
+
+    /// 使用installer_builder打包的离线apt软件包目录
+    pub fn with_apt_bundle<P: AsRef<Path>>(self, dir: P) -> Self {
+        self.packages.set_apt_bundle(dir.as_ref().to_path_buf());
+        self
+    }
+
     pub fn deploy(&self) -> Result<()> {
         self.check_all()?;
         self.deploy_all()?;
@@ -119,18 +356,19 @@ impl Deployer {
         Ok(())
     }
 
-    pub fn deploy_dir() -> PathBuf {
-        home_dir().unwrap().join(DEPLOY_DIR)
+    /// 部署目录，在`runner`提供的主目录中
+    pub fn deploy_dir(runner: &dyn CommandRunner) -> Result<PathBuf> {
+        Ok(runner.home_dir()?.join(DEPLOY_DIR))
     }
 
-    pub fn unset_bashrc() -> Result<()> {
-        let bashrc_path = home_dir()
-            .ok_or(anyhow!("Fail to get home dir"))?
-            .join(".bashrc");
+    /// 从`home`中的.bashrc移除部署时添加的内容
+    pub fn unset_bashrc<P: AsRef<Path>>(home: P) -> Result<()> {
+        let bashrc_path = home.as_ref().join(".bashrc");
 
         let bashrc = fs::read_to_string(&bashrc_path).context("Fail to read .bashrc")?;
 
-        let re = Regex::new(format!(r"(?s){}\n.*?{}\n", Self::BEGIN_TAG, Self::END_TAG).as_str())?;
+        let re =
+            Regex::new(format!(r"(?s)\n?{}\n.*?{}\n", Self::BEGIN_TAG, Self::END_TAG).as_str())?;
 
         let replaced = re.replace(&bashrc, "").to_string();
 
@@ -139,11 +377,106 @@ impl Deployer {
         Ok(())
     }
 
+    /// 删除上一次部署到`deploy_dir`时在部署目录之外安装的文件，`keep`中的文件会被保留
+    pub fn remove_installed_files<P: AsRef<Path>>(deploy_dir: P, keep: &[PathBuf]) -> Result<()> {
+        let state = State::load(deploy_dir).context("Fail to load deploy state")?;
+
+        for file in state.files.iter().filter(|file| !keep.contains(file)) {
+            if FileOp::is_file(file) {
+                info!(target: "Deployer", "removing {}", file.to_string_lossy());
+                fs::remove_file(file)
+                    .context(format!("Fail to remove {}", file.to_string_lossy()))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 统计已部署的allrc中每个配置的rc脚本的source耗时，按加载顺序返回
+    pub fn profile_shell(runner: &dyn CommandRunner) -> Result<Vec<(String, Duration)>> {
+        let allrc_file = Self::deploy_dir(runner)?.join(Self::ALLRC);
+        let allrc = fs::read_to_string(&allrc_file).context(format!(
+            "Fail to read {}, is it deployed?",
+            allrc_file.to_string_lossy()
+        ))?;
+
+        let re = Regex::new(r"^source '(.+)'$")?;
+
+        /* 每个source前后记录时间，结果写到fd 3，rc脚本自身的输出全部丢弃 */
+        let mut script = String::from("exec 3>&1 >/dev/null 2>&1\n");
+        for line in allrc.lines() {
+            match re.captures(line) {
+                Some(caps) => {
+                    let name = Path::new(&caps[1])
+                        .parent()
+                        .and_then(|dir| dir.file_name())
+                        .map(|name| name.to_string_lossy().to_string())
+                        .unwrap_or_default();
+                    script.push_str(&format!(
+                        "__start=$EPOCHREALTIME; {line}; printf '%s %s %s\\n' '{name}' \"$__start\" \"$EPOCHREALTIME\" >&3\n"
+                    ));
+                }
+                None => script.push_str(&format!("{line}\n")),
+            }
+        }
+
+        /* $EPOCHREALTIME的小数点随locale变化，如de_DE中是逗号 */
+        let mut cmd = Command::new("bash");
+        cmd.arg("--norc")
+            .arg("-i")
+            .arg("-c")
+            .arg(script)
+            .env("LC_ALL", "C");
+        let output = runner.run(cmd).context("Fail to run bash")?;
+
+        Self::parse_profile(&output)
+    }
+
+    /// 解析profile脚本输出的`名称 开始时间 结束时间`行，其余行忽略
+    fn parse_profile(output: &str) -> Result<Vec<(String, Duration)>> {
+        let mut profile = Vec::new();
+        for line in output.lines() {
+            let fields: Vec<_> = line.split_whitespace().collect();
+            let [name, start, end] = fields[..] else {
+                continue;
+            };
+            let (start, end): (f64, f64) = (
+                start.parse().context("bash 5 or later is required")?,
+                end.parse().context("bash 5 or later is required")?,
+            );
+            profile.push((
+                name.to_string(),
+                Duration::from_secs_f64((end - start).max(0.0)),
+            ));
+        }
+
+        Ok(profile)
+    }
+
+    /// 移除之前通过.deb文件安装的软件包
+    pub fn remove_installed_debs(runner: Rc<dyn CommandRunner>) -> Result<()> {
+        let state =
+            State::load(Self::deploy_dir(runner.as_ref())?).context("Fail to load deploy state")?;
+
+        if state.debs.is_empty() {
+            return Ok(());
+        }
+
+        Packages::detect(runner).with(Backend::Apt, |apt| {
+            for name in &state.debs {
+                info!(target: "Deployer", "removing package {name}");
+                apt.remove(name)?;
+            }
+            Ok(())
+        })
+    }
+
     fn check_all(&self) -> Result<()> {
         self.installers.iter().try_for_each(
             |InstallInfo {
                  config_file,
                  install_items,
+                 ..
              }| {
                 debug!(target: "Deployer", "Checking for {}", config_file.to_string_lossy());
 
@@ -169,6 +502,7 @@ impl Deployer {
 
         for InstallInfo {
             config_file,
+            config_name,
             install_items,
         } in &self.installers
         {
@@ -182,21 +516,50 @@ impl Deployer {
 
             debug!("new installed item: {installed:?}");
 
-            installed_list.push(installed);
+            installed_list.push((config_name, installed));
         }
 
-        let rc_content: String = installed_list
-            .iter()
-            .filter_map(|installed| match installed {
-                super::Installed::Rc { command } => Some(command.clone()),
-                _ => None,
-            })
-            .collect::<Vec<_>>()
-            .join("\n");
+        /* apt软件包在安装阶段只是入队，这里在一个事务中统一安装 */
+        self.packages
+            .commit_apt()
+            .context("Fail to install apt packages")?;
+
+        /* 每个配置的rc脚本单独成文件，便于profile-shell统计各配置的加载耗时 */
+        let mut rc_configs: Vec<&String> = Vec::new();
+        for (config_name, _) in &installed_list {
+            if !rc_configs.contains(config_name) {
+                rc_configs.push(config_name);
+            }
+        }
+
+        let mut source_rc = Vec::new();
+        for config_name in rc_configs {
+            let rc_list: Vec<_> = installed_list
+                .iter()
+                .filter(|(name, _)| *name == config_name)
+                .filter_map(|(_, installed)| match installed {
+                    Installed::Rc { command } => Some(command.clone()),
+                    _ => None,
+                })
+                .collect();
+
+            if rc_list.is_empty() {
+                continue;
+            }
+
+            let rc_file = self.install_dir.join(config_name).join("rc");
+            FileOp::write(&rc_file, rc_list.join("\n") + "\n", None).context(format!(
+                "Fail to create file: {}",
+                rc_file.to_string_lossy()
+            ))?;
+
+            source_rc.push(format!("source '{}'", rc_file.to_string_lossy()));
+        }
+        let rc_content = source_rc.join("\n");
 
         let path_content = installed_list
             .iter()
-            .filter_map(|installed| match installed {
+            .filter_map(|(_, installed)| match installed {
                 Installed::Path { path } => Some(path.clone()),
                 _ => None,
             })
@@ -218,26 +581,57 @@ impl Deployer {
             uninstall_rc
         );
 
-        let allrc_file = self.install_dir.join("allrc");
+        let allrc_file = self.install_dir.join(Self::ALLRC);
 
         FileOp::write(&allrc_file, allrc, None).context(format!(
             "Fail to create file: {}",
             allrc_file.to_string_lossy()
         ))?;
 
-        Self::unset_bashrc().context("Fail to unset .bashrc")?;
-        Self::setup_bash(allrc_file).context("Fail to setup allrc")?;
+        let completions: Vec<_> = installed_list
+            .iter()
+            .filter_map(|(_, installed)| match installed {
+                Installed::Completion { file } => Some(file.clone()),
+                _ => None,
+            })
+            .collect();
+
+        Self::remove_installed_files(&self.install_dir, &completions)?;
+
+        /* 之前通过.deb安装的软件包仍然保留在状态中，卸载时一并移除 */
+        let state = State::load(&self.install_dir).context("Fail to load deploy state")?;
+        let mut debs = state.debs;
+        for (_, installed) in &installed_list {
+            if let Installed::Deb { name } = installed
+                && !debs.contains(name)
+            {
+                debs.push(name.clone());
+            }
+        }
+
+        /* 没有参与这次部署的配置的输入值也保留 */
+        let mut inputs = state.inputs;
+        inputs.extend(self.inputs.clone());
+
+        State {
+            files: completions,
+            debs,
+            inputs,
+        }
+        .save(&self.install_dir)
+        .context("Fail to save deploy state")?;
+
+        Self::unset_bashrc(&self.home).context("Fail to unset .bashrc")?;
+        self.setup_bash(allrc_file).context("Fail to setup allrc")?;
 
         Ok(())
     }
 
-    fn setup_bash(rc_file: PathBuf) -> Result<()> {
+    fn setup_bash(&self, rc_file: PathBuf) -> Result<()> {
         let source_rc = format!("test -f {0} && source {0}", rc_file.to_string_lossy());
         let new_block = format!("\n{}\n{}\n{}\n", Self::BEGIN_TAG, source_rc, Self::END_TAG);
 
-        let bashrc_path = home_dir()
-            .ok_or(anyhow!("Fail to get home dir"))?
-            .join(".bashrc");
+        let bashrc_path = self.home.join(".bashrc");
 
         let mut bashrc = fs::read_to_string(&bashrc_path).context("Fail to read .bashrc")?;
 
@@ -252,6 +646,10 @@ impl Deployer {
         config: &Config,
         config_path: P1,
         install_path: P2,
+        completion_dir: &Path,
+        packages: &Rc<Packages>,
+        secret_key: &Rc<SecretKey>,
+        host: &Host,
     ) -> Vec<Box<dyn InstallItem>>
     where
         P1: AsRef<Path>,
@@ -267,48 +665,227 @@ impl Deployer {
             config.install.files.as_ref(),
         );
 
+        /* 不满足条件的单项被跳过 */
+        let skip = |kind: &str, name: &str, when: Option<&Condition>| {
+            let reason = when.and_then(|when| when.mismatch(host));
+            if let Some(reason) = &reason {
+                info!(target: "Deployer", "{}: skip {kind} {name}, {reason}", config.infomation.name);
+            }
+            reason.is_some()
+        };
+
+        if let Some(repos) = &config.install.apt_repo {
+            if packages.host() == Some(Backend::Apt) {
+                /* 同一配置中的apt软件包可能来自这些软件源 */
+                let covered: Vec<String> = apt
+                    .into_iter()
+                    .flatten()
+                    .map(|package| AptPackage::from(package).name)
+                    .chain(
+                        config
+                            .install
+                            .packages
+                            .iter()
+                            .flatten()
+                            .map(|package| PackageName::from(package).name),
+                    )
+                    .collect();
+
+                for (name, repo) in repos {
+                    if skip("apt repository", name, repo.when.as_ref()) {
+                        continue;
+                    }
+
+                    packages.expect_apt_source(name, covered.clone());
+                    installers.push(Box::new(AptRepo::from_repo(
+                        name,
+                        repo,
+                        config_path.as_ref(),
+                        packages.clone(),
+                    )));
+                }
+            } else {
+                info!(target: "Deployer", "{}: skip apt repositories, host package manager is {:?}", config.infomation.name, packages.host());
+            }
+        }
+
+        /* 软件源在软件包之前添加，安装阶段解析软件包时才能找到来自软件源的软件包 */
+        if let Some(softwares) = &config.install.packages {
+            for sw in softwares {
+                let package = PackageName::from(sw);
+                if skip("package", &package.name, package.when.as_ref()) {
+                    continue;
+                }
+
+                installers.push(Box::new(Package::from_name(
+                    sw,
+                    packages.host(),
+                    packages.clone(),
+                )));
+            }
+        }
+
+        if let Some(selections) = &config.install.debconf {
+            if packages.host() == Some(Backend::Apt) {
+                for (question, answer) in selections {
+                    let answer = DebconfSpec::from(answer);
+                    if skip("debconf selection", question, answer.when.as_ref()) {
+                        continue;
+                    }
+
+                    installers.push(Box::new(Debconf::from_pair(
+                        question,
+                        answer.answer,
+                        packages.clone(),
+                    )));
+                }
+            } else {
+                info!(target: "Deployer", "{}: skip debconf selections, host package manager is {:?}", config.infomation.name, packages.host());
+            }
+        }
+
+        if let Some(debs) = &config.install.deb {
+            if packages.host() == Some(Backend::Apt) {
+                for (index, deb) in debs.iter().enumerate() {
+                    if skip("deb package", &format!("#{index}"), deb.when()) {
+                        continue;
+                    }
+
+                    installers.push(Box::new(Deb::from_content(
+                        deb.content(),
+                        config_path.as_ref(),
+                        install_path.as_ref().join("deb"),
+                        packages.clone(),
+                    )));
+                }
+            } else {
+                info!(target: "Deployer", "{}: skip deb packages, host package manager is {:?}", config.infomation.name, packages.host());
+            }
+        }
+
         if let Some(softwares) = apt {
+            if packages.host() == Some(Backend::Apt) {
+                for sw in softwares {
+                    let package = AptPackage::from(sw);
+                    if skip("apt package", &package.name, package.when.as_ref()) {
+                        continue;
+                    }
+
+                    installers.push(Box::new(Apt::from_package(package, packages.clone())));
+                }
+            } else {
+                info!(target: "Deployer", "{}: skip apt packages, host package manager is {:?}", config.infomation.name, packages.host());
+            }
+        }
+
+        for (backend, softwares) in [
+            (Backend::Dnf, &config.install.dnf),
+            (Backend::Pacman, &config.install.pacman),
+            (Backend::Apk, &config.install.apk),
+            (Backend::Zypper, &config.install.zypper),
+        ] {
+            let Some(softwares) = softwares else {
+                continue;
+            };
+
+            if packages.host() != Some(backend) {
+                info!(target: "Deployer", "{}: skip {backend} packages, host package manager is {:?}", config.infomation.name, packages.host());
+                continue;
+            }
+
             for sw in softwares {
-                installers.push(Box::new(Apt::from_sw(sw.clone())));
+                let package = PackageName::from(sw);
+                if skip("package", &package.name, package.when.as_ref()) {
+                    continue;
+                }
+
+                installers.push(Box::new(Package::from_name(
+                    package,
+                    Some(backend),
+                    packages.clone(),
+                )));
             }
         }
 
         if let Some(alias_list) = alias {
-            for alias in alias_list {
-                installers.push(Box::new(Alias::from_pair(alias.0, alias.1)))
+            for (name, alias) in alias_list {
+                let alias = AliasSpec::from(alias);
+                if skip("alias", name, alias.when.as_ref()) {
+                    continue;
+                }
+
+                installers.push(Box::new(Alias::from_pair(name, alias.command)))
             }
         }
 
         if let Some(commands) = command {
             for command in commands {
+                if skip("command", command.0, command.1.when()) {
+                    continue;
+                }
+
                 installers.push(Box::new(command::Command::from_content(
                     command.0,
-                    command.1,
+                    command.1.content(),
                     config_path.as_ref(),
                     install_path.as_ref().join("bin"),
                 )));
+
+                if let Some(completion) = command.1.completion() {
+                    installers.push(Box::new(Completion::from_completion(
+                        command.0,
+                        completion,
+                        config_path.as_ref(),
+                        completion_dir,
+                    )));
+                }
             }
         }
 
         if let Some(env_lsit) = env {
             for env in env_lsit {
-                installers.push(Box::new(Env::from_kv(env.0, env.1)));
+                if skip("env", env.0, env.1.when()) {
+                    continue;
+                }
+
+                installers.push(Box::new(Env::from_kv(
+                    env.0,
+                    env.1.value(),
+                    install_path.as_ref(),
+                    secret_key.clone(),
+                )));
             }
         }
 
         if let Some(rc_list) = envrc {
-            for rc in rc_list {
-                installers.push(Box::new(Envrc::from_content(rc, config_path.as_ref())));
+            for (index, rc) in rc_list.iter().enumerate() {
+                if skip("envrc", &format!("#{index}"), rc.when()) {
+                    continue;
+                }
+
+                installers.push(Box::new(Envrc::from_entry(
+                    rc,
+                    config_path.as_ref(),
+                    install_path
+                        .as_ref()
+                        .join("lazy")
+                        .join(format!("{index}.sh")),
+                )));
             }
         }
 
         if let Some(files) = files {
             for file in files {
+                if skip("file", &file.0.to_string_lossy(), file.1.when()) {
+                    continue;
+                }
+
                 installers.push(Box::new(File::from_content(
                     file.0,
-                    file.1,
+                    file.1.content(),
                     config_path.as_ref(),
                     install_path.as_ref(),
+                    secret_key.clone(),
                 )));
             }
         }
@@ -316,3 +893,437 @@ impl Deployer {
         installers
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::fake::FakeApt;
+    use tempfile::TempDir;
+
+    const CONFIG: &str = r#"
+[infomation]
+name = "demo"
+
+[install]
+apt = ["tree", { name = "fd-find", alternatives = ["fd"] }]
+packages = ["ripgrep"]
+
+[install.env]
+EDITOR = "vim"
+
+[install.alias]
+ll = "ls -l"
+
+[install.files]
+"demo.conf" = "hello"
+"#;
+
+    const REPO_CONFIG: &str = r#"
+[infomation]
+name = "repo"
+
+[install]
+apt = ["from-repo"]
+
+[install.apt_repo.first]
+uris = ["https://example.com/first"]
+suites = ["stable"]
+key = { raw = "key" }
+
+[install.apt_repo.second]
+uris = ["https://example.com/second"]
+suites = ["stable"]
+key = { raw = "key" }
+"#;
+
+    /// 临时的主目录，其中有.bashrc和配置文件`demo.toml`
+    fn home_with(config: &str) -> (TempDir, PathBuf) {
+        let home = TempDir::new().unwrap();
+        fs::write(home.path().join(".bashrc"), "# bashrc\n").unwrap();
+        let config_file = home.path().join("demo.toml");
+        fs::write(&config_file, config).unwrap();
+        (home, config_file)
+    }
+
+    #[test]
+    fn deploy_with_fake_apt() {
+        let (home, config) = home_with(CONFIG);
+
+        let fake = Rc::new(
+            FakeApt::new()
+                .with_package("tree", "2.1.0")
+                .with_package("fd-find", "8.7.0")
+                .with_package("ripgrep", "13.0.0")
+                .with_home(home.path()),
+        );
+
+        Deployer::from_list_with(&[&config], fake.clone())
+            .unwrap()
+            .deploy()
+            .unwrap();
+
+        for name in ["tree", "fd-find", "ripgrep"] {
+            assert!(fake.is_installed(name), "{name} is not installed");
+        }
+        /* 所有apt软件包在同一个事务中安装 */
+        let installs: Vec<_> = fake
+            .commands()
+            .into_iter()
+            .filter(|cmd| cmd.starts_with("apt install"))
+            .collect();
+        assert_eq!(installs.len(), 1, "{installs:?}");
+
+        let deploy_dir = home.path().join(DEPLOY_DIR);
+        let rc = fs::read_to_string(deploy_dir.join("demo").join("rc")).unwrap();
+        assert!(rc.contains("alias ll=$'ls -l'"), "{rc}");
+        assert!(rc.contains("EDITOR"), "{rc}");
+        assert_eq!(
+            fs::read_to_string(deploy_dir.join("demo").join("demo.conf")).unwrap(),
+            "hello"
+        );
+
+        let bashrc = fs::read_to_string(home.path().join(".bashrc")).unwrap();
+        assert!(bashrc.starts_with("# bashrc\n"));
+        assert!(
+            bashrc.contains(
+                &deploy_dir
+                    .join(Deployer::ALLRC)
+                    .to_string_lossy()
+                    .to_string()
+            )
+        );
+
+        /* 再次部署时替换.bashrc中之前添加的内容 */
+        Deployer::from_list_with(&[&config], fake.clone())
+            .unwrap()
+            .deploy()
+            .unwrap();
+        let redeployed = fs::read_to_string(home.path().join(".bashrc")).unwrap();
+        assert_eq!(redeployed, bashrc);
+    }
+
+    #[test]
+    fn packages_from_added_repositories() {
+        let (home, config) = home_with(REPO_CONFIG);
+        let fake = Rc::new(
+            FakeApt::new()
+                .with_source_package("second", "from-repo", "1.0")
+                .with_home(home.path()),
+        );
+
+        Deployer::from_list_with(&[&config], fake.clone())
+            .unwrap()
+            .deploy()
+            .unwrap();
+
+        assert!(fake.is_installed("from-repo"));
+        /* 初始化时更新一次，添加两个软件源后只再更新一次 */
+        let updates = fake
+            .commands()
+            .into_iter()
+            .filter(|cmd| cmd == "apt update")
+            .count();
+        assert_eq!(updates, 2);
+    }
+
+    #[test]
+    fn generic_packages_from_added_repositories() {
+        let (home, config) = home_with(
+            r#"
+[infomation]
+name = "repo"
+
+[install]
+packages = ["from-repo"]
+
+[install.apt_repo.second]
+uris = ["https://example.com/second"]
+suites = ["stable"]
+key = { raw = "key" }
+"#,
+        );
+        let fake = Rc::new(
+            FakeApt::new()
+                .with_source_package("second", "from-repo", "1.0")
+                .with_home(home.path()),
+        );
+
+        Deployer::from_list_with(&[&config], fake.clone())
+            .unwrap()
+            .deploy()
+            .unwrap();
+
+        assert!(fake.is_installed("from-repo"));
+    }
+
+    #[test]
+    fn unrelated_package_is_not_excused_by_repositories() {
+        let (home, config) = home_with(REPO_CONFIG);
+        let other = home.path().join("other.toml");
+        fs::write(
+            &other,
+            "[infomation]\nname = \"other\"\n\n[install]\napt = [\"tpyo\"]\n",
+        )
+        .unwrap();
+        let fake = Rc::new(
+            FakeApt::new()
+                .with_source_package("second", "from-repo", "1.0")
+                .with_home(home.path()),
+        );
+
+        let err = Deployer::from_list_with(&[&config, &other], fake.clone())
+            .unwrap()
+            .deploy()
+            .unwrap_err();
+
+        assert!(format!("{err:#}").contains("tpyo"), "{err:#}");
+        assert!(!fake.is_installed("from-repo"));
+    }
+
+    #[test]
+    fn held_packages_are_not_held_again() {
+        let (home, config) = home_with(
+            "[infomation]\nname = \"held\"\n\n[install]\napt = [{ name = \"tree\", hold = true }]\n",
+        );
+        let fake = Rc::new(
+            FakeApt::new()
+                .with_package("tree", "2.1.0")
+                .with_home(home.path()),
+        );
+
+        for _ in 0..2 {
+            Deployer::from_list_with(&[&config], fake.clone())
+                .unwrap()
+                .deploy()
+                .unwrap();
+        }
+
+        assert!(fake.package("tree").unwrap().held);
+        let holds = fake
+            .commands()
+            .into_iter()
+            .filter(|cmd| cmd.starts_with("apt-mark hold"))
+            .count();
+        assert_eq!(holds, 1);
+    }
+
+    #[test]
+    fn items_with_unmet_conditions_are_skipped() {
+        let (home, config) = home_with(
+            r#"
+[infomation]
+name = "when"
+
+[install.env]
+KEPT = "yes"
+SKIPPED = { value = "no", when = { arch = ["no-such-arch"] } }
+
+[install.files]
+"kept.conf" = "kept"
+"skipped.conf" = { content = "skipped", when = { arch = ["no-such-arch"] } }
+
+[install.debconf]
+"demo/question" = { answer = "boolean true", when = { arch = ["no-such-arch"] } }
+"#,
+        );
+        let fake = Rc::new(FakeApt::new().with_home(home.path()));
+
+        Deployer::from_list_with(&[&config], fake.clone())
+            .unwrap()
+            .deploy()
+            .unwrap();
+
+        let deploy_dir = home.path().join(DEPLOY_DIR).join("when");
+        let rc = fs::read_to_string(deploy_dir.join("rc")).unwrap();
+        assert!(rc.contains("KEPT") && !rc.contains("SKIPPED"), "{rc}");
+        assert!(deploy_dir.join("kept.conf").exists());
+        assert!(!deploy_dir.join("skipped.conf").exists());
+        assert_eq!(fake.debconf("demo/question"), None);
+    }
+
+    #[test]
+    fn home_comes_from_the_runner() {
+        let (home, config) = home_with(
+            r#"
+[infomation]
+name = "home"
+
+[install.env]
+HOME_DIR = "{{ home }}"
+MARKED = { value = "yes", when = { file = ["~/marker"] } }
+UNMARKED = { value = "no", when = { file = ["~/missing"] } }
+"#,
+        );
+        fs::write(home.path().join("marker"), "").unwrap();
+        let fake = Rc::new(FakeApt::new().with_home(home.path()));
+
+        Deployer::from_list_with(&[&config], fake.clone())
+            .unwrap()
+            .deploy()
+            .unwrap();
+
+        let rc = fs::read_to_string(home.path().join(DEPLOY_DIR).join("home").join("rc")).unwrap();
+        assert!(
+            rc.contains(&home.path().to_string_lossy().to_string()),
+            "{rc}"
+        );
+        assert!(rc.contains("MARKED") && !rc.contains("UNMARKED"), "{rc}");
+    }
+
+    fn config_info(name: &str, requires: &[&str]) -> ConfigInfo {
+        let mut config = Config::default();
+        config.infomation.name = name.to_string();
+        if !requires.is_empty() {
+            config.infomation.requires =
+                Some(requires.iter().map(|name| name.to_string()).collect());
+        }
+
+        ConfigInfo {
+            file: PathBuf::from(format!("{name}.toml")),
+            config,
+        }
+    }
+
+    fn sorted_names(list: Vec<ConfigInfo>) -> Result<Vec<String>> {
+        Ok(Deployer::sort_configs(list)?
+            .into_iter()
+            .map(|info| info.config.infomation.name)
+            .collect())
+    }
+
+    #[test]
+    fn configs_are_sorted_by_requires() {
+        let names = sorted_names(vec![
+            config_info("app", &["rust", "bash"]),
+            config_info("misc", &[]),
+            config_info("rust", &["bash"]),
+            config_info("bash", &[]),
+        ])
+        .unwrap();
+
+        /* 没有依赖关系的配置保持原来的顺序 */
+        assert_eq!(names, ["misc", "bash", "rust", "app"]);
+    }
+
+    #[test]
+    fn missing_dependency_is_an_error() {
+        let err = sorted_names(vec![config_info("app", &["rust"])]).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "app in app.toml requires rust, but it is not in the config list"
+        );
+    }
+
+    #[test]
+    fn dependency_cycle_is_an_error() {
+        let err = sorted_names(vec![
+            config_info("misc", &[]),
+            config_info("a", &["b"]),
+            config_info("b", &["c"]),
+            config_info("c", &["a"]),
+        ])
+        .unwrap_err();
+
+        assert_eq!(err.to_string(), "Configs require each other: a, b, c");
+    }
+
+    #[test]
+    fn profile_output_is_parsed() -> Result<()> {
+        let output = "git 100.250000 100.500000\n\nrust 101.000000 101.125000\n";
+
+        let profile = Deployer::parse_profile(output)?;
+
+        assert_eq!(
+            profile,
+            vec![
+                ("git".to_string(), Duration::from_millis(250)),
+                ("rust".to_string(), Duration::from_millis(125)),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn profile_without_epochrealtime_is_an_error() {
+        /* bash 4中$EPOCHREALTIME为空，变成了普通字符串 */
+        let error = Deployer::parse_profile("git $EPOCHREALTIME x").unwrap_err();
+        assert_eq!(error.to_string(), "bash 5 or later is required");
+    }
+
+    #[test]
+    fn dependents_of_skipped_configs_are_skipped() {
+        let (home, unmet) = home_with(
+            "[infomation]\nname = \"arm\"\nwhen = { arch = [\"aarch64\"] }\n\n[install.env]\nARM = \"yes\"\n",
+        );
+        let dependent = home.path().join("dependent.toml");
+        fs::write(
+            &dependent,
+            "[infomation]\nname = \"dependent\"\nrequires = [\"arm\"]\n\n[install.env]\nDEPENDENT = \"yes\"\n",
+        )
+        .unwrap();
+        let fake = Rc::new(FakeApt::new().with_home(home.path()));
+
+        Deployer::from_list_with(&[&unmet, &dependent], fake.clone())
+            .unwrap()
+            .deploy()
+            .unwrap();
+
+        let deploy_dir = home.path().join(DEPLOY_DIR);
+        assert!(!deploy_dir.join("arm").exists());
+        assert!(!deploy_dir.join("dependent").exists());
+
+        let config: Config = toml::from_str(&fs::read_to_string(&dependent).unwrap()).unwrap();
+        let skipped = HashSet::from(["arm".to_string()]);
+        let host = Host::fake(fake.clone());
+        assert_eq!(
+            Deployer::skip_reason(&config, &skipped, fake.as_ref(), &host).as_deref(),
+            Some("requires arm which is skipped")
+        );
+    }
+
+    #[test]
+    fn stale_completions_are_removed() {
+        let with_completion = r#"
+[infomation]
+name = "tool"
+
+[install.command.tool]
+content = "echo tool"
+completion = { subcommands = ["start"], flags = ["--help"] }
+"#;
+        let (home, config) = home_with(with_completion);
+        let fake = Rc::new(FakeApt::new().with_home(home.path()));
+        let completion = Completion::completion_dir(home.path()).join("tool");
+        let deploy = || {
+            Deployer::from_list_with(&[&config], fake.clone())
+                .unwrap()
+                .deploy()
+                .unwrap()
+        };
+
+        deploy();
+        let deploy_dir = home.path().join(DEPLOY_DIR);
+        assert!(completion.is_file());
+        assert_eq!(
+            State::load(&deploy_dir).unwrap().files,
+            vec![completion.clone()]
+        );
+
+        /* 配置中去掉补全后重新部署，旧的补全被删除 */
+        fs::write(
+            &config,
+            with_completion.replace("completion =", "# completion ="),
+        )
+        .unwrap();
+        deploy();
+        assert!(!completion.exists());
+        assert!(State::load(&deploy_dir).unwrap().files.is_empty());
+
+        /* 卸载时删除状态中记录的补全 */
+        fs::write(&config, with_completion).unwrap();
+        deploy();
+        Deployer::remove_installed_files(&deploy_dir, &[]).unwrap();
+        assert!(!completion.exists());
+    }
+}
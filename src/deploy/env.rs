@@ -1,43 +1,98 @@
 use crate::deploy::{InstallItem, Installed};
-use anyhow::Ok;
+use crate::op::file::FileOp;
+use crate::secret::SecretKey;
+use crate::types::config::{Secret, StringOr};
+use anyhow::{Context, Ok};
+use derive_more::Debug;
 use log::info;
 use regex::Regex;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 #[derive(Debug)]
 pub struct Env {
     key: String,
-    value: String,
+    value: StringOr<Secret>,
+
+    /// 解密后的值保存在这个文件中，rc脚本只引用文件，不包含明文
+    secret_file: PathBuf,
+
+    #[debug(ignore)]
+    secret_key: Rc<SecretKey>,
 }
 
 impl Env {
-    pub fn from_kv<K, V>(key: K, value: V) -> Self
+    pub fn from_kv<K, P>(
+        key: K,
+        value: &StringOr<Secret>,
+        install_path: P,
+        secret_key: Rc<SecretKey>,
+    ) -> Self
     where
         K: Into<String>,
-        V: Into<String>,
+        P: AsRef<Path>,
     {
+        let key = key.into();
         Self {
-            key: key.into(),
-            value: value.into(),
+            secret_file: install_path.as_ref().join("secrets").join(&key),
+            key,
+            value: value.clone(),
+            secret_key,
         }
     }
 }
 
 impl InstallItem for Env {
-    fn check(&self) -> anyhow::Result<()> {
-        info!(target: "Env", "Checking env key {}...", self.key);
-
+    fn validate(&self) -> anyhow::Result<()> {
         let re = Regex::new(r"^[a-zA-Z_][a-zA-Z0-9_-]*$").unwrap();
         anyhow::ensure!(
             !self.key.is_empty() && !self.key.contains('=') && re.is_match(&self.key),
             "Name only allows letters, numbers, underscores(_), and hyphens(-)."
         );
+
+        /* 只检查密文的格式，不需要密钥 */
+        if let StringOr::Object(Secret { secret }) = &self.value {
+            SecretKey::check_format(secret)
+                .context(format!("Secret of env {} is invaild", self.key))?;
+        }
+
+        Ok(())
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        info!(target: "Env", "Checking env key {}...", self.key);
+        self.validate()?;
+
+        /* 提前解密一次，密钥错误时不安装任何内容 */
+        if let StringOr::Object(Secret { secret }) = &self.value {
+            self.secret_key
+                .decrypt(secret)
+                .context(format!("Fail to decrypt env {}", self.key))?;
+        }
+
         Ok(())
     }
 
     fn install(&self) -> anyhow::Result<Installed> {
         info!(target: "Env", "Installing {}...", self.key);
-        Ok(Installed::Rc {
-            command: format!("export {}=\"{}\"", self.key, self.value),
-        })
+
+        let command = match &self.value {
+            StringOr::String(value) => format!("export {}=\"{}\"", self.key, value),
+            StringOr::Object(Secret { secret }) => {
+                let value = self
+                    .secret_key
+                    .decrypt(secret)
+                    .context(format!("Fail to decrypt env {}", self.key))?;
+                FileOp::write_private(&self.secret_file, value)?;
+
+                format!(
+                    "export {}=\"$(< '{}')\"",
+                    self.key,
+                    self.secret_file.to_string_lossy()
+                )
+            }
+        };
+
+        Ok(Installed::Rc { command })
     }
 }
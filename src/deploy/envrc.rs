@@ -1,36 +1,55 @@
 use crate::{
     deploy::{InstallItem, Installed},
+    http::download,
     op::file::FileOp,
-    types::config::Content,
+    types::config::{Content, EnvrcEntry},
 };
-use anyhow::{Context, ensure};
+use anyhow::{Context, bail, ensure};
 use log::info;
+use regex::Regex;
 use std::{
     fs,
     path::{Path, PathBuf},
 };
+use url::Url;
 
 #[derive(Debug)]
 pub struct Envrc {
     content: Content,
+    lazy: Vec<String>,
     config_path: PathBuf,
+    install_file: PathBuf,
 }
 
 impl Envrc {
-    pub fn from_content<P>(content: &Content, config_path: P) -> Self
+    pub fn from_entry<P1, P2>(entry: &EnvrcEntry, config_path: P1, install_file: P2) -> Self
     where
-        P: AsRef<Path>,
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
     {
         Self {
-            content: content.clone(),
+            content: entry.content().clone(),
+            lazy: entry.lazy().to_vec(),
             config_path: config_path.as_ref().to_path_buf(),
+            install_file: install_file.as_ref().to_path_buf(),
         }
     }
+
+    /// 为每个触发命令生成一个桩函数，第一次调用时删除所有桩函数，source脚本后再执行原命令
+    fn lazy_stubs(&self) -> String {
+        let unset = format!("unset -f {}", self.lazy.join(" "));
+        let source = format!("source '{}'", self.install_file.to_string_lossy());
+
+        self.lazy
+            .iter()
+            .map(|name| format!("{name}() {{ {unset}; {source}; {name} \"$@\"; }}"))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
 }
 
 impl InstallItem for Envrc {
-    fn check(&self) -> anyhow::Result<()> {
-        info!(target: "Envrc", "Checking envrc {:?}...", self.content);
+    fn validate(&self) -> anyhow::Result<()> {
         match &self.content {
             Content::Raw(_) => {}
             Content::File(path) => {
@@ -46,22 +65,78 @@ impl InstallItem for Envrc {
                     path.to_string_lossy()
                 );
             }
-            Content::Url(_) => todo!(),
+            Content::Url(url) => {
+                Url::parse(url).context(format!("{url} is not a valid url"))?;
+            }
+            Content::Secret(_) => bail!("secret content is only supported in env and files"),
         }
+
+        let re = Regex::new(r"^[a-zA-Z_][a-zA-Z0-9_-]*$").unwrap();
+        for name in &self.lazy {
+            ensure!(
+                re.is_match(name),
+                "Lazy command {name:?} only allows letters, numbers, underscores(_), and hyphens(-)."
+            );
+        }
+
         Ok(())
     }
 
+    fn check(&self) -> anyhow::Result<()> {
+        info!(target: "Envrc", "Checking envrc {:?}...", self.content);
+        self.validate()
+    }
+
     fn install(&self) -> anyhow::Result<Installed> {
         info!(target: "Envrc", "Installing envrc {:?}...", self.content);
         let rc_content = match &self.content {
             Content::Raw(content) => content.clone(),
-            Content::File(path) => fs::read_to_string(path)
-                .context(format!("Fail to read file {}", path.to_string_lossy()))?,
-            Content::Url(_) => todo!(),
+            Content::File(path) => {
+                let path = self.config_path.join(path);
+                fs::read_to_string(&path)
+                    .context(format!("Fail to read file {}", path.to_string_lossy()))?
+            }
+            Content::Url(url) => String::from_utf8(download(&Url::parse(url)?)?.to_vec())
+                .context(format!("{url} is not a text file"))?,
+            Content::Secret(_) => bail!("secret content is only supported in env and files"),
         };
 
+        if self.lazy.is_empty() {
+            return Ok(Installed::Rc {
+                command: rc_content,
+            });
+        }
+
+        info!(target: "Envrc", "Deferring envrc until first use of {}", self.lazy.join(", "));
+
+        FileOp::write(&self.install_file, rc_content, None).context(format!(
+            "Fail to create file {}",
+            self.install_file.to_string_lossy()
+        ))?;
+
         Ok(Installed::Rc {
-            command: rc_content,
+            command: self.lazy_stubs(),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lazy_stubs_source_the_rc_once() {
+        let envrc = Envrc {
+            content: Content::Raw("eval \"$(pyenv init -)\"".to_string()),
+            lazy: vec!["pyenv".to_string(), "python".to_string()],
+            config_path: PathBuf::from("/config"),
+            install_file: PathBuf::from("/deploy/pyenv/envrc"),
+        };
+
+        assert_eq!(
+            envrc.lazy_stubs(),
+            "pyenv() { unset -f pyenv python; source '/deploy/pyenv/envrc'; pyenv \"$@\"; }\n\
+             python() { unset -f pyenv python; source '/deploy/pyenv/envrc'; python \"$@\"; }"
+        );
+    }
+}
@@ -1,11 +1,14 @@
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 use crate::{
     deploy::{InstallItem, Installed},
     op::file::FileOp,
+    secret::SecretKey,
     types::config::{Content, StringOr},
 };
-use anyhow::{Context, ensure};
+use anyhow::{Context, bail, ensure};
+use derive_more::Debug;
 use log::info;
 
 #[derive(Debug)]
@@ -13,6 +16,9 @@ pub struct File {
     content: StringOr<Content>,
     config_path: PathBuf,
     install_file: PathBuf,
+
+    #[debug(ignore)]
+    secret_key: Rc<SecretKey>,
 }
 
 impl File {
@@ -21,6 +27,7 @@ impl File {
         content: &StringOr<Content>,
         config_path: P2,
         install_path: P3,
+        secret_key: Rc<SecretKey>,
     ) -> Self
     where
         P1: AsRef<Path>,
@@ -39,13 +46,13 @@ impl File {
             content: content.clone(),
             config_path: config_path.as_ref().to_path_buf(),
             install_file,
+            secret_key,
         }
     }
 }
 
 impl InstallItem for File {
-    fn check(&self) -> anyhow::Result<()> {
-        info!(target: "File", "Checking file {:?}...", self.content);
+    fn validate(&self) -> anyhow::Result<()> {
         match &self.content {
             StringOr::String(_) | StringOr::Object(Content::Raw(_)) => {}
             StringOr::Object(Content::File(path)) => {
@@ -61,7 +68,29 @@ impl InstallItem for File {
                     path.to_string_lossy()
                 );
             }
-            StringOr::Object(Content::Url(_)) => todo!(),
+            StringOr::Object(Content::Url(_)) => bail!("url content of files is not supported yet"),
+            /* 只检查密文的格式，不需要密钥 */
+            StringOr::Object(Content::Secret(secret)) => {
+                SecretKey::check_format(secret).context(format!(
+                    "Secret of file {} is invaild",
+                    self.install_file.to_string_lossy()
+                ))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        info!(target: "File", "Checking file {:?}...", self.content);
+        self.validate()?;
+
+        /* 提前解密一次，密钥错误时不安装任何内容 */
+        if let StringOr::Object(Content::Secret(secret)) = &self.content {
+            self.secret_key.decrypt(secret).context(format!(
+                "Fail to decrypt file {}",
+                self.install_file.to_string_lossy()
+            ))?;
         }
 
         ensure!(
@@ -97,7 +126,17 @@ impl InstallItem for File {
                     self.install_file.to_string_lossy()
                 ))?;
             }
-            StringOr::Object(Content::Url(_)) => todo!(),
+            StringOr::Object(Content::Url(_)) => bail!("url content of files is not supported yet"),
+            StringOr::Object(Content::Secret(secret)) => {
+                let content = self.secret_key.decrypt(secret).context(format!(
+                    "Fail to decrypt file {}",
+                    self.install_file.to_string_lossy()
+                ))?;
+                FileOp::write_private(&self.install_file, content).context(format!(
+                    "fail to install file {}",
+                    self.install_file.to_string_lossy()
+                ))?;
+            }
         }
 
         Ok(Installed::File {
@@ -1,25 +1,42 @@
+use crate::op::package::Backend;
 use anyhow::Result;
 use std::path::PathBuf;
 
 pub mod deployer;
+pub mod state;
+pub mod validator;
 
 mod alias;
 mod apt;
+mod apt_repo;
 mod command;
+mod completion;
+mod deb;
+mod debconf;
 mod env;
 mod envrc;
 mod file;
+mod package;
 
 #[derive(Debug)]
 #[allow(unused)]
 enum Installed {
     Apt { name: String },
+    Package { backend: Backend, name: String },
     Rc { command: String },
     Path { path: String },
     File { file: PathBuf },
+    Completion { file: PathBuf },
+    Deb { name: String },
+    Debconf { question: String },
 }
 
 trait InstallItem: std::fmt::Debug {
+    /// 不执行命令、不写文件、不需要密钥的静态检查，check会先执行它
+    fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+
     /// 安装前的检查
     fn check(&self) -> Result<()>;
 
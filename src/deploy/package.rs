@@ -0,0 +1,135 @@
+use crate::{
+    deploy::{InstallItem, Installed},
+    op::package::{Backend, Packages},
+    types::config::{AptPackage, PackageName},
+};
+use anyhow::{anyhow, ensure};
+use derive_more::Debug;
+use log::*;
+use std::rc::Rc;
+
+#[derive(Debug)]
+pub struct Package {
+    package: PackageName,
+    backend: Option<Backend>,
+
+    #[debug(ignore)]
+    packages: Rc<Packages>,
+}
+
+impl Package {
+    /// `backend`为None时表示当前系统没有识别到支持的包管理器
+    pub fn from_name<T>(package: T, backend: Option<Backend>, packages: Rc<Packages>) -> Self
+    where
+        T: Into<PackageName>,
+    {
+        Self {
+            package: package.into(),
+            backend,
+            packages,
+        }
+    }
+
+    fn backend(&self) -> anyhow::Result<Backend> {
+        self.backend.ok_or(anyhow!(
+            "No supported package manager found for {}",
+            self.package.name
+        ))
+    }
+
+    /// 按`distro`和`alternatives`找出当前系统中可用的包名
+    fn resolve(&self, backend: Backend) -> anyhow::Result<String> {
+        let candidates = self.packages.candidates(
+            &self.package.name,
+            self.package.alternatives.as_ref(),
+            self.package.distro.as_ref(),
+        );
+
+        if let [name] = &candidates[..] {
+            self.packages.with(backend, |manager| manager.check(name))?;
+            return Ok(name.clone());
+        }
+
+        let mut errors = Vec::new();
+        for name in &candidates {
+            match self.packages.with(backend, |manager| manager.check(name)) {
+                Ok(()) => return Ok(name.clone()),
+                Err(err) => errors.push(format!("{err:#}")),
+            }
+        }
+
+        Err(anyhow!(
+            "Con not find any of {} on {}: {}",
+            candidates.join(", "),
+            self.packages.distro(),
+            errors.join("; ")
+        ))
+    }
+}
+
+impl InstallItem for Package {
+    fn validate(&self) -> anyhow::Result<()> {
+        let package = &self.package;
+
+        /* 以`-`开头的名字会被包管理器当作选项 */
+        for name in std::iter::once(&package.name)
+            .chain(package.alternatives.iter().flatten())
+            .chain(package.distro.iter().flat_map(|distro| distro.values()))
+        {
+            ensure!(
+                !name.is_empty() && !name.starts_with('-') && !name.contains(char::is_whitespace),
+                "{name:?} is not a valid package name"
+            );
+        }
+
+        Ok(())
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        self.validate()?;
+        let backend = self.backend()?;
+        info!(target: "Package", "Checking package {} by {backend}...", self.package.name);
+
+        /* 包管理器本身初始化失败时直接报错，只有软件包找不到时才考虑待添加的apt软件源 */
+        self.packages.with(backend, |_| Ok(()))?;
+        let name = match self.resolve(backend) {
+            Ok(name) => name,
+            Err(err) => match self.packages.pending_apt_source(&self.package.name) {
+                Some(repo) if backend == Backend::Apt => {
+                    warn!(target: "Package", "{err:#}, it will be checked again after adding apt repository {repo}");
+                    return Ok(());
+                }
+                _ => return Err(err),
+            },
+        };
+
+        if name != self.package.name {
+            info!(target: "Package", "{} resolved to {name} on {}", self.package.name, self.packages.distro());
+        }
+
+        Ok(())
+    }
+
+    fn install(&self) -> anyhow::Result<Installed> {
+        let backend = self.backend()?;
+        let name = self.resolve(backend)?;
+
+        /* 与install.apt一样只入队，在添加软件源和预设debconf之后统一安装 */
+        if backend == Backend::Apt {
+            info!(target: "Package", "Queueing {name} by apt...");
+            let package = AptPackage {
+                name: name.clone(),
+                ..Default::default()
+            };
+            self.packages.with_apt(|apt| apt.queue(&package, None))?;
+
+            return Ok(Installed::Apt { name });
+        }
+
+        info!(target: "Package", "Installing {name} by {backend}...");
+        self.packages
+            .with(backend, |manager| manager.install(&name))?;
+
+        Ok(Installed::Package { backend, name })
+    }
+}
@@ -0,0 +1,50 @@
+use crate::op::file::FileOp;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// 部署状态，保存在部署目录下，卸载时根据它清理部署目录之外的内容
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    /// 安装在部署目录之外的文件
+    #[serde(default)]
+    pub files: Vec<PathBuf>,
+
+    /// 通过.deb文件安装的软件包
+    #[serde(default)]
+    pub debs: Vec<String>,
+
+    /// 每个配置的输入值: <配置名> <输入名> <值>，再次部署时不需要重新输入
+    #[serde(default)]
+    pub inputs: HashMap<String, HashMap<String, String>>,
+}
+
+impl State {
+    const STATE_FILE: &str = "state.toml";
+
+    pub fn load<P: AsRef<Path>>(deploy_dir: P) -> Result<Self> {
+        let state_file = deploy_dir.as_ref().join(Self::STATE_FILE);
+
+        if !FileOp::exist(&state_file) {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(&state_file)
+            .context(format!("Fail to read {}", state_file.to_string_lossy()))?;
+
+        toml::from_str(&content)
+            .context(format!("File {} is invaild", state_file.to_string_lossy()))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, deploy_dir: P) -> Result<()> {
+        let state_file = deploy_dir.as_ref().join(Self::STATE_FILE);
+
+        FileOp::write(&state_file, toml::to_string_pretty(self)?, None)?;
+
+        Ok(())
+    }
+}
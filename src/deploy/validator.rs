@@ -0,0 +1,400 @@
+use crate::deploy::InstallItem;
+use crate::deploy::alias::Alias;
+use crate::deploy::apt::Apt;
+use crate::deploy::apt_repo::AptRepo;
+use crate::deploy::command::Command;
+use crate::deploy::completion::Completion;
+use crate::deploy::deb::Deb;
+use crate::deploy::debconf::Debconf;
+use crate::deploy::deployer::Deployer;
+use crate::deploy::env::Env;
+use crate::deploy::envrc::Envrc;
+use crate::deploy::file::File;
+use crate::deploy::package::Package;
+use crate::op::package::{Backend, Packages};
+use crate::runner::{CommandRunner, SystemRunner};
+use crate::secret::SecretKey;
+use crate::types::config::{AliasSpec, AptPackage, Config, DebconfSpec, PackageName};
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+
+/// 不执行命令、不写文件、不需要密钥地检查配置，可以用于pre-commit钩子
+///
+/// 不考虑部署条件和当前系统，检查配置中所有的项，以及配置之间的冲突: 重名的别名或命令、
+/// 别名遮盖了命令、空的段。带有`when`的配置或项是有意按条件部署的，不参与重名检查
+///
+/// 所有问题都会被收集起来，而不是遇到第一个问题就停止
+#[derive(Debug)]
+pub struct Validator {
+    /// 已检查的别名和命令，以及定义它们的配置
+    aliases: HashMap<String, String>,
+    commands: HashMap<String, String>,
+    problems: Vec<String>,
+    /// 提供主目录，检查时不会运行命令
+    runner: Rc<dyn CommandRunner>,
+}
+
+impl Default for Validator {
+    fn default() -> Self {
+        Self::new(Rc::new(SystemRunner))
+    }
+}
+
+impl Validator {
+    pub fn new(runner: Rc<dyn CommandRunner>) -> Self {
+        Self {
+            aliases: Default::default(),
+            commands: Default::default(),
+            problems: Default::default(),
+            runner,
+        }
+    }
+
+    /// 检查`config_file`中的配置`config`，`config`应该是渲染过变量的配置
+    pub fn validate<P: AsRef<Path>>(&mut self, config: &Config, config_file: P) {
+        let config_file = config_file.as_ref();
+        let origin = format!(
+            "{} in {}",
+            config.infomation.name,
+            config_file.to_string_lossy()
+        );
+        let config_path = config_file.parent().unwrap_or(Path::new("."));
+
+        self.check_empty(config, &origin);
+        self.check_names(config, &origin);
+
+        let (home, deploy_dir) = match self
+            .runner
+            .home_dir()
+            .and_then(|home| Ok((home, Deployer::deploy_dir(self.runner.as_ref())?)))
+        {
+            Ok(dirs) => dirs,
+            Err(err) => {
+                self.problems.push(format!("{origin}: {err:#}"));
+                return;
+            }
+        };
+
+        for (location, item) in self.items(config, config_path, &home, &deploy_dir) {
+            if let Err(err) = item.validate() {
+                self.problems.push(format!("{origin}: {location}: {err:#}"));
+            }
+        }
+    }
+
+    /// 记录在检查配置之外发现的问题，如配置文件无法解析
+    pub fn report(&mut self, problem: String) {
+        self.problems.push(problem);
+    }
+
+    pub fn problems(&self) -> &[String] {
+        &self.problems
+    }
+
+    /// 空的段通常是漏写或写错了位置
+    fn check_empty(&mut self, config: &Config, origin: &str) {
+        let Ok(serde_jsonc::Value::Object(install)) = serde_jsonc::to_value(&config.install) else {
+            return;
+        };
+
+        for (section, value) in install {
+            let empty = match value {
+                serde_jsonc::Value::Array(list) => list.is_empty(),
+                serde_jsonc::Value::Object(map) => map.is_empty(),
+                _ => false,
+            };
+
+            if empty {
+                self.problems
+                    .push(format!("{origin}: install.{section} is empty"));
+            }
+        }
+    }
+
+    fn check_names(&mut self, config: &Config, origin: &str) {
+        if config.infomation.when.is_some() {
+            return;
+        }
+
+        let install = &config.install;
+
+        for (name, alias) in sorted(install.alias.as_ref()) {
+            if AliasSpec::from(alias).when.is_some() {
+                continue;
+            }
+
+            if let Some(other) = self.aliases.insert(name.clone(), origin.to_string()) {
+                self.problems
+                    .push(format!("{origin}: alias {name} is also defined in {other}"));
+            }
+            if let Some(other) = self.commands.get(name) {
+                self.problems.push(format!(
+                    "{origin}: alias {name} shadows the command defined in {other}"
+                ));
+            }
+        }
+
+        for (name, entry) in sorted(install.command.as_ref()) {
+            if entry.when().is_some() {
+                continue;
+            }
+
+            if let Some(other) = self.commands.insert(name.clone(), origin.to_string()) {
+                self.problems.push(format!(
+                    "{origin}: command {name} is also defined in {other}"
+                ));
+            }
+            if let Some(other) = self.aliases.get(name) {
+                self.problems.push(format!(
+                    "{origin}: command {name} is shadowed by the alias defined in {other}"
+                ));
+            }
+        }
+    }
+
+    /// 配置中所有需要检查的项，以及它们在配置中的位置
+    fn items(
+        &self,
+        config: &Config,
+        config_path: &Path,
+        home: &Path,
+        deploy_dir: &Path,
+    ) -> Vec<(String, Box<dyn InstallItem>)> {
+        let install = &config.install;
+        let install_path = deploy_dir.join(&config.infomation.name);
+        let completion_dir = Completion::completion_dir(home);
+        /* 只用于构造安装项，检查时不会用到 */
+        let packages = Rc::new(Packages::new(None, self.runner.clone()));
+        let secret_key = Rc::new(SecretKey::default());
+
+        let mut items: Vec<(String, Box<dyn InstallItem>)> = Vec::new();
+
+        for (index, package) in install.packages.iter().flatten().enumerate() {
+            items.push((
+                format!("install.packages[{index}]"),
+                Box::new(Package::from_name(package, None, packages.clone())),
+            ));
+        }
+
+        for (index, package) in install.apt.iter().flatten().enumerate() {
+            items.push((
+                format!("install.apt[{index}]"),
+                Box::new(Apt::from_package(
+                    AptPackage::from(package),
+                    packages.clone(),
+                )),
+            ));
+        }
+
+        for (backend, list) in [
+            (Backend::Dnf, &install.dnf),
+            (Backend::Pacman, &install.pacman),
+            (Backend::Apk, &install.apk),
+            (Backend::Zypper, &install.zypper),
+        ] {
+            for (index, package) in list.iter().flatten().enumerate() {
+                items.push((
+                    format!("install.{backend}[{index}]"),
+                    Box::new(Package::from_name(
+                        PackageName::from(package),
+                        Some(backend),
+                        packages.clone(),
+                    )),
+                ));
+            }
+        }
+
+        for (question, answer) in sorted(install.debconf.as_ref()) {
+            items.push((
+                format!("install.debconf.{question}"),
+                Box::new(Debconf::from_pair(
+                    question,
+                    DebconfSpec::from(answer).answer,
+                    packages.clone(),
+                )),
+            ));
+        }
+
+        for (index, deb) in install.deb.iter().flatten().enumerate() {
+            items.push((
+                format!("install.deb[{index}]"),
+                Box::new(Deb::from_content(
+                    deb.content(),
+                    config_path,
+                    install_path.join("deb"),
+                    packages.clone(),
+                )),
+            ));
+        }
+
+        for (name, repo) in sorted(install.apt_repo.as_ref()) {
+            items.push((
+                format!("install.apt_repo.{name}"),
+                Box::new(AptRepo::from_repo(
+                    name,
+                    repo,
+                    config_path,
+                    packages.clone(),
+                )),
+            ));
+        }
+
+        for (name, alias) in sorted(install.alias.as_ref()) {
+            items.push((
+                format!("install.alias.{name}"),
+                Box::new(Alias::from_pair(name, AliasSpec::from(alias).command)),
+            ));
+        }
+
+        for (name, entry) in sorted(install.command.as_ref()) {
+            items.push((
+                format!("install.command.{name}"),
+                Box::new(Command::from_content(
+                    name,
+                    entry.content(),
+                    config_path,
+                    install_path.join("bin"),
+                )),
+            ));
+
+            if let Some(completion) = entry.completion() {
+                items.push((
+                    format!("install.command.{name}.completion"),
+                    Box::new(Completion::from_completion(
+                        name,
+                        completion,
+                        config_path,
+                        &completion_dir,
+                    )),
+                ));
+            }
+        }
+
+        for (name, value) in sorted(install.env.as_ref()) {
+            items.push((
+                format!("install.env.{name}"),
+                Box::new(Env::from_kv(
+                    name,
+                    value.value(),
+                    &install_path,
+                    secret_key.clone(),
+                )),
+            ));
+        }
+
+        for (index, entry) in install.envrc.iter().flatten().enumerate() {
+            items.push((
+                format!("install.envrc[{index}]"),
+                Box::new(Envrc::from_entry(
+                    entry,
+                    config_path,
+                    install_path.join("lazy").join(format!("{index}.sh")),
+                )),
+            ));
+        }
+
+        for (path, content) in sorted(install.files.as_ref()) {
+            items.push((
+                format!("install.files.{}", path.to_string_lossy()),
+                Box::new(File::from_content(
+                    path,
+                    content.content(),
+                    config_path,
+                    &install_path,
+                    secret_key.clone(),
+                )),
+            ));
+        }
+
+        items
+    }
+}
+
+/// 按键排序，使报告的顺序固定
+fn sorted<K: Ord, V>(map: Option<&HashMap<K, V>>) -> Vec<(&K, &V)> {
+    let mut list: Vec<_> = map.into_iter().flatten().collect();
+    list.sort_by(|a, b| a.0.cmp(b.0));
+    list
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::fake::FakeApt;
+    use tempfile::TempDir;
+
+    const FIRST: &str = r#"
+[infomation]
+name = "first"
+
+[install]
+apt = []
+
+[install.alias]
+ll = "ls -l"
+gs = "git status"
+
+[install.command]
+hello = "echo hello"
+"#;
+
+    const SECOND: &str = r#"
+[infomation]
+name = "second"
+
+[install.alias]
+ll = "ls -la"
+hello = "echo hi"
+arm = { command = "ls", when = { arch = ["aarch64"] } }
+
+[install.command]
+gs = "git status --short"
+hello = "echo hello again"
+arm = { content = "uname -m", when = { arch = ["aarch64"] } }
+"#;
+
+    const GUARDED: &str = r#"
+[infomation]
+name = "guarded"
+when = { arch = ["aarch64"] }
+
+[install.alias]
+ll = "ls -lh"
+"#;
+
+    fn validate(configs: &[&str]) -> Vec<String> {
+        let home = TempDir::new().unwrap();
+        let mut validator = Validator::new(Rc::new(FakeApt::new().with_home(home.path())));
+
+        for (index, content) in configs.iter().enumerate() {
+            let config: Config = toml::from_str(content).unwrap();
+            validator.validate(&config, format!("/configs/{index}.toml"));
+        }
+
+        validator.problems().to_vec()
+    }
+
+    #[test]
+    fn conflicting_names_are_reported() {
+        assert_eq!(
+            validate(&[FIRST, SECOND]),
+            [
+                "first in /configs/0.toml: install.apt is empty",
+                "second in /configs/1.toml: alias hello shadows the command defined in first in /configs/0.toml",
+                "second in /configs/1.toml: alias ll is also defined in first in /configs/0.toml",
+                "second in /configs/1.toml: command gs is shadowed by the alias defined in first in /configs/0.toml",
+                "second in /configs/1.toml: command hello is also defined in first in /configs/0.toml",
+                "second in /configs/1.toml: command hello is shadowed by the alias defined in second in /configs/1.toml",
+            ]
+        );
+    }
+
+    #[test]
+    fn conditional_configs_are_not_checked_for_names() {
+        assert_eq!(
+            validate(&[FIRST, GUARDED]),
+            ["first in /configs/0.toml: install.apt is empty"]
+        );
+    }
+}
@@ -0,0 +1,412 @@
+use crate::loader::ConfigFormat;
+use regex::Regex;
+use serde_jsonc::Value;
+use std::fmt::Display;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use toml_edit::{ImDocument, Item, TableLike};
+
+/// 配置文件中某个位置的错误，显示为`file:line:column: message`和出错的那一行
+#[derive(Debug)]
+pub struct Diagnostic {
+    file: PathBuf,
+    line: usize,
+    column: usize,
+    width: usize,
+    source_line: String,
+    message: String,
+}
+
+impl Diagnostic {
+    /// `span`是出错内容在`content`中的字节范围，不在字符边界上时扩展到完整的字符
+    pub fn from_span<P, M>(file: P, content: &str, span: Range<usize>, message: M) -> Self
+    where
+        P: AsRef<Path>,
+        M: Into<String>,
+    {
+        let start = floor_char_boundary(content, span.start);
+        let line_start = content[..start].rfind('\n').map_or(0, |index| index + 1);
+        let line_end = content[start..]
+            .find('\n')
+            .map_or(content.len(), |index| start + index);
+        let end = ceil_char_boundary(content, span.end.clamp(start, line_end));
+        let source_line = content[line_start..line_end].trim_end_matches('\r');
+
+        Self {
+            file: file.as_ref().to_path_buf(),
+            line: content[..start].matches('\n').count() + 1,
+            column: content[line_start..start].chars().count() + 1,
+            width: content[start..end].chars().count().max(1),
+            source_line: source_line.to_string(),
+            message: message.into(),
+        }
+    }
+
+    /// `line`和`column`从1开始，`column`按字节计算(与json解析器一致)
+    pub fn from_line<P, M>(file: P, content: &str, line: usize, column: usize, message: M) -> Self
+    where
+        P: AsRef<Path>,
+        M: Into<String>,
+    {
+        let line_start: usize = content
+            .split_inclusive('\n')
+            .take(line.saturating_sub(1))
+            .map(str::len)
+            .sum();
+        let offset = line_start + column.saturating_sub(1);
+
+        Self::from_span(file, content, offset..offset + 1, message)
+    }
+
+    /// 反序列化失败时定位到出错的位置，不能定位时返回None
+    pub fn from_parse_error<P: AsRef<Path>>(
+        file: P,
+        content: &str,
+        err: &anyhow::Error,
+    ) -> Option<Self> {
+        if let Some(err) = err.downcast_ref::<toml::de::Error>() {
+            return Some(Self::from_span(file, content, err.span()?, err.message()));
+        }
+
+        if let Some(err) = err.downcast_ref::<serde_yaml::Error>() {
+            let index = err.location()?.index();
+            return Some(Self::from_span(
+                file,
+                content,
+                index..index + 1,
+                err.to_string(),
+            ));
+        }
+
+        if let Some(err) = err.downcast_ref::<serde_jsonc::Error>() {
+            return Some(Self::from_line(
+                file,
+                content,
+                err.line(),
+                err.column(),
+                err.to_string(),
+            ));
+        }
+
+        None
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let number = self.line.to_string();
+        let pad = " ".repeat(number.len());
+
+        writeln!(
+            f,
+            "{}:{}:{}: {}",
+            self.file.to_string_lossy(),
+            self.line,
+            self.column,
+            self.message
+        )?;
+        writeln!(f, "{pad} |")?;
+        writeln!(f, "{number} | {}", self.source_line)?;
+        write!(
+            f,
+            "{pad} | {}{}",
+            " ".repeat(self.column - 1),
+            "^".repeat(self.width)
+        )
+    }
+}
+
+/// `index`之前最近的字符边界，超出`content`时为`content`的长度
+fn floor_char_boundary(content: &str, index: usize) -> usize {
+    (0..=index.min(content.len()))
+        .rev()
+        .find(|index| content.is_char_boundary(*index))
+        .unwrap_or_default()
+}
+
+/// `index`之后最近的字符边界，超出`content`时为`content`的长度
+fn ceil_char_boundary(content: &str, index: usize) -> usize {
+    (index.min(content.len())..=content.len())
+        .find(|index| content.is_char_boundary(*index))
+        .unwrap_or(content.len())
+}
+
+/// 配置中字段的路径
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// 配置中不认识的字段，serde默认会忽略它们，拼错的字段会让整段配置不生效
+#[derive(Debug)]
+pub struct UnknownKey {
+    pub path: Vec<Segment>,
+    /// 最接近的已知字段
+    pub suggestion: Option<String>,
+}
+
+impl UnknownKey {
+    /// json schema的声明，允许出现在根节点
+    const SCHEMA_KEY: &str = "$schema";
+
+    /// 对比原始内容和反序列化后重新序列化的内容，原始内容中多出来的字段就是不认识的字段
+    pub fn find(original: &Value, known: &Value) -> Vec<Self> {
+        let mut unknown = Vec::new();
+        Self::walk(original, known, &mut Vec::new(), &mut unknown);
+        unknown
+    }
+
+    fn walk(original: &Value, known: &Value, path: &mut Vec<Segment>, unknown: &mut Vec<Self>) {
+        match (original, known) {
+            (Value::Object(original), Value::Object(known)) => {
+                for (key, value) in original {
+                    if path.is_empty() && key == Self::SCHEMA_KEY {
+                        continue;
+                    }
+
+                    path.push(Segment::Key(key.clone()));
+                    match known.get(key) {
+                        Some(known) => Self::walk(value, known, path, unknown),
+                        None => unknown.push(Self {
+                            path: path.clone(),
+                            suggestion: Self::suggest(key, known.keys()),
+                        }),
+                    }
+                    path.pop();
+                }
+            }
+            (Value::Array(original), Value::Array(known)) => {
+                for (index, (original, known)) in original.iter().zip(known).enumerate() {
+                    path.push(Segment::Index(index));
+                    Self::walk(original, known, path, unknown);
+                    path.pop();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// 编辑距离最小的已知字段，相邻字符交换也算一次编辑，如`alais`和`alias`
+    fn suggest<'a, I>(key: &str, candidates: I) -> Option<String>
+    where
+        I: Iterator<Item = &'a String>,
+    {
+        let limit = (key.chars().count() / 3).max(1);
+
+        candidates
+            .map(|candidate| (strsim::osa_distance(key, candidate), candidate))
+            .filter(|(distance, _)| *distance <= limit)
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, candidate)| candidate.clone())
+    }
+
+    fn key(&self) -> &str {
+        match self.path.last() {
+            Some(Segment::Key(key)) => key,
+            _ => "",
+        }
+    }
+
+    /// 所在位置的可读形式，如`install`、`configs[0].install`
+    fn parent(&self) -> String {
+        let mut parent = String::new();
+        for segment in &self.path[..self.path.len() - 1] {
+            match segment {
+                Segment::Key(key) if parent.is_empty() => parent.push_str(key),
+                Segment::Key(key) => parent.push_str(&format!(".{key}")),
+                Segment::Index(index) => parent.push_str(&format!("[{index}]")),
+            }
+        }
+        parent
+    }
+
+    pub fn diagnose<P: AsRef<Path>>(
+        &self,
+        format: ConfigFormat,
+        file: P,
+        content: &str,
+    ) -> Diagnostic {
+        let message = match self.parent() {
+            parent if parent.is_empty() => format!("unknown key `{}`", self.key()),
+            parent => format!("unknown key `{}` in `{parent}`", self.key()),
+        };
+        let message = match &self.suggestion {
+            Some(suggestion) => format!("{message}, did you mean `{suggestion}`?"),
+            None => message,
+        };
+
+        let span = match format {
+            ConfigFormat::Toml => self.toml_span(content),
+            _ => self.text_span(content),
+        };
+
+        Diagnostic::from_span(file, content, span.unwrap_or(0..0), message)
+    }
+
+    /// 在toml文档中找到字段名的位置
+    fn toml_span(&self, content: &str) -> Option<Range<usize>> {
+        let document = ImDocument::parse(content).ok()?;
+        let mut table: &dyn TableLike = document.as_table();
+
+        for (index, segment) in self.path.iter().enumerate() {
+            let Segment::Key(key) = segment else {
+                continue;
+            };
+
+            let (key, item) = table.get_key_value(key)?;
+            if index == self.path.len() - 1 {
+                return key.span();
+            }
+
+            table = match (item, self.path.get(index + 1)) {
+                (Item::ArrayOfTables(tables), Some(Segment::Index(index))) => tables.get(*index)?,
+                (Item::Value(value), Some(Segment::Index(index))) => {
+                    value.as_array()?.get(*index)?.as_inline_table()?
+                }
+                (item, _) => item.as_table_like()?,
+            };
+        }
+
+        None
+    }
+
+    /// yaml和json中按顺序查找路径上的各个字段名，找到最后一个字段名的大致位置
+    fn text_span(&self, content: &str) -> Option<Range<usize>> {
+        let mut offset = 0;
+        let mut span = None;
+
+        for segment in &self.path {
+            let Segment::Key(key) = segment else {
+                continue;
+            };
+
+            let re = Regex::new(&format!(r#"["']?{}["']?\s*:"#, regex::escape(key))).ok()?;
+            let found = re.find(&content[offset..])?;
+            span = Some(offset + found.start()..offset + found.end() - 1);
+            offset += found.end();
+        }
+
+        span
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_error_at_non_ascii() {
+        let content = "{\"x\": 中}";
+        let err = anyhow::Error::from(serde_jsonc::from_str::<Value>(content).unwrap_err());
+        let diagnostic = Diagnostic::from_parse_error("a.json", content, &err).unwrap();
+
+        assert_eq!(
+            (diagnostic.line, diagnostic.column, diagnostic.width),
+            (1, 7, 1)
+        );
+        assert!(
+            diagnostic
+                .to_string()
+                .ends_with("1 | {\"x\": 中}\n  |       ^")
+        );
+    }
+
+    #[test]
+    fn span_inside_a_char() {
+        let content = "a: 中文\nb: 1";
+        /* 中 从第3个字节开始，占3个字节 */
+        let diagnostic = Diagnostic::from_span("a.yaml", content, 4..5, "bad");
+
+        assert_eq!(
+            (diagnostic.line, diagnostic.column, diagnostic.width),
+            (1, 4, 1)
+        );
+        assert_eq!(diagnostic.source_line, "a: 中文");
+    }
+
+    /// toml配置中不认识的字段
+    fn unknown_keys(content: &str) -> Vec<UnknownKey> {
+        let original: Value = ConfigFormat::Toml.parse(content).unwrap();
+        let config: crate::types::config::ConfigList = ConfigFormat::Toml.parse(content).unwrap();
+        UnknownKey::find(&original, &serde_jsonc::to_value(config).unwrap())
+    }
+
+    #[test]
+    fn unknown_keys_with_suggestions() {
+        let content = r#"
+[[configs]]
+infomation = { name = "a", descripton = "typo" }
+install = { alais = { ll = "ls -l" }, something_else = 1 }
+"#;
+        let unknown = unknown_keys(content);
+        let found: Vec<_> = unknown
+            .iter()
+            .map(|key| {
+                (
+                    format!("{}.{}", key.parent(), key.key()),
+                    key.suggestion.as_deref(),
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            found,
+            [
+                (
+                    "configs[0].infomation.descripton".to_string(),
+                    Some("description")
+                ),
+                ("configs[0].install.alais".to_string(), Some("alias")),
+                ("configs[0].install.something_else".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn schema_key_is_allowed_at_root() {
+        let content = "\"$schema\" = \"configlist.schema.json\"\nconfigs = []\n";
+        assert!(unknown_keys(content).is_empty());
+    }
+
+    #[test]
+    fn unknown_key_in_toml_is_located() {
+        let content = r#"[[configs]]
+infomation = { name = "a" }
+
+[configs.install]
+apt = ["tree"]
+
+[configs.install.env]
+EDITOR = "vim"
+
+[[configs]]
+infomation = { name = "b" }
+
+[configs.install]
+  aptt = ["fd-find"]
+"#;
+        let unknown = unknown_keys(content);
+        assert_eq!(unknown.len(), 1);
+
+        let diagnostic = unknown[0].diagnose(ConfigFormat::Toml, "list.toml", content);
+        assert_eq!(
+            (diagnostic.line, diagnostic.column, diagnostic.width),
+            (14, 3, 4)
+        );
+        assert_eq!(
+            diagnostic.to_string(),
+            "list.toml:14:3: unknown key `aptt` in `configs[1].install`, did you mean `apt`?\n   |\n14 |   aptt = [\"fd-find\"]\n   |   ^^^^"
+        );
+    }
+
+    #[test]
+    fn char_boundaries() {
+        let content = "a中b";
+        assert_eq!(floor_char_boundary(content, 2), 1);
+        assert_eq!(ceil_char_boundary(content, 2), 4);
+        assert_eq!(floor_char_boundary(content, 4), 4);
+        assert_eq!(floor_char_boundary(content, 10), 5);
+        assert_eq!(ceil_char_boundary(content, 10), 5);
+    }
+}
@@ -0,0 +1,316 @@
+use crate::types::config::{Config, Input, InputType};
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use log::{info, warn};
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+
+/// 非交互运行时提供输入值的应答文件，按配置名分组，如:
+///
+/// ```toml
+/// [git]
+/// email = "me@example.com"
+/// ```
+#[derive(Debug, Default)]
+pub struct Answers {
+    values: HashMap<String, HashMap<String, String>>,
+}
+
+impl Answers {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content =
+            fs::read_to_string(path).context(format!("Fail to read {}", path.to_string_lossy()))?;
+        let tables: HashMap<String, toml::Table> = toml::from_str(&content)
+            .context(format!("File {} is invaild", path.to_string_lossy()))?;
+
+        let mut values = HashMap::new();
+        for (config, table) in tables {
+            let mut answers = HashMap::new();
+            for (name, value) in table {
+                let value = match value {
+                    toml::Value::String(value) => value,
+                    toml::Value::Integer(value) => value.to_string(),
+                    toml::Value::Boolean(value) => value.to_string(),
+                    _ => bail!(
+                        "{config}.{name} in {} must be a string, an integer or a bool",
+                        path.to_string_lossy()
+                    ),
+                };
+                answers.insert(name, value);
+            }
+            values.insert(config, answers);
+        }
+
+        Ok(Self { values })
+    }
+
+    /// 确定`config`中所有输入的值，`saved`是上次部署时保存的值
+    pub fn resolve(
+        &self,
+        config: &Config,
+        saved: Option<&HashMap<String, String>>,
+    ) -> Result<HashMap<String, String>> {
+        let config_name = &config.infomation.name;
+        let mut values = HashMap::new();
+
+        for input in config.inputs.iter().flatten() {
+            let value = self.resolve_input(config_name, input, saved)?;
+            values.insert(input.name.clone(), value);
+        }
+
+        Ok(values)
+    }
+
+    fn resolve_input(
+        &self,
+        config_name: &str,
+        input: &Input,
+        saved: Option<&HashMap<String, String>>,
+    ) -> Result<String> {
+        let env = Input::env_name(config_name, &input.name);
+        let env_value = std::env::var(&env).ok();
+
+        for (source, value) in [
+            (
+                "answers file".to_string(),
+                self.values
+                    .get(config_name)
+                    .and_then(|answers| answers.get(&input.name)),
+            ),
+            (format!("${env}"), env_value.as_ref()),
+        ] {
+            if let Some(value) = value {
+                info!(target: "Input", "{config_name}.{}: use the value from {source}", input.name);
+                return input.normalize(value).context(format!(
+                    "Input {config_name}.{} from {source} is invaild",
+                    input.name
+                ));
+            }
+        }
+
+        /* 配置中的规则可能已经改变，上次的值不合法时重新询问 */
+        if let Some(value) = saved.and_then(|saved| saved.get(&input.name)) {
+            match input.normalize(value) {
+                Ok(value) => {
+                    info!(target: "Input", "{config_name}.{}: use the value from last deployment", input.name);
+                    return Ok(value);
+                }
+                Err(err) => {
+                    warn!(target: "Input", "{config_name}.{}: value from last deployment is invaild, {err}", input.name)
+                }
+            }
+        }
+
+        if std::io::stdin().is_terminal() {
+            return input.prompt(config_name);
+        }
+
+        match &input.default {
+            Some(default) => input.normalize(default),
+            None => Err(anyhow!(
+                "Input {config_name}.{} is required, provide it in the answers file or ${env}",
+                input.name
+            )),
+        }
+    }
+}
+
+impl Input {
+    const NAME_PATTERN: &str = r"^[A-Za-z_][A-Za-z0-9_]*$";
+
+    /// 提供输入值的环境变量名，如`PIONEER_GIT_EMAIL`
+    pub fn env_name(config_name: &str, name: &str) -> String {
+        format!("PIONEER_{config_name}_{name}")
+            .to_uppercase()
+            .replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+    }
+
+    /// 按类型检查并规范化输入的值
+    pub fn normalize(&self, value: &str) -> Result<String> {
+        let value = match self.kind.unwrap_or_default() {
+            InputType::String => value.to_string(),
+            InputType::Bool => match value.trim().to_lowercase().as_str() {
+                "true" | "yes" | "y" => "true".to_string(),
+                "false" | "no" | "n" => "false".to_string(),
+                _ => bail!("{value} is not a bool"),
+            },
+            InputType::Integer => value
+                .trim()
+                .parse::<i64>()
+                .map_err(|_| anyhow!("{value} is not an integer"))?
+                .to_string(),
+        };
+
+        if let Some(pattern) = &self.pattern {
+            let re = Regex::new(&format!("^(?:{pattern})$"))?;
+            ensure!(re.is_match(&value), "{value} does not match {pattern}");
+        }
+
+        Ok(value)
+    }
+
+    fn prompt(&self, config_name: &str) -> Result<String> {
+        loop {
+            eprint!(
+                "{} ({config_name}.{})",
+                self.description.as_ref().unwrap_or(&self.name),
+                self.name
+            );
+            if let Some(default) = &self.default {
+                eprint!(" [{default}]");
+            }
+            eprint!(": ");
+            std::io::stderr().flush()?;
+
+            let mut answer = String::new();
+            if std::io::stdin().read_line(&mut answer)? == 0 {
+                bail!("No value for input {config_name}.{}", self.name);
+            }
+
+            let answer = match (answer.trim(), &self.default) {
+                ("", Some(default)) => default.as_str(),
+                ("", None) => continue,
+                (answer, _) => answer,
+            };
+
+            match self.normalize(answer) {
+                Ok(value) => return Ok(value),
+                Err(err) => warn!(target: "Input", "{err}"),
+            }
+        }
+    }
+
+    /// 静态检查输入的定义
+    pub fn validate(&self) -> Result<()> {
+        ensure!(
+            Regex::new(Self::NAME_PATTERN).unwrap().is_match(&self.name),
+            "Input name {} only allows letters, numbers and underscores(_)",
+            self.name
+        );
+
+        if let Some(pattern) = &self.pattern {
+            Regex::new(pattern).context(format!("{}: invalid pattern {pattern}", self.name))?;
+        }
+
+        if let Some(default) = &self.default {
+            self.normalize(default)
+                .context(format!("{}: default value is invaild", self.name))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    fn input(toml: &str) -> Input {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn config(name: &str, inputs: &str) -> Config {
+        toml::from_str(&format!(
+            "{inputs}\n[infomation]\nname = \"{name}\"\n[install]\n"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn normalize_values() {
+        let flag = input("name = \"flag\"\ntype = \"bool\"");
+        assert_eq!(flag.normalize(" Yes ").unwrap(), "true");
+        assert_eq!(flag.normalize("n").unwrap(), "false");
+        assert!(flag.normalize("maybe").is_err());
+
+        let count = input("name = \"count\"\ntype = \"integer\"");
+        assert_eq!(count.normalize(" 42 ").unwrap(), "42");
+        assert!(count.normalize("4.2").is_err());
+
+        /* 正则表达式需要完整匹配 */
+        let email = input("name = \"email\"\npattern = \".+@.+\"");
+        assert_eq!(email.normalize("me@example.com").unwrap(), "me@example.com");
+        assert!(email.normalize("me").is_err());
+        let digits = input("name = \"digits\"\npattern = \"[0-9]+\"");
+        assert!(digits.normalize("12a").is_err());
+    }
+
+    #[test]
+    fn validate_definitions() {
+        assert!(input("name = \"git_email\"").validate().is_ok());
+        assert!(input("name = \"git-email\"").validate().is_err());
+        assert!(input("name = \"x\"\npattern = \"(\"").validate().is_err());
+        assert!(
+            input("name = \"x\"\ntype = \"integer\"\ndefault = \"ten\"")
+                .validate()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn env_name() {
+        assert_eq!(Input::env_name("my-git", "email"), "PIONEER_MY_GIT_EMAIL");
+    }
+
+    #[test]
+    fn answers_file_then_env_then_saved() {
+        let answers_file = NamedTempFile::new().unwrap();
+        fs::write(
+            answers_file.path(),
+            "[input_precedence]\nfrom_file = \"file\"\nport = 8080\n",
+        )
+        .unwrap();
+        let answers = Answers::from_file(answers_file.path()).unwrap();
+
+        let config = config(
+            "input_precedence",
+            r#"
+[[inputs]]
+name = "from_file"
+[[inputs]]
+name = "port"
+type = "integer"
+[[inputs]]
+name = "from_env"
+[[inputs]]
+name = "from_saved"
+"#,
+        );
+        let saved: HashMap<String, String> = ["from_file", "from_env", "from_saved"]
+            .iter()
+            .map(|name| (name.to_string(), "saved".to_string()))
+            .collect();
+
+        for name in ["from_file", "from_env"] {
+            /* 变量名只在这个测试中使用，不会影响并行运行的其他测试 */
+            unsafe { std::env::set_var(Input::env_name("input_precedence", name), "env") };
+        }
+
+        let values = answers.resolve(&config, Some(&saved)).unwrap();
+        assert_eq!(values["from_file"], "file");
+        assert_eq!(values["port"], "8080");
+        assert_eq!(values["from_env"], "env");
+        assert_eq!(values["from_saved"], "saved");
+    }
+
+    #[test]
+    fn invalid_answer_is_an_error() {
+        let answers_file = NamedTempFile::new().unwrap();
+        fs::write(answers_file.path(), "[invalid_answer]\nport = \"http\"\n").unwrap();
+        let answers = Answers::from_file(answers_file.path()).unwrap();
+        let config = config(
+            "invalid_answer",
+            "[[inputs]]\nname = \"port\"\ntype = \"integer\"",
+        );
+
+        let err = answers.resolve(&config, None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Input invalid_answer.port from answers file is invaild"
+        );
+    }
+}
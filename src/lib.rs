@@ -1,7 +1,14 @@
 pub mod builder;
+pub mod condition;
 pub mod deploy;
+pub mod diagnostic;
+pub mod input;
+pub mod loader;
 pub mod log;
 mod op;
 pub mod program;
+pub mod runner;
+pub mod secret;
+pub mod template;
 pub mod types;
 pub use op::http;
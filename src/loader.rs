@@ -0,0 +1,875 @@
+use crate::diagnostic::{Diagnostic, UnknownKey};
+use crate::types::config::{
+    AptPackage, CommandEntry, Completion, Config, ConfigList, Content, DebEntry, EnvrcEntry,
+    FileEntry, InstallList, PackageName, StringOr,
+};
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use log::info;
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+
+/// 读取配置文件，并展开其中的`extends`和`include`
+///
+/// 合并顺序为: 基础配置、include的配置(按顺序)、当前配置，后合并的优先。各字段的合并规则:
+/// - 软件包列表取并集，同名的软件包以后合并的为准
+/// - 映射表(别名、命令、环境变量、文件等)按键覆盖
+/// - envrc按顺序拼接
+/// - 自定义变量按变量名覆盖，输入按输入名合并
+///
+/// 被引入的配置中的相对文件路径会转换为相对于它自身所在目录的绝对路径
+///
+/// 一个文件可以只有一个配置，也可以用`[[configs]]`写多个配置，多个配置的文件不能被引用
+///
+/// `strict`时配置中不认识的字段(通常是拼错的字段)会报错，否则忽略它们
+pub struct ConfigLoader {
+    /// 正在加载的配置文件链，用于检测循环引用和报告出处
+    chain: Vec<PathBuf>,
+    strict: bool,
+}
+
+impl ConfigLoader {
+    pub fn load<P: AsRef<Path>>(path: P, strict: bool) -> Result<Config> {
+        let mut loader = Self {
+            chain: Vec::new(),
+            strict,
+        };
+        loader.load_file(path.as_ref())
+    }
+
+    /// 读取文件中的所有配置，单个配置的文件返回只有一个元素的列表
+    pub fn load_list<P: AsRef<Path>>(path: P, strict: bool) -> Result<Vec<Config>> {
+        let path = path.as_ref();
+        let configs = match ConfigFile::read(path, strict)? {
+            ConfigFile::Single(_) => return Ok(vec![Self::load(path, strict)?]),
+            ConfigFile::List(list) => list.configs,
+        };
+
+        let canonical =
+            fs::canonicalize(path).context(format!("Fail to read {}", path.to_string_lossy()))?;
+        let mut loader = Self {
+            chain: vec![canonical],
+            strict,
+        };
+
+        let mut names = HashSet::new();
+        let mut loaded = Vec::new();
+
+        for config in configs {
+            let name = config.infomation.name.clone();
+            ensure!(
+                names.insert(name.clone()),
+                "Config {name} is defined more than once in {}",
+                path.to_string_lossy()
+            );
+
+            let config = match config.extends.is_none() && config.include.is_none() {
+                true => config,
+                false => loader.expand(path, config).context(format!(
+                    "Fail to load config {name} in {}",
+                    path.to_string_lossy()
+                ))?,
+            };
+            loaded.push(config);
+        }
+
+        Ok(loaded)
+    }
+
+    /// 只保留`only`中的配置和它们`requires`的配置，`only`为空时保留全部
+    pub fn select<T, F>(items: Vec<T>, only: &[String], config: F) -> Result<Vec<T>>
+    where
+        F: Fn(&T) -> &Config,
+    {
+        if only.is_empty() {
+            return Ok(items);
+        }
+
+        let mut selected: Vec<&str> = Vec::new();
+        let mut pending: Vec<&str> = only.iter().map(String::as_str).collect();
+
+        while let Some(name) = pending.pop() {
+            if selected.contains(&name) {
+                continue;
+            }
+
+            let requested = only.iter().any(|only| only == name);
+            let found = items
+                .iter()
+                .map(&config)
+                .find(|config| config.infomation.name == name);
+
+            /* 找不到被依赖的配置时，由排序时报告 */
+            let info = match found {
+                Some(config) => &config.infomation,
+                None if requested => bail!("Con not find config: {name}"),
+                None => continue,
+            };
+
+            if !requested {
+                info!(target: "Loader", "Select {name}, which is required by other selected configs");
+            }
+
+            selected.push(name);
+            pending.extend(info.requires.iter().flatten().map(String::as_str));
+        }
+
+        let selected: HashSet<String> = selected.into_iter().map(str::to_string).collect();
+
+        Ok(items
+            .into_iter()
+            .filter(|item| selected.contains(&config(item).infomation.name))
+            .collect())
+    }
+
+    fn load_file(&mut self, path: &Path) -> Result<Config> {
+        let canonical =
+            fs::canonicalize(path).context(format!("Fail to read {}", path.to_string_lossy()))?;
+
+        if let Some(start) = self.chain.iter().position(|file| *file == canonical) {
+            bail!(
+                "Config include cycle: {} -> {}",
+                self.chain[start..]
+                    .iter()
+                    .map(|file| file.to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join(" -> "),
+                canonical.to_string_lossy()
+            );
+        }
+
+        let config = match ConfigFile::read(path, self.strict)? {
+            ConfigFile::Single(config) => *config,
+            ConfigFile::List(_) => bail!(
+                "{} holds a list of configs, which can not be extended or included",
+                path.to_string_lossy()
+            ),
+        };
+
+        if config.extends.is_none() && config.include.is_none() {
+            return Ok(config);
+        }
+
+        self.chain.push(canonical);
+        let merged = self.expand(path, config);
+        self.chain.pop();
+
+        merged
+    }
+
+    fn expand(&mut self, path: &Path, config: Config) -> Result<Config> {
+        let config_dir = path.parent().unwrap_or(Path::new("."));
+        let mut install = InstallList::default();
+        let mut vars = None;
+        let mut inputs = None;
+        let mut infomation = config.infomation;
+
+        if let Some(base) = &config.extends {
+            let base_file = Self::extends_file(config_dir, base);
+            let base = self.load_nested(&base_file, config_dir).context(format!(
+                "Fail to load {} extended by {}",
+                base_file.to_string_lossy(),
+                path.to_string_lossy()
+            ))?;
+
+            info!(target: "Loader", "{} extends {}", path.to_string_lossy(), base_file.to_string_lossy());
+
+            infomation.description = infomation.description.or(base.infomation.description);
+            infomation.install_while = infomation.install_while.or(base.infomation.install_while);
+            infomation.when = infomation.when.or(base.infomation.when);
+            infomation.requires = infomation.requires.or(base.infomation.requires);
+            override_by_key(&mut vars, base.vars);
+            union_by(&mut inputs, base.inputs, |input| input.name.clone());
+            install.merge(base.install);
+        }
+
+        for include in config.include.iter().flatten() {
+            let include_file = config_dir.join(include);
+            let included = self
+                .load_nested(&include_file, config_dir)
+                .context(format!(
+                    "Fail to load {} included by {}",
+                    include_file.to_string_lossy(),
+                    path.to_string_lossy()
+                ))?;
+
+            info!(target: "Loader", "{} includes {}", path.to_string_lossy(), include_file.to_string_lossy());
+            override_by_key(&mut vars, included.vars);
+            union_by(&mut inputs, included.inputs, |input| input.name.clone());
+            install.merge(included.install);
+        }
+
+        override_by_key(&mut vars, config.vars);
+        union_by(&mut inputs, config.inputs, |input| input.name.clone());
+        install.merge(config.install);
+
+        Ok(Config {
+            extends: None,
+            include: None,
+            vars,
+            inputs,
+            infomation,
+            install,
+        })
+    }
+
+    /// 加载被引用的配置，并把其中的相对路径转换为相对于引用者目录`config_dir`的路径
+    ///
+    /// 逐层转换后，展开的配置中的路径都相对于最外层的配置文件，打包到安装脚本时不会带上本机的绝对路径
+    fn load_nested(&mut self, path: &Path, config_dir: &Path) -> Result<Config> {
+        let mut config = self.load_file(path)?;
+        let nested_dir = path
+            .strip_prefix(config_dir)
+            .unwrap_or(path)
+            .parent()
+            .unwrap_or(Path::new(""));
+
+        config.install.rebase(nested_dir);
+        Ok(config)
+    }
+
+    /// `extends = "base"`指向同目录下的`base.toml`，没有toml文件时依次查找其他格式
+    fn extends_file(config_dir: &Path, base: &str) -> PathBuf {
+        let file = config_dir.join(base);
+
+        if file.extension().is_some() {
+            return file;
+        }
+
+        ConfigFormat::EXTENSIONS
+            .iter()
+            .map(|ext| file.with_extension(ext))
+            .find(|file| file.exists())
+            .unwrap_or_else(|| file.with_extension("toml"))
+    }
+
+    /// 用于打包到安装脚本的配置内容，`configs`是从这个文件中加载并选中的配置
+    ///
+    /// 没有引用其他配置且全部选中时保留原文件内容，否则按原格式序列化展开后的配置
+    pub fn embed<P: AsRef<Path>>(path: P, configs: &[Config]) -> Result<String> {
+        let path = path.as_ref();
+        let format = ConfigFormat::from_path(path)?;
+        let content =
+            fs::read_to_string(path).context(format!("Fail to read {}", path.to_string_lossy()))?;
+
+        let unchanged = |raw: &[&Config]| {
+            raw.len() == configs.len()
+                && raw
+                    .iter()
+                    .all(|config| config.extends.is_none() && config.include.is_none())
+        };
+
+        /* 配置已经加载过，这里不再检查字段 */
+        match ConfigFile::read(path, false)? {
+            ConfigFile::Single(raw) if unchanged(&[&raw]) => Ok(content),
+            ConfigFile::List(raw) if unchanged(&raw.configs.iter().collect::<Vec<_>>()) => {
+                Ok(content)
+            }
+            ConfigFile::Single(_) => format.to_string(&configs[0]),
+            ConfigFile::List(_) => format.to_string(&ConfigList {
+                configs: configs.to_vec(),
+            }),
+        }
+    }
+}
+
+/// 配置文件中的内容，根据是否有`configs`字段区分
+enum ConfigFile {
+    Single(Box<Config>),
+    List(ConfigList),
+}
+
+impl ConfigFile {
+    fn read(path: &Path, strict: bool) -> Result<Self> {
+        /* 只检查是否有configs字段，其他字段会被忽略 */
+        #[derive(Deserialize)]
+        struct Probe {
+            configs: Option<IgnoredAny>,
+        }
+
+        let content =
+            fs::read_to_string(path).context(format!("Fail to read {}", path.to_string_lossy()))?;
+        let format = ConfigFormat::from_path(path)?;
+
+        let probe: Probe = Self::parse(path, format, &content)?;
+        let file = match probe.configs {
+            Some(_) => Self::List(Self::parse(path, format, &content)?),
+            None => Self::Single(Box::new(Self::parse(path, format, &content)?)),
+        };
+
+        if strict {
+            file.check_unknown_keys(path, format, &content)?;
+        }
+
+        Ok(file)
+    }
+
+    /// 能定位到出错位置时报告位置和出错的那一行
+    fn parse<T: DeserializeOwned>(path: &Path, format: ConfigFormat, content: &str) -> Result<T> {
+        format.parse(content).map_err(|err| {
+            match Diagnostic::from_parse_error(path, content, &err) {
+                Some(diagnostic) => anyhow!("{diagnostic}"),
+                None => err,
+            }
+            .context(format!("File {} is invaild", path.to_string_lossy()))
+        })
+    }
+
+    /// 重新序列化后没有出现的字段就是不认识的字段
+    fn check_unknown_keys(&self, path: &Path, format: ConfigFormat, content: &str) -> Result<()> {
+        let original: serde_jsonc::Value = format.parse(content)?;
+        let known = match self {
+            Self::Single(config) => serde_jsonc::to_value(config)?,
+            Self::List(list) => serde_jsonc::to_value(list)?,
+        };
+
+        let diagnostics: Vec<String> = UnknownKey::find(&original, &known)
+            .iter()
+            .map(|key| key.diagnose(format, path, content).to_string())
+            .collect();
+
+        ensure!(
+            diagnostics.is_empty(),
+            "{}\n\nFile {} has unknown keys, fix them or pass --lenient to ignore them",
+            diagnostics.join("\n\n"),
+            path.to_string_lossy()
+        );
+
+        Ok(())
+    }
+}
+
+/// 配置文件的格式，根据扩展名区分
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+    Json,
+    /// 带注释的json
+    Jsonc,
+}
+
+impl ConfigFormat {
+    pub const EXTENSIONS: [&str; 5] = ["toml", "yaml", "yml", "json", "jsonc"];
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+
+        match extension.as_str() {
+            "toml" => Ok(Self::Toml),
+            "yaml" | "yml" => Ok(Self::Yaml),
+            "json" => Ok(Self::Json),
+            "jsonc" => Ok(Self::Jsonc),
+            _ => Err(anyhow!(
+                "{} is not a config file, supported extensions: {}",
+                path.to_string_lossy(),
+                Self::EXTENSIONS.join(", ")
+            )),
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Toml => "toml",
+            Self::Yaml => "yaml",
+            Self::Json => "json",
+            Self::Jsonc => "jsonc",
+        }
+    }
+
+    pub fn parse<T: DeserializeOwned>(&self, content: &str) -> Result<T> {
+        Ok(match self {
+            Self::Toml => toml::from_str(content)?,
+            Self::Yaml => serde_yaml::from_str(content)?,
+            /* json是jsonc的子集 */
+            Self::Json | Self::Jsonc => serde_jsonc::from_str(content)?,
+        })
+    }
+
+    pub fn to_string<T: Serialize>(&self, value: &T) -> Result<String> {
+        if *self == Self::Toml {
+            return Ok(toml::to_string_pretty(value)?);
+        }
+
+        /* toml会忽略None，yaml和json会输出null，去掉这些字段 */
+        let mut value = serde_jsonc::to_value(value)?;
+        Self::strip_null(&mut value);
+
+        Ok(match self {
+            Self::Yaml => serde_yaml::to_string(&value)?,
+            _ => serde_jsonc::to_string_pretty(&value)?,
+        })
+    }
+
+    fn strip_null(value: &mut serde_jsonc::Value) {
+        match value {
+            serde_jsonc::Value::Object(map) => {
+                map.retain(|_, value| !value.is_null());
+                map.values_mut().for_each(Self::strip_null);
+            }
+            serde_jsonc::Value::Array(list) => list.iter_mut().for_each(Self::strip_null),
+            _ => {}
+        }
+    }
+}
+
+impl InstallList {
+    /// 合并`other`，`other`中的内容优先
+    fn merge(&mut self, other: InstallList) {
+        union_by(&mut self.packages, other.packages, |package| {
+            PackageName::from(package).name
+        });
+        union_by(&mut self.apt, other.apt, |package| {
+            AptPackage::from(package).name
+        });
+        union_by(&mut self.deb, other.deb, |deb| deb.content().clone());
+        for (list, other) in [
+            (&mut self.dnf, other.dnf),
+            (&mut self.pacman, other.pacman),
+            (&mut self.apk, other.apk),
+            (&mut self.zypper, other.zypper),
+        ] {
+            union_by(list, other, |package| PackageName::from(package).name);
+        }
+
+        override_by_key(&mut self.apt_repo, other.apt_repo);
+        override_by_key(&mut self.debconf, other.debconf);
+        override_by_key(&mut self.alias, other.alias);
+        override_by_key(&mut self.command, other.command);
+        override_by_key(&mut self.env, other.env);
+        override_by_key(&mut self.files, other.files);
+
+        if let Some(envrc) = other.envrc {
+            self.envrc.get_or_insert_default().extend(envrc);
+        }
+    }
+
+    /// 把所有相对的文件路径转换为基于`dir`的路径
+    fn rebase(&mut self, dir: &Path) {
+        let rebase = |content: &mut Content| {
+            if let Content::File(path) = content {
+                *path = dir.join(&path);
+            }
+        };
+        let rebase_or = |content: &mut StringOr<Content>| {
+            if let StringOr::Object(content) = content {
+                rebase(content);
+            }
+        };
+
+        for repo in self.apt_repo.iter_mut().flat_map(HashMap::values_mut) {
+            rebase(&mut repo.key);
+        }
+
+        for entry in self.deb.iter_mut().flatten() {
+            match entry {
+                DebEntry::Content(content) => rebase(content),
+                DebEntry::Spec(spec) => rebase(&mut spec.content),
+            }
+        }
+
+        for entry in self.command.iter_mut().flat_map(HashMap::values_mut) {
+            match entry {
+                CommandEntry::Content(content) => rebase_or(content),
+                CommandEntry::Spec(spec) => {
+                    rebase_or(&mut spec.content);
+                    if let Some(Completion::Script(script)) = &mut spec.completion {
+                        rebase_or(script);
+                    }
+                }
+            }
+        }
+
+        for entry in self.envrc.iter_mut().flatten() {
+            match entry {
+                EnvrcEntry::Content(content) => rebase(content),
+                EnvrcEntry::Spec(spec) => rebase(&mut spec.content),
+            }
+        }
+
+        for entry in self.files.iter_mut().flat_map(HashMap::values_mut) {
+            match entry {
+                FileEntry::Content(content) => rebase_or(content),
+                FileEntry::Spec(spec) => rebase_or(&mut spec.content),
+            }
+        }
+    }
+}
+
+/// 列表取并集，`key`相同的元素用`other`中的替换
+fn union_by<T, K, F>(list: &mut Option<Vec<T>>, other: Option<Vec<T>>, key: F)
+where
+    K: PartialEq,
+    F: Fn(&T) -> K,
+{
+    let Some(other) = other else {
+        return;
+    };
+
+    let list = list.get_or_insert_default();
+    for item in other {
+        match list.iter().position(|exist| key(exist) == key(&item)) {
+            Some(index) => list[index] = item,
+            None => list.push(item),
+        }
+    }
+}
+
+fn override_by_key<K, V>(map: &mut Option<HashMap<K, V>>, other: Option<HashMap<K, V>>)
+where
+    K: Eq + Hash,
+{
+    if let Some(other) = other {
+        map.get_or_insert_default().extend(other);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn write(dir: &Path, file: &str, content: &str) -> PathBuf {
+        let path = dir.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn file_content(config: &Config, name: &str) -> StringOr<Content> {
+        config.install.files.as_ref().unwrap()[Path::new(name)]
+            .content()
+            .clone()
+    }
+
+    #[test]
+    fn paths_are_relative_to_the_root_config() {
+        let dir = TempDir::new().unwrap();
+        let main = write(
+            dir.path(),
+            "main.toml",
+            "include = [\"sub/a.toml\"]\n[infomation]\nname = \"main\"\n[install]\n",
+        );
+        write(
+            dir.path(),
+            "sub/a.toml",
+            r#"
+include = ["deeper/b.toml"]
+[infomation]
+name = "a"
+[install.files]
+"a.conf" = { file = "a.txt" }
+"#,
+        );
+        write(
+            dir.path(),
+            "sub/deeper/b.toml",
+            r#"
+[infomation]
+name = "b"
+[install]
+envrc = [{ file = "b.sh" }]
+[install.files]
+"b.conf" = { file = "b.txt" }
+"#,
+        );
+
+        let config = ConfigLoader::load(&main, true).unwrap();
+
+        assert_eq!(
+            file_content(&config, "a.conf"),
+            StringOr::Object(Content::File("sub/a.txt".into()))
+        );
+        assert_eq!(
+            file_content(&config, "b.conf"),
+            StringOr::Object(Content::File("sub/deeper/b.txt".into()))
+        );
+        assert_eq!(
+            config.install.envrc.unwrap()[0].content(),
+            &Content::File("sub/deeper/b.sh".into())
+        );
+    }
+
+    #[test]
+    fn include_cycle_is_an_error() {
+        let dir = TempDir::new().unwrap();
+        let a = write(
+            dir.path(),
+            "a.toml",
+            "include = [\"b.toml\"]\n[infomation]\nname = \"a\"\n[install]\n",
+        );
+        write(
+            dir.path(),
+            "b.toml",
+            "extends = \"a\"\n[infomation]\nname = \"b\"\n[install]\n",
+        );
+
+        let err = ConfigLoader::load(&a, true).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("Config include cycle"),
+            "{err:#}"
+        );
+    }
+
+    #[test]
+    fn later_configs_take_precedence() {
+        let dir = TempDir::new().unwrap();
+        write(
+            dir.path(),
+            "base.toml",
+            r#"
+[infomation]
+name = "base"
+description = "from base"
+[install]
+apt = ["tree", { name = "vim", hold = true }]
+envrc = [{ raw = "base" }]
+[install.alias]
+a = "base"
+b = "base"
+c = "base"
+"#,
+        );
+        write(
+            dir.path(),
+            "extra.toml",
+            r#"
+[infomation]
+name = "extra"
+[install]
+apt = ["fd-find"]
+envrc = [{ raw = "extra" }]
+[install.alias]
+b = "extra"
+c = "extra"
+"#,
+        );
+        let main = write(
+            dir.path(),
+            "main.toml",
+            r#"
+extends = "base"
+include = ["extra.toml"]
+[infomation]
+name = "main"
+[install]
+apt = ["vim"]
+envrc = [{ raw = "main" }]
+[install.alias]
+c = "main"
+"#,
+        );
+
+        let config = ConfigLoader::load(&main, true).unwrap();
+
+        assert_eq!(config.infomation.name, "main");
+        assert_eq!(config.infomation.description.as_deref(), Some("from base"));
+        assert!(config.extends.is_none() && config.include.is_none());
+
+        let alias = config.install.alias.unwrap();
+        for (name, from) in [("a", "base"), ("b", "extra"), ("c", "main")] {
+            assert_eq!(alias[name], StringOr::String(from.to_string()), "{name}");
+        }
+
+        /* 同名的软件包以后合并的为准，位置不变 */
+        assert_eq!(
+            config.install.apt.unwrap(),
+            vec![
+                StringOr::String("tree".into()),
+                StringOr::String("vim".into()),
+                StringOr::String("fd-find".into()),
+            ]
+        );
+
+        let envrc: Vec<_> = config
+            .install
+            .envrc
+            .unwrap()
+            .iter()
+            .map(|rc| rc.content().clone())
+            .collect();
+        assert_eq!(
+            envrc,
+            ["base", "extra", "main"].map(|raw| Content::Raw(raw.into()))
+        );
+    }
+
+    const LIST: &str = r#"
+[[configs]]
+infomation = { name = "a" }
+install = {}
+
+[[configs]]
+infomation = { name = "b", requires = ["a"] }
+install = {}
+
+[[configs]]
+infomation = { name = "c", requires = ["b"] }
+install = {}
+
+[[configs]]
+infomation = { name = "d" }
+install = {}
+"#;
+
+    fn names(configs: &[Config]) -> Vec<&str> {
+        configs
+            .iter()
+            .map(|config| config.infomation.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn selected_configs_bring_their_requires() {
+        let dir = TempDir::new().unwrap();
+        let list = write(dir.path(), "list.toml", LIST);
+        let configs = ConfigLoader::load_list(&list, true).unwrap();
+        let select = |only: &[&str]| {
+            let only: Vec<String> = only.iter().map(ToString::to_string).collect();
+            ConfigLoader::select(configs.clone(), &only, |config| config)
+        };
+
+        assert_eq!(names(&select(&["c"]).unwrap()), ["a", "b", "c"]);
+        assert_eq!(names(&select(&["d", "b"]).unwrap()), ["a", "b", "d"]);
+        assert_eq!(names(&select(&[]).unwrap()), ["a", "b", "c", "d"]);
+        assert_eq!(
+            select(&["x"]).unwrap_err().to_string(),
+            "Con not find config: x"
+        );
+    }
+
+    #[test]
+    fn configs_in_a_list_are_expanded() {
+        let dir = TempDir::new().unwrap();
+        write(
+            dir.path(),
+            "base.toml",
+            "[infomation]\nname = \"base\"\n[install]\napt = [\"tree\"]\n",
+        );
+        let list = write(
+            dir.path(),
+            "list.toml",
+            r#"
+[[configs]]
+extends = "base"
+infomation.name = "extended"
+install.apt = ["vim"]
+
+[[configs]]
+infomation.name = "plain"
+install.apt = ["jq"]
+"#,
+        );
+
+        let configs = ConfigLoader::load_list(&list, true).unwrap();
+
+        assert_eq!(names(&configs), ["extended", "plain"]);
+        assert_eq!(
+            configs[0].install.apt.clone().unwrap(),
+            ["tree", "vim"].map(|name| StringOr::String(name.into()))
+        );
+        assert!(configs[0].extends.is_none());
+
+        let duplicated = write(
+            dir.path(),
+            "duplicated.toml",
+            "[[configs]]\ninfomation.name = \"a\"\ninstall = {}\n[[configs]]\ninfomation.name = \"a\"\ninstall = {}\n",
+        );
+        let err = ConfigLoader::load_list(&duplicated, true).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("Config a is defined more than once")
+        );
+    }
+
+    #[test]
+    fn embedded_list_loads_the_same_configs() {
+        let dir = TempDir::new().unwrap();
+        write(
+            dir.path(),
+            "base.toml",
+            "[infomation]\nname = \"base\"\n[install]\napt = [\"tree\"]\n",
+        );
+        let content = LIST.replacen("[[configs]]\n", "[[configs]]\nextends = \"base\"\n", 1);
+        let list = write(dir.path(), "list.toml", &content);
+        let configs = ConfigLoader::load_list(&list, true).unwrap();
+
+        /* 有引用或只选中部分配置时序列化展开后的配置 */
+        let selected =
+            ConfigLoader::select(configs.clone(), &["b".into()], |config| config).unwrap();
+        for chosen in [&configs, &selected] {
+            let content = ConfigLoader::embed(&list, chosen).unwrap();
+            assert!(!content.contains("extends"), "{content}");
+            let embedded = write(dir.path(), "embedded/list.toml", &content);
+            let reloaded = ConfigLoader::load_list(&embedded, true).unwrap();
+            assert_eq!(
+                serde_jsonc::to_value(&reloaded).unwrap(),
+                serde_jsonc::to_value(chosen).unwrap()
+            );
+        }
+
+        /* 没有引用且全部选中时保留原文件内容 */
+        let plain = write(dir.path(), "plain.toml", LIST);
+        let configs = ConfigLoader::load_list(&plain, true).unwrap();
+        assert_eq!(ConfigLoader::embed(&plain, &configs).unwrap(), LIST);
+    }
+
+    /// 同一个配置的各种格式，`{key}`处替换为一个字段名
+    const FORMATS: [(&str, &str); 4] = [
+        (
+            "demo.toml",
+            "[infomation]\nname = \"demo\"\n\n[install]\n{key} = [\"tree\"]\n\n[install.alias]\nll = \"ls -l\"\n",
+        ),
+        (
+            "demo.yaml",
+            "infomation:\n  name: demo\ninstall:\n  {key}:\n    - tree\n  alias:\n    ll: ls -l\n",
+        ),
+        (
+            "demo.json",
+            r#"{"infomation": {"name": "demo"}, "install": {"{key}": ["tree"], "alias": {"ll": "ls -l"}}}"#,
+        ),
+        (
+            "demo.jsonc",
+            "{\n  // 注释\n  \"infomation\": {\"name\": \"demo\"},\n  \"install\": {\"{key}\": [\"tree\"], \"alias\": {\"ll\": \"ls -l\"}}\n}\n",
+        ),
+    ];
+
+    #[test]
+    fn all_formats_load_the_same_config() {
+        let dir = TempDir::new().unwrap();
+
+        let configs: Vec<_> = FORMATS
+            .iter()
+            .map(|(file, content)| {
+                let path = write(dir.path(), file, &content.replace("{key}", "apt"));
+                serde_jsonc::to_value(ConfigLoader::load(&path, true).unwrap()).unwrap()
+            })
+            .collect();
+
+        assert_eq!(configs[0]["install"]["apt"][0], "tree");
+        assert!(configs.iter().all(|config| *config == configs[0]));
+    }
+
+    #[test]
+    fn unknown_keys_are_found_in_all_formats() {
+        let dir = TempDir::new().unwrap();
+
+        for (file, content) in FORMATS {
+            let path = write(dir.path(), file, &content.replace("{key}", "atp"));
+
+            let err = format!("{:#}", ConfigLoader::load(&path, true).unwrap_err());
+            assert!(
+                err.contains("unknown key `atp` in `install`, did you mean `apt`?"),
+                "{file}: {err}"
+            );
+            assert!(ConfigLoader::load(&path, false).is_ok(), "{file}");
+        }
+    }
+}
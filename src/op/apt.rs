@@ -1,41 +1,279 @@
-use crate::program::run_or_sudo;
-use anyhow::{Context, Ok, Result, anyhow};
-use lazy_static::lazy_static;
-use std::collections::HashSet;
+use crate::op::bundle::AptBundle;
+use crate::op::dpkg::DpkgLock;
+use crate::op::file::FileOp;
+use crate::op::package::PackageManager;
+use crate::runner::CommandRunner;
+use crate::types::config::AptPackage;
+use anyhow::{Context, Ok, Result, anyhow, ensure};
+use glob::Pattern;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::ffi::OsStr;
-use std::fmt::Debug;
-use std::path::PathBuf;
+use std::fmt::{Debug, Display};
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::sync::Mutex;
-use which::which;
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tempfile::NamedTempFile;
 
-type AptIst = Mutex<Option<AptOp>>;
+pub struct AptOp {
+    runner: Rc<dyn CommandRunner>,
+    apt_path: PathBuf,
+    native_arch: String,
+    /// 等待dpkg锁的最长时间
+    lock_timeout: Duration,
+    /// 已添加为软件源的离线软件包目录
+    bundle: Option<PathBuf>,
+    /// 已安装的软件包及其版本，同时以`pkg`和`pkg:arch`为键
+    installed: HashMap<String, String>,
+    queued: Vec<QueuedPackage>,
+    preseeded: Vec<DebconfSelection>,
+}
 
-lazy_static! {
-    static ref APT: AptIst = Default::default();
+/// 软件包在当前系统中的状态
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AptStatus {
+    /// 已安装，附带已安装的版本
+    Installed(String),
+    /// 可以安装，附带候选版本
+    Available(String),
+    /// 虚包，附带提供它的软件包
+    Virtual(Vec<String>),
+    NotFound,
 }
 
-pub struct AptOp {
-    apt_path: PathBuf,
-    list: HashSet<String>,
+impl Display for AptStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AptStatus::Installed(version) => write!(f, "already installed (version {version})"),
+            AptStatus::Available(version) => write!(f, "available (version {version})"),
+            AptStatus::Virtual(providers) => {
+                write!(f, "virtual, provided by {}", providers.join(", "))
+            }
+            AptStatus::NotFound => write!(f, "not found"),
+        }
+    }
+}
+
+/// 等待安装的软件包
+#[derive(Debug, Clone)]
+struct QueuedPackage {
+    package: AptPackage,
+    version: Option<String>,
+    /// 已经安装了需要的版本，只需要处理hold
+    present: bool,
+}
+
+impl QueuedPackage {
+    fn same_transaction(&self, other: &Self) -> bool {
+        self.package.no_install_recommends.unwrap_or_default()
+            == other.package.no_install_recommends.unwrap_or_default()
+            && self.package.target_release == other.package.target_release
+    }
 }
 
-#[allow(unused)]
 impl AptOp {
-    pub fn try_get() -> Result<&'static AptIst> {
-        let mut apt = APT.lock().unwrap();
+    const UPDATE_STAMP: &str = "/var/lib/apt/periodic/update-success-stamp";
+    const LISTS_DIR: &str = "/var/lib/apt/lists";
+    const DPKG_STATUS: &str = "/var/lib/dpkg/status";
+    /// 软件包列表在这个时间内更新过则认为是新的，不需要再apt update
+    const FRESH_FOR: Duration = Duration::from_secs(24 * 60 * 60);
+    /// 默认等待dpkg锁的时间，足够`unattended-upgrades`完成一次升级
+    pub const LOCK_TIMEOUT: Duration = Duration::from_secs(300);
+
+    pub fn new(options: AptOptions, runner: Rc<dyn CommandRunner>) -> Result<Self> {
+        let AptOptions {
+            refresh: policy,
+            lock_timeout,
+            bundle,
+        } = options;
+
+        let mut native_arch = Command::new("dpkg");
+        native_arch.arg("--print-architecture");
+
+        let mut apt = Self {
+            apt_path: runner.which("apt")?,
+            native_arch: runner
+                .run(native_arch)
+                .context("Fail to get dpkg architecture")?
+                .trim()
+                .to_string(),
+            lock_timeout,
+            bundle: None,
+            installed: Default::default(),
+            queued: Default::default(),
+            preseeded: Default::default(),
+            runner,
+        };
 
-        if apt.is_none() {
-            *apt = Some(Self {
-                apt_path: which("apt")?,
-                list: Default::default(),
-            });
+        apt.recover_dpkg()?;
 
-            let apt = apt.as_mut().unwrap();
-            apt.update().context("apt update fail")?;
-            apt.get_list().context("apt list fail")?;
+        if let Some(bundle) = bundle {
+            apt.add_bundle(&bundle).context(format!(
+                "Fail to add apt bundle {}",
+                bundle.to_string_lossy()
+            ))?;
         }
-        Ok(&APT)
+
+        let update = match policy {
+            RefreshPolicy::Always => true,
+            RefreshPolicy::Never => false,
+            RefreshPolicy::Auto => !apt.lists_fresh(),
+        };
+
+        if update {
+            match apt.update() {
+                /* 离线机器上其他软件源无法更新，只使用bundle中的软件包 */
+                Err(err) if apt.bundle.is_some() => {
+                    warn!(target: "APT", "apt update fail, only use packages in bundle: {err:#}")
+                }
+                result => result.context("apt update fail")?,
+            }
+        } else if policy == RefreshPolicy::Never {
+            info!(target: "APT", "Skip apt update");
+        } else {
+            info!(target: "APT", "Package lists are fresh, skip apt update");
+        }
+
+        apt.load_installed()
+            .context("Fail to get installed packages")?;
+
+        Ok(apt)
+    }
+
+    /// 把离线软件包目录添加为优先的软件源，并且只更新这个软件源
+    fn add_bundle(&mut self, dir: &Path) -> Result<()> {
+        let dir = fs::canonicalize(dir)?;
+        info!(target: "APT", "Using offline packages in {}...", dir.to_string_lossy());
+
+        let sources = NamedTempFile::new()?;
+        fs::write(sources.path(), AptBundle::sources(&dir))?;
+        FileOp::install_as_root(
+            self.runner.as_ref(),
+            sources.path(),
+            AptBundle::SOURCES_FILE,
+            0o644,
+        )?;
+
+        let preferences = NamedTempFile::new()?;
+        fs::write(preferences.path(), AptBundle::preferences())?;
+        FileOp::install_as_root(
+            self.runner.as_ref(),
+            preferences.path(),
+            AptBundle::PREFERENCES_FILE,
+            0o644,
+        )?;
+        self.bundle = Some(dir);
+
+        let mut cmd = Command::new("apt-get");
+        cmd.arg("update")
+            .arg("-o")
+            .arg(format!("Dir::Etc::SourceList={}", AptBundle::SOURCES_FILE))
+            .arg("-o")
+            .arg("Dir::Etc::SourceParts=-")
+            .arg("-o")
+            .arg("APT::Get::List-Cleanup=0");
+
+        self.run_locked(cmd)
+            .context("Fail to update bundle package list")?;
+        Ok(())
+    }
+
+    /// bundle目录只在安装过程中存在，安装完成后移除指向它的软件源
+    fn remove_bundle(&mut self) -> Result<()> {
+        if self.bundle.take().is_none() {
+            return Ok(());
+        }
+
+        let mut cmd = Command::new("rm");
+        cmd.arg("-f")
+            .arg(AptBundle::SOURCES_FILE)
+            .arg(AptBundle::PREFERENCES_FILE);
+
+        self.runner
+            .run_as_root(cmd)
+            .context("Fail to remove apt bundle source")?;
+        Ok(())
+    }
+
+    /// dpkg被中断时询问并运行`dpkg --configure -a`，否则apt会拒绝安装任何软件包
+    fn recover_dpkg(&self) -> Result<()> {
+        if let Some(cmd) = DpkgLock::recovery(self.runner.as_ref())? {
+            info!(target: "APT", "Recovering interrupted dpkg...");
+            DpkgLock::wait(
+                self.runner.as_ref(),
+                Instant::now() + self.lock_timeout,
+                self.lock_timeout,
+            )?;
+            self.runner
+                .run_as_root(cmd)
+                .context("Fail to run dpkg --configure -a")?;
+        }
+
+        Ok(())
+    }
+
+    /// 运行需要dpkg锁的命令，锁被其他进程占用时等待其释放后再运行
+    fn run_locked(&self, cmd: Command) -> Result<String> {
+        let deadline = Instant::now() + self.lock_timeout;
+        loop {
+            DpkgLock::wait(self.runner.as_ref(), deadline, self.lock_timeout)?;
+
+            /* 检查和运行之间锁可能又被抢占，此时重试 */
+            match self.runner.run_or_sudo(Self::copy_command(&cmd)) {
+                Err(err) if DpkgLock::is_lock_error(&err) && Instant::now() < deadline => {
+                    info!(target: "APT", "dpkg lock was taken by another process, retrying...");
+                    thread::sleep(Duration::from_secs(1));
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn copy_command(cmd: &Command) -> Command {
+        let mut copy = Command::new(cmd.get_program());
+        copy.args(cmd.get_args());
+
+        for (key, value) in cmd.get_envs() {
+            match value {
+                Some(value) => copy.env(key, value),
+                None => copy.env_remove(key),
+            };
+        }
+
+        copy
+    }
+
+    /// 根据软件包列表最后一次更新的时间判断是否足够新
+    fn lists_fresh(&self) -> bool {
+        self.runner
+            .apt_lists_updated()
+            .and_then(|updated| updated.elapsed().ok())
+            .is_some_and(|age| age < Self::FRESH_FOR)
+    }
+
+    /// update-success-stamp和软件包列表中最新的修改时间
+    pub fn lists_updated() -> Option<SystemTime> {
+        let modified = |path: &Path| fs::metadata(path).and_then(|meta| meta.modified()).ok();
+
+        let lists = fs::read_dir(Self::LISTS_DIR)
+            .into_iter()
+            .flatten()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_name().to_string_lossy().ends_with("Packages"))
+            .filter_map(|entry| modified(&entry.path()));
+
+        lists.chain(modified(Path::new(Self::UPDATE_STAMP))).max()
+    }
+
+    /// dpkg状态文件的修改时间
+    pub fn status_modified() -> Option<SystemTime> {
+        fs::metadata(Self::DPKG_STATUS)
+            .and_then(|meta| meta.modified())
+            .ok()
     }
 
     pub fn check<S>(&self, name: S) -> Result<()>
@@ -44,11 +282,45 @@ impl AptOp {
     {
         let name = name.as_ref().to_string_lossy().to_string();
 
-        /* 创建命令 */
-        self.list
-            .get(&name)
-            .ok_or(anyhow!(format!("Con not find package: {name}")))?;
-        Ok(())
+        match self.status(&name)? {
+            AptStatus::NotFound => Err(anyhow!("Con not find package: {name}")),
+            AptStatus::Virtual(providers)
+                if providers.len() > 1
+                    && !providers.iter().any(|p| self.installed.contains_key(p)) =>
+            {
+                Err(anyhow!(
+                    "{name} is a virtual package provided by {}, select one of them explicitly",
+                    providers.join(", ")
+                ))
+            }
+            status => {
+                info!(target: "APT", "{name}: {status}");
+                Ok(())
+            }
+        }
+    }
+
+    /// 软件包是否已经安装(任意版本)，`name`可以带`:arch`后缀
+    pub fn is_installed(&self, name: &str) -> bool {
+        self.installed.contains_key(name)
+    }
+
+    /// 查询软件包的状态，`name`可以带`:arch`后缀
+    pub fn status(&self, name: &str) -> Result<AptStatus> {
+        if let Some(version) = self.installed.get(name) {
+            return Ok(AptStatus::Installed(version.clone()));
+        }
+
+        if let Some(candidate) = self.policy(name)?.candidate {
+            return Ok(AptStatus::Available(candidate));
+        }
+
+        let providers = self.providers(name)?;
+        if !providers.is_empty() {
+            return Ok(AptStatus::Virtual(providers));
+        }
+
+        Ok(AptStatus::NotFound)
     }
 
     pub fn install<S: AsRef<OsStr>>(&self, name: S) -> Result<()> {
@@ -57,11 +329,341 @@ impl AptOp {
         cmd.arg("install").arg("-y").arg(name);
         cmd.env("DEBIAN_FRONTEND", "noninteractive");
 
-        run_or_sudo(cmd).context("apt install fail")?;
+        self.run_locked(cmd).context("apt install fail")?;
+
+        Ok(())
+    }
+
+    /// 检查软件包及其安装选项，返回要安装的具体版本
+    pub fn check_package(&self, package: &AptPackage) -> Result<Option<String>> {
+        self.check(&package.name)?;
+
+        if package.version.is_none() && package.target_release.is_none() {
+            return Ok(None);
+        }
+
+        self.policy(&package.name)?.select(package)
+    }
+
+    /// 把软件包加入待安装队列，`version`为`check_package`得到的具体版本，由`commit`统一安装
+    pub fn queue(&mut self, package: &AptPackage, version: Option<String>) -> Result<()> {
+        let present = match self.status(&package.name)? {
+            AptStatus::Installed(installed) => version.as_ref().is_none_or(|v| *v == installed),
+            AptStatus::Virtual(providers) => {
+                version.is_none() && providers.iter().any(|p| self.installed.contains_key(p))
+            }
+            _ => false,
+        };
+
+        if present {
+            info!(target: "APT", "{} is already installed, skip", package.name);
+        }
+
+        self.queued.push(QueuedPackage {
+            package: package.clone(),
+            version,
+            present,
+        });
+
+        Ok(())
+    }
+
+    /// 读取.deb文件的元数据，并检查架构是否与当前系统匹配
+    pub fn inspect_deb<P: AsRef<Path>>(&self, deb: P) -> Result<DebInfo> {
+        let deb = deb.as_ref();
+
+        let mut cmd = Command::new("dpkg-deb");
+        cmd.arg("-f")
+            .arg(deb)
+            .arg("Package")
+            .arg("Version")
+            .arg("Architecture");
+
+        let content = self.runner.run(cmd).context(format!(
+            "{} is not a valid deb package",
+            deb.to_string_lossy()
+        ))?;
+
+        let fields: HashMap<_, _> = content
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .map(|(key, value)| (key.trim(), value.trim().to_string()))
+            .collect();
+
+        let field = |key: &str| {
+            fields
+                .get(key)
+                .cloned()
+                .ok_or(anyhow!("{} has no {key} field", deb.to_string_lossy()))
+        };
+
+        let info = DebInfo {
+            name: field("Package")?,
+            version: field("Version")?,
+            arch: field("Architecture")?,
+        };
+
+        ensure!(
+            info.arch == self.native_arch || info.arch == "all",
+            "{} is built for {}, but the host is {}",
+            deb.to_string_lossy(),
+            info.arch,
+            self.native_arch
+        );
+
+        Ok(info)
+    }
+
+    /// 把.deb文件加入待安装队列，与其他软件包在同一个事务中安装，依赖由apt解决
+    pub fn queue_deb<P: AsRef<Path>>(&mut self, deb: P, info: &DebInfo) {
+        let present = self.installed.get(&info.name) == Some(&info.version);
+
+        if present {
+            info!(target: "APT", "{} {} is already installed, skip", info.name, info.version);
+        }
+
+        self.queued.push(QueuedPackage {
+            package: AptPackage {
+                name: deb.as_ref().to_string_lossy().to_string(),
+                ..Default::default()
+            },
+            version: None,
+            present,
+        });
+    }
+
+    /// 在尽可能少的apt事务中安装队列中的所有软件包
+    ///
+    /// `--no-install-recommends`和`-t`是整个事务的选项，选项不同的软件包分开安装。
+    /// 事务失败时二分查找出导致失败的软件包。
+    pub fn commit(&mut self) -> Result<()> {
+        let queued = std::mem::take(&mut self.queued);
+
+        let mut groups: Vec<Vec<QueuedPackage>> = Vec::new();
+        for item in queued.iter().filter(|item| !item.present).cloned() {
+            match groups
+                .iter_mut()
+                .find(|group| group[0].same_transaction(&item))
+            {
+                Some(group) => group.push(item),
+                None => groups.push(vec![item]),
+            }
+        }
+
+        for group in &groups {
+            info!(target: "APT", "Installing {} packages in one transaction...", group.len());
+
+            if let Err(err) = self.install_packages(group) {
+                let mut offending = Vec::new();
+                self.bisect(group, &mut offending);
+
+                let offending: Vec<_> = offending
+                    .iter()
+                    .map(|item| item.package.name.as_str())
+                    .collect();
+
+                return Err(if offending.is_empty() {
+                    err.context("apt transaction fail, but every package installs on its own")
+                } else {
+                    err.context(format!(
+                        "apt transaction fail, offending packages: {}",
+                        offending.join(", ")
+                    ))
+                });
+            }
+        }
+
+        let holds: Vec<_> = queued
+            .iter()
+            .filter(|item| item.package.hold.unwrap_or_default())
+            .map(|item| item.package.name.as_str())
+            .collect();
+
+        if !holds.is_empty() {
+            self.hold(&holds)?;
+        }
+
+        self.verify_preseeded()
+            .context("debconf selections are not applied")?;
+
+        Ok(())
+    }
+
+    /// 通过debconf-set-selections预先设置debconf问题的答案，安装软件包时会使用这些答案
+    pub fn preseed(&mut self, selection: &DebconfSelection) -> Result<()> {
+        let selections = NamedTempFile::new()?;
+        fs::write(selections.path(), selection.to_string() + "\n")?;
+
+        let mut cmd = Command::new("debconf-set-selections");
+        cmd.arg(selections.path());
+
+        self.runner
+            .run_as_root(cmd)
+            .context(format!("Fail to preseed {}", selection.question))?;
+
+        self.preseeded.push(selection.clone());
+        Ok(())
+    }
+
+    /// 检查预设的debconf答案是否生效，已经安装的软件包不会读取新的答案，需要重新配置
+    fn verify_preseeded(&mut self) -> Result<()> {
+        let mismatched = self.mismatched_selections()?;
+        if mismatched.is_empty() {
+            return Ok(());
+        }
+
+        let mut owners: Vec<_> = mismatched.iter().map(|s| s.owner.clone()).collect();
+        owners.sort();
+        owners.dedup();
+
+        for owner in owners
+            .iter()
+            .filter(|owner| self.installed.contains_key(*owner))
+        {
+            info!(target: "APT", "Reconfiguring {owner} to apply debconf selections...");
+            let mut cmd = Command::new("dpkg-reconfigure");
+            cmd.arg("-f")
+                .arg("noninteractive")
+                .arg(owner)
+                .env("DEBIAN_FRONTEND", "noninteractive");
+
+            self.runner
+                .run_as_root(cmd)
+                .context(format!("Fail to reconfigure {owner}"))?;
+        }
+
+        let mismatched = self.mismatched_selections()?;
+        ensure!(
+            mismatched.is_empty(),
+            "{}",
+            mismatched
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        Ok(())
+    }
+
+    fn mismatched_selections(&self) -> Result<Vec<DebconfSelection>> {
+        let mut mismatched = Vec::new();
+        let mut shown: HashMap<String, HashMap<String, String>> = HashMap::new();
+
+        /* password类型的答案不会被debconf-show显示 */
+        for selection in self.preseeded.iter().filter(|s| s.kind != "password") {
+            if !shown.contains_key(&selection.owner) {
+                let mut cmd = Command::new("debconf-show");
+                cmd.arg(&selection.owner);
+                let content = self.runner.run(cmd).context(format!(
+                    "Fail to read debconf selections of {}",
+                    selection.owner
+                ))?;
+                shown.insert(
+                    selection.owner.clone(),
+                    DebconfSelection::parse_show(&content),
+                );
+            }
+
+            if shown[&selection.owner].get(&selection.question) != Some(&selection.value) {
+                mismatched.push(selection.clone());
+            }
+        }
+
+        Ok(mismatched)
+    }
+
+    /// `items`一起安装会失败，逐步缩小范围重试安装，找出单独安装也会失败的软件包
+    ///
+    /// 重试是真正的安装，查找结束后能安装的软件包都已经装上了，只有找出的软件包没有安装。
+    fn bisect<'a>(&self, items: &'a [QueuedPackage], offending: &mut Vec<&'a QueuedPackage>) {
+        if items.len() == 1 {
+            offending.push(&items[0]);
+            return;
+        }
+
+        let (left, right) = items.split_at(items.len() / 2);
+        for half in [left, right] {
+            if self.install_packages(half).is_err() {
+                self.bisect(half, offending);
+            }
+        }
+    }
+
+    fn install_packages(&self, items: &[QueuedPackage]) -> Result<()> {
+        let Some(first) = items.first() else {
+            return Ok(());
+        };
+
+        let mut cmd = Command::new(&self.apt_path);
+        cmd.arg("install").arg("-y");
+        cmd.env("DEBIAN_FRONTEND", "noninteractive");
+
+        if first.package.no_install_recommends.unwrap_or_default() {
+            cmd.arg("--no-install-recommends");
+        }
+
+        if let Some(release) = &first.package.target_release {
+            cmd.arg("-t").arg(release);
+        }
+
+        if items.iter().any(|item| item.version.is_some()) {
+            cmd.arg("--allow-downgrades");
+        }
+
+        for item in items {
+            match &item.version {
+                Some(version) => cmd.arg(format!("{}={version}", item.package.name)),
+                None => cmd.arg(&item.package.name),
+            };
+        }
+
+        self.run_locked(cmd).context("apt install fail")?;
 
         Ok(())
     }
 
+    /// 锁定软件包的版本，已经锁定的软件包不再重复锁定
+    pub fn hold<S: AsRef<str>>(&self, names: &[S]) -> Result<()> {
+        let held = self.held()?;
+        let names: Vec<&str> = names
+            .iter()
+            .map(AsRef::as_ref)
+            .filter(|name| !held.iter().any(|held| held == name))
+            .collect();
+
+        if names.is_empty() {
+            return Ok(());
+        }
+
+        info!(target: "APT", "Holding {}...", names.join(", "));
+        let mut cmd = Command::new("apt-mark");
+        cmd.arg("hold").args(names);
+
+        self.run_locked(cmd).context("Fail to hold packages")?;
+
+        Ok(())
+    }
+
+    /// 已经锁定版本的软件包
+    fn held(&self) -> Result<Vec<String>> {
+        let mut cmd = Command::new("apt-mark");
+        cmd.arg("showhold");
+
+        let content = self.runner.run(cmd).context("Fail to get held packages")?;
+        Ok(content
+            .lines()
+            .map(|line| line.trim().to_string())
+            .collect())
+    }
+
+    pub fn policy<S: AsRef<OsStr>>(&self, name: S) -> Result<AptPolicy> {
+        let mut cmd = Command::new("apt-cache");
+        cmd.arg("policy").arg(name);
+
+        Ok(AptPolicy::parse(&self.runner.run(cmd)?))
+    }
+
     pub fn remove<S: AsRef<OsStr>>(&self, name: S) -> Result<()> {
         let name = name.as_ref();
 
@@ -73,7 +675,7 @@ impl AptOp {
             .arg("--purge")
             .arg(name);
 
-        run_or_sudo(cmd).context(format!(
+        self.run_locked(cmd).context(format!(
             "Fail to remove package: {}",
             name.to_string_lossy()
         ))?;
@@ -81,28 +683,357 @@ impl AptOp {
         Ok(())
     }
 
+    /// 重新更新软件源并刷新软件包列表，添加软件源之后需要调用
+    pub fn refresh(&mut self) -> Result<()> {
+        self.update().context("apt update fail")?;
+        self.get_installed()
+            .context("Fail to get installed packages")?;
+        Ok(())
+    }
+
     fn update(&mut self) -> Result<()> {
         let mut cmd = Command::new(&self.apt_path);
         cmd.arg("update");
 
-        run_or_sudo(cmd).context("Fail to update package list")?;
+        self.run_locked(cmd)
+            .context("Fail to update package list")?;
         Ok(())
     }
 
-    fn get_list(&mut self) -> Result<()> {
-        let mut cmd = Command::new(&self.apt_path);
-        cmd.arg("list");
+    /// 获取已安装的软件包，dpkg状态没有变化时直接使用磁盘上的缓存
+    fn load_installed(&mut self) -> Result<()> {
+        let (Some(cache_dir), Some(status_mtime)) =
+            (self.runner.cache_dir(), self.runner.dpkg_status_modified())
+        else {
+            return self.get_installed();
+        };
+        let status_mtime = status_mtime.duration_since(UNIX_EPOCH)?.as_micros() as u64;
+
+        if let Some(cache) = InstalledCache::load(&cache_dir)
+            && cache.status_mtime == status_mtime
+            && cache.native_arch == self.native_arch
+        {
+            debug!(target: "APT", "Using cached package index");
+            self.installed = cache.installed;
+            return Ok(());
+        }
+
+        self.get_installed()?;
 
-        let list_content = run_or_sudo(cmd).context("apt list fail")?;
-        self.list = list_content
+        let cache = InstalledCache {
+            status_mtime,
+            native_arch: self.native_arch.clone(),
+            installed: self.installed.clone(),
+        };
+        if let Err(err) = cache.save(&cache_dir) {
+            debug!(target: "APT", "Fail to save package index cache: {err:#}");
+        }
+
+        Ok(())
+    }
+
+    /// 通过dpkg-query获取已安装的软件包
+    fn get_installed(&mut self) -> Result<()> {
+        let mut cmd = Command::new("dpkg-query");
+        cmd.arg("-W")
+            .arg("-f=${Package}\\t${Architecture}\\t${Version}\\t${db:Status-Status}\\n");
+
+        let content = self.runner.run(cmd).context("dpkg-query fail")?;
+
+        self.installed.clear();
+        for line in content.lines() {
+            let [name, arch, version, status] = line.split('\t').collect::<Vec<_>>()[..] else {
+                continue;
+            };
+
+            if status != "installed" {
+                continue;
+            }
+
+            self.installed
+                .insert(format!("{name}:{arch}"), version.to_string());
+
+            if arch == self.native_arch || arch == "all" {
+                self.installed.insert(name.to_string(), version.to_string());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// 通过`apt-cache showpkg`获取提供该虚包的软件包
+    fn providers(&self, name: &str) -> Result<Vec<String>> {
+        let mut cmd = Command::new("apt-cache");
+        cmd.arg("showpkg").arg(name);
+
+        /* 找不到软件包时apt-cache showpkg也可能返回成功，只是没有输出 */
+        let Result::Ok(content) = self.runner.run(cmd) else {
+            return Ok(Vec::new());
+        };
+
+        let mut providers: Vec<String> = content
             .lines()
-            .filter_map(|s| s.split_once('/').map(|(before, _)| before.to_string()))
+            .skip_while(|line| !line.starts_with("Reverse Provides:"))
+            .skip(1)
+            .filter_map(|line| line.split_whitespace().next())
+            .map(String::from)
             .collect();
+        providers.dedup();
+
+        Ok(providers)
+    }
+}
 
+impl Drop for AptOp {
+    fn drop(&mut self) {
+        if let Err(err) = self.remove_bundle() {
+            warn!(target: "APT", "{err:#}");
+        }
+    }
+}
+
+/// 初始化apt时的选项
+#[derive(Debug, Clone)]
+pub struct AptOptions {
+    pub refresh: RefreshPolicy,
+    /// 等待dpkg锁的最长时间
+    pub lock_timeout: Duration,
+    /// 离线软件包目录，由installer_builder打包
+    pub bundle: Option<PathBuf>,
+}
+
+impl Default for AptOptions {
+    fn default() -> Self {
+        Self {
+            refresh: Default::default(),
+            lock_timeout: AptOp::LOCK_TIMEOUT,
+            bundle: None,
+        }
+    }
+}
+
+/// apt update的策略
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RefreshPolicy {
+    /// 软件包列表过期时才更新
+    #[default]
+    Auto,
+    /// 总是更新
+    Always,
+    /// 从不更新
+    Never,
+}
+
+/// 已安装软件包的磁盘缓存，dpkg状态文件变化后失效
+#[derive(Debug, Serialize, Deserialize)]
+struct InstalledCache {
+    status_mtime: u64,
+    native_arch: String,
+    installed: HashMap<String, String>,
+}
+
+impl InstalledCache {
+    fn cache_file(cache_dir: &Path) -> PathBuf {
+        cache_dir
+            .join(env!("CARGO_PKG_NAME"))
+            .join("dpkg-installed.toml")
+    }
+
+    fn load(cache_dir: &Path) -> Option<Self> {
+        let content = fs::read_to_string(Self::cache_file(cache_dir)).ok()?;
+        toml::from_str(&content).ok()
+    }
+
+    fn save(&self, cache_dir: &Path) -> Result<()> {
+        FileOp::write(Self::cache_file(cache_dir), toml::to_string(self)?, None)?;
         Ok(())
     }
 }
 
+/// debconf问题的答案，格式与debconf-set-selections相同: <owner> <question> <type> <value>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebconfSelection {
+    pub owner: String,
+    pub question: String,
+    pub kind: String,
+    pub value: String,
+}
+
+impl DebconfSelection {
+    const TYPES: [&str; 9] = [
+        "string",
+        "boolean",
+        "select",
+        "multiselect",
+        "note",
+        "text",
+        "password",
+        "error",
+        "title",
+    ];
+
+    /// 从`<question> = "<type> <value>"`形式的配置项创建，owner取问题名`/`之前的部分
+    pub fn from_pair(question: &str, answer: &str) -> Result<Self> {
+        let (owner, _) = question.split_once('/').ok_or(anyhow!(
+            "{question} is not a debconf question like package/question"
+        ))?;
+        let (kind, value) = answer
+            .trim()
+            .split_once(char::is_whitespace)
+            .unwrap_or((answer.trim(), ""));
+
+        ensure!(
+            Self::TYPES.contains(&kind),
+            "{question}: unknown debconf type {kind}, expected one of {}",
+            Self::TYPES.join(", ")
+        );
+
+        ensure!(
+            kind != "boolean" || matches!(value.trim(), "true" | "false"),
+            "{question}: boolean value must be true or false"
+        );
+
+        Ok(Self {
+            owner: owner.to_string(),
+            question: question.to_string(),
+            kind: kind.to_string(),
+            value: value.trim().to_string(),
+        })
+    }
+
+    /// 解析debconf-show的输出，如`* wireshark-common/install-setuid: true`
+    fn parse_show(content: &str) -> HashMap<String, String> {
+        content
+            .lines()
+            .filter_map(|line| line.trim_start_matches(['*', ' ']).split_once(':'))
+            .map(|(question, value)| (question.to_string(), value.trim().to_string()))
+            .collect()
+    }
+}
+
+impl Display for DebconfSelection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}",
+            self.owner, self.question, self.kind, self.value
+        )
+    }
+}
+
+/// .deb文件的元数据
+#[derive(Debug, Clone)]
+pub struct DebInfo {
+    pub name: String,
+    pub version: String,
+    pub arch: String,
+}
+
+/// `apt-cache policy`的输出
+#[derive(Debug, Default)]
+pub struct AptPolicy {
+    pub installed: Option<String>,
+    pub candidate: Option<String>,
+    pub versions: Vec<AptVersion>,
+}
+
+/// 版本表中的一个版本及其来源
+#[derive(Debug, Default)]
+pub struct AptVersion {
+    pub version: String,
+    pub sources: Vec<String>,
+}
+
+impl AptPolicy {
+    /*
+     * tree:
+     *   Installed: (none)
+     *   Candidate: 2.1.0-1
+     *   Version table:
+     *  *** 2.1.0-1 500
+     *         500 http://deb.debian.org/debian bookworm/main amd64 Packages
+     *         100 /var/lib/dpkg/status
+     */
+    pub fn parse(content: &str) -> Self {
+        let mut policy = Self::default();
+        let field = |value: &str| match value.trim() {
+            "(none)" | "" => None,
+            value => Some(value.to_string()),
+        };
+
+        for line in content.lines() {
+            let line = line.trim().trim_start_matches("***").trim();
+
+            if let Some(installed) = line.strip_prefix("Installed:") {
+                policy.installed = field(installed);
+            } else if let Some(candidate) = line.strip_prefix("Candidate:") {
+                policy.candidate = field(candidate);
+            } else if let [version, priority] = line.split_whitespace().collect::<Vec<_>>()[..]
+                && priority.parse::<i32>().is_ok()
+            {
+                policy.versions.push(AptVersion {
+                    version: version.to_string(),
+                    sources: Vec::new(),
+                });
+            } else if let Some(version) = policy.versions.last_mut()
+                && line
+                    .split_whitespace()
+                    .next()
+                    .is_some_and(|p| p.parse::<i32>().is_ok())
+            {
+                version.sources.push(line.to_string());
+            }
+        }
+
+        policy
+    }
+
+    /// 按`version`和`target_release`从版本表中选出要安装的版本，没有指定版本时返回None
+    pub fn select(&self, package: &AptPackage) -> Result<Option<String>> {
+        let versions: Vec<_> = self
+            .versions
+            .iter()
+            .filter(|version| {
+                package.target_release.as_ref().is_none_or(|release| {
+                    version
+                        .sources
+                        .iter()
+                        .any(|source| source.contains(&format!(" {release}/")))
+                })
+            })
+            .collect();
+
+        ensure!(
+            !versions.is_empty(),
+            "{} is not available from release {}",
+            package.name,
+            package.target_release.as_deref().unwrap_or_default()
+        );
+
+        let Some(version) = &package.version else {
+            return Ok(None);
+        };
+
+        let pattern = Pattern::new(version).context(format!("Invalid version: {version}"))?;
+
+        /* apt-cache policy的版本表按版本从高到低排列，取第一个匹配的版本 */
+        let matched = versions
+            .iter()
+            .find(|candidate| pattern.matches(&candidate.version))
+            .ok_or(anyhow!(
+                "Version {version} of {} is not available, available versions: {}",
+                package.name,
+                versions
+                    .iter()
+                    .map(|candidate| candidate.version.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))?;
+
+        Ok(Some(matched.version.clone()))
+    }
+}
+
 impl Debug for AptOp {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("AptOp")
@@ -110,3 +1041,309 @@ impl Debug for AptOp {
             .finish()
     }
 }
+
+impl PackageManager for AptOp {
+    fn check(&self, name: &str) -> Result<()> {
+        AptOp::check(self, name)
+    }
+
+    fn install(&self, name: &str) -> Result<()> {
+        AptOp::install(self, name)
+    }
+
+    fn remove(&self, name: &str) -> Result<()> {
+        AptOp::remove(self, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::fake::FakeApt;
+
+    const INSTALLED: &str = "\
+tree:
+  Installed: 2.1.0-1
+  Candidate: 2.1.0-1
+  Version table:
+ *** 2.1.0-1 500
+        500 http://deb.debian.org/debian bookworm/main amd64 Packages
+        100 /var/lib/dpkg/status
+";
+
+    /* 官方源的nginx被pin到990，nginx.org的源被pin到100 */
+    const PINNED: &str = "\
+nginx:
+  Installed: (none)
+  Candidate: 1.22.1-9+deb12u1
+  Package pin: 1.22.1-9+deb12u1
+  Version table:
+     1.25.3-1~bookworm 100
+        100 https://nginx.org/packages/mainline/debian bookworm/nginx amd64 Packages
+     1.24.0-1~bookworm 100
+        100 https://nginx.org/packages/mainline/debian bookworm/nginx amd64 Packages
+     1.22.1-9+deb12u1 990
+        990 http://deb.debian.org/debian bookworm/main amd64 Packages
+        990 http://security.debian.org/debian-security bookworm-security/main amd64 Packages
+     1.22.1-9 990
+        990 http://deb.debian.org/debian bookworm/main amd64 Packages
+";
+
+    const BACKPORTS: &str = "\
+linux-image-amd64:
+  Installed: 6.1.76-1
+  Candidate: 6.1.76-1
+  Version table:
+     6.5.10-1~bpo12+1 100
+        100 http://deb.debian.org/debian bookworm-backports/main amd64 Packages
+ *** 6.1.76-1 500
+        500 http://deb.debian.org/debian bookworm/main amd64 Packages
+        100 /var/lib/dpkg/status
+";
+
+    fn package(name: &str, version: Option<&str>, release: Option<&str>) -> AptPackage {
+        AptPackage {
+            name: name.into(),
+            version: version.map(Into::into),
+            target_release: release.map(Into::into),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parse_installed_package() {
+        let policy = AptPolicy::parse(INSTALLED);
+
+        assert_eq!(policy.installed.as_deref(), Some("2.1.0-1"));
+        assert_eq!(policy.candidate.as_deref(), Some("2.1.0-1"));
+        assert_eq!(policy.versions.len(), 1);
+        assert_eq!(policy.versions[0].version, "2.1.0-1");
+        assert_eq!(
+            policy.versions[0].sources,
+            [
+                "500 http://deb.debian.org/debian bookworm/main amd64 Packages",
+                "100 /var/lib/dpkg/status"
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_pinned_package_with_several_sources() {
+        let policy = AptPolicy::parse(PINNED);
+
+        assert_eq!(policy.installed, None);
+        assert_eq!(policy.candidate.as_deref(), Some("1.22.1-9+deb12u1"));
+        let versions: Vec<_> = policy
+            .versions
+            .iter()
+            .map(|version| (version.version.as_str(), version.sources.len()))
+            .collect();
+        assert_eq!(
+            versions,
+            [
+                ("1.25.3-1~bookworm", 1),
+                ("1.24.0-1~bookworm", 1),
+                ("1.22.1-9+deb12u1", 2),
+                ("1.22.1-9", 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_missing_package() {
+        let policy = AptPolicy::parse("");
+
+        assert_eq!(policy.installed, None);
+        assert_eq!(policy.candidate, None);
+        assert!(policy.versions.is_empty());
+    }
+
+    #[test]
+    fn select_version_by_glob() {
+        let policy = AptPolicy::parse(PINNED);
+        let select = |version| policy.select(&package("nginx", Some(version), None));
+
+        /* 版本表从高到低排列，取第一个匹配的版本 */
+        assert_eq!(
+            select("1.2*").unwrap().as_deref(),
+            Some("1.25.3-1~bookworm")
+        );
+        assert_eq!(
+            select("1.22.1-9*").unwrap().as_deref(),
+            Some("1.22.1-9+deb12u1")
+        );
+        assert_eq!(select("1.22.1-9").unwrap().as_deref(), Some("1.22.1-9"));
+
+        let err = select("1.26*").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Version 1.26* of nginx is not available, available versions: 1.25.3-1~bookworm, 1.24.0-1~bookworm, 1.22.1-9+deb12u1, 1.22.1-9"
+        );
+        assert!(select("[").is_err());
+        assert_eq!(policy.select(&package("nginx", None, None)).unwrap(), None);
+    }
+
+    #[test]
+    fn select_version_by_target_release() {
+        let policy = AptPolicy::parse(BACKPORTS);
+        let select =
+            |version, release| policy.select(&package("linux-image-amd64", version, Some(release)));
+
+        assert_eq!(select(None, "bookworm-backports").unwrap(), None);
+        assert_eq!(
+            select(Some("6.*"), "bookworm-backports")
+                .unwrap()
+                .as_deref(),
+            Some("6.5.10-1~bpo12+1")
+        );
+        /* bookworm不匹配bookworm-backports */
+        assert_eq!(
+            select(Some("6.*"), "bookworm").unwrap().as_deref(),
+            Some("6.1.76-1")
+        );
+        assert!(select(Some("6.1*"), "bookworm-backports").is_err());
+
+        let err = select(None, "trixie").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "linux-image-amd64 is not available from release trixie"
+        );
+    }
+
+    #[test]
+    fn debconf_selection_from_pair() -> Result<()> {
+        let selection =
+            DebconfSelection::from_pair("tzdata/Areas", "select  Europe/Berlin with spaces ")?;
+        assert_eq!(selection.owner, "tzdata");
+        assert_eq!(selection.question, "tzdata/Areas");
+        assert_eq!(selection.kind, "select");
+        assert_eq!(selection.value, "Europe/Berlin with spaces");
+        assert_eq!(
+            selection.to_string(),
+            "tzdata tzdata/Areas select Europe/Berlin with spaces"
+        );
+
+        let empty = DebconfSelection::from_pair("mysql-server/root_password", "password")?;
+        assert_eq!(empty.kind, "password");
+        assert_eq!(empty.value, "");
+
+        assert!(DebconfSelection::from_pair("no-owner", "string x").is_err());
+        assert!(DebconfSelection::from_pair("pkg/q", "number 3").is_err());
+        assert!(DebconfSelection::from_pair("pkg/q", "boolean yes").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn debconf_show_is_parsed() {
+        let shown = DebconfSelection::parse_show(
+            "* wireshark-common/install-setuid: true\n  wireshark-common/group-is-user-group: \n  tzdata/Areas: Europe\n",
+        );
+
+        assert_eq!(shown.len(), 3);
+        assert_eq!(shown["wireshark-common/install-setuid"], "true");
+        assert_eq!(shown["wireshark-common/group-is-user-group"], "");
+        assert_eq!(shown["tzdata/Areas"], "Europe");
+    }
+
+    #[test]
+    fn failing_package_is_found_by_bisect() -> Result<()> {
+        let home = tempfile::TempDir::new()?;
+        let fake = Rc::new(
+            ["a", "b", "c", "d"]
+                .into_iter()
+                .fold(FakeApt::new(), |fake, name| fake.with_package(name, "1.0"))
+                .with_failing("c")
+                .with_home(home.path()),
+        );
+        let mut apt = AptOp::new(AptOptions::default(), fake.clone())?;
+        for name in ["a", "b", "c", "d"] {
+            apt.queue(&package(name, None, None), None)?;
+        }
+
+        let error = apt.commit().unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "apt transaction fail, offending packages: c"
+        );
+        assert!(["a", "b", "d"].iter().all(|name| fake.is_installed(name)));
+        assert!(!fake.is_installed("c"));
+        Ok(())
+    }
+
+    #[test]
+    fn held_packages_are_not_held_again() -> Result<()> {
+        let home = tempfile::TempDir::new()?;
+        let fake = Rc::new(
+            FakeApt::new()
+                .with_installed("tree", "2.1.0")
+                .with_installed("jq", "1.6")
+                .with_home(home.path()),
+        );
+        let apt = AptOp::new(AptOptions::default(), fake.clone())?;
+
+        apt.hold(&["tree"])?;
+        apt.hold(&["tree", "jq"])?;
+        apt.hold(&["jq"])?;
+
+        let holds: Vec<_> = fake
+            .commands()
+            .into_iter()
+            .filter(|cmd| cmd.starts_with("apt-mark hold"))
+            .collect();
+        assert_eq!(holds, ["apt-mark hold tree", "apt-mark hold jq"]);
+        Ok(())
+    }
+
+    fn count(fake: &FakeApt, prefix: &str) -> usize {
+        fake.commands()
+            .iter()
+            .filter(|cmd| cmd.starts_with(prefix))
+            .count()
+    }
+
+    #[test]
+    fn fresh_lists_are_not_updated() -> Result<()> {
+        let stale = SystemTime::now() - AptOp::FRESH_FOR - Duration::from_secs(60);
+        let fake = Rc::new(FakeApt::new().with_lists_updated(stale));
+
+        AptOp::new(AptOptions::default(), fake.clone())?;
+        assert_eq!(count(&fake, "apt update"), 1);
+
+        /* 刚刚更新过 */
+        AptOp::new(AptOptions::default(), fake.clone())?;
+        assert_eq!(count(&fake, "apt update"), 1);
+
+        let always = AptOptions {
+            refresh: RefreshPolicy::Always,
+            ..Default::default()
+        };
+        AptOp::new(always, fake.clone())?;
+        assert_eq!(count(&fake, "apt update"), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn installed_cache_follows_dpkg_status() -> Result<()> {
+        let home = tempfile::TempDir::new()?;
+        let fake = Rc::new(
+            FakeApt::new()
+                .with_package("tree", "2.1.0")
+                .with_home(home.path()),
+        );
+
+        let apt = AptOp::new(AptOptions::default(), fake.clone())?;
+        assert!(!apt.is_installed("tree"));
+        assert!(InstalledCache::cache_file(&home.path().join(".cache")).is_file());
+
+        /* dpkg状态没有变化，使用缓存 */
+        AptOp::new(AptOptions::default(), fake.clone())?;
+        assert_eq!(count(&fake, "dpkg-query -W"), 1);
+
+        apt.install("tree")?;
+        let apt = AptOp::new(AptOptions::default(), fake.clone())?;
+        assert_eq!(count(&fake, "dpkg-query -W"), 2);
+        assert!(apt.is_installed("tree"));
+        Ok(())
+    }
+}
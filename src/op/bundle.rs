@@ -0,0 +1,286 @@
+use crate::op::package::Packages;
+use crate::runner::CommandRunner;
+use crate::types::config::AptPackage;
+use anyhow::{Context, Result, anyhow, ensure};
+use log::{info, warn};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::rc::Rc;
+
+/// 离线安装用的apt软件包集合，是一个不需要签名的本地flat仓库
+///
+/// 在打包机器上下载软件包及其所有依赖，目标机器上作为优先级更高的软件源使用
+pub struct AptBundle;
+
+impl AptBundle {
+    pub const ORIGIN: &str = "distro-pioneer-bundle";
+    pub const SOURCES_FILE: &str = "/etc/apt/sources.list.d/distro-pioneer-bundle.sources";
+    pub const PREFERENCES_FILE: &str = "/etc/apt/preferences.d/distro-pioneer-bundle";
+    /// 高于普通软件源的500，但低于1000，不会因此降级已安装的软件包
+    const PRIORITY: u32 = 990;
+
+    /// 在`dir`中下载`packages`及其依赖，并生成`Packages`和`Release`索引
+    pub fn build(packages: &[AptPackage], dir: &Path, runner: Rc<dyn CommandRunner>) -> Result<()> {
+        fs::create_dir_all(dir)?;
+
+        let hosts = Packages::detect(runner.clone());
+        let runner = runner.as_ref();
+        let mut with_recommends = Vec::new();
+        let mut without_recommends = Vec::new();
+        let mut pinned = Vec::new();
+
+        for package in packages {
+            let name = hosts
+                .candidates(
+                    &package.name,
+                    package.alternatives.as_ref(),
+                    package.distro.as_ref(),
+                )
+                .into_iter()
+                .find(|name| Self::apt_cache(runner, &["show", name]).is_ok())
+                .ok_or(anyhow!("Con not find package: {}", package.name))?;
+
+            /* 带通配符的版本只能在目标机器上匹配，这里下载候选版本 */
+            match &package.version {
+                Some(version) if !version.contains(['*', '?', '[']) => {
+                    pinned.push((name.clone(), version.clone()))
+                }
+                Some(version) => {
+                    warn!(target: "Bundle", "{name}: version pattern {version} is not bundled, use candidate version")
+                }
+                None => {}
+            }
+
+            match package.no_install_recommends.unwrap_or_default() {
+                true => without_recommends.push(name),
+                false => with_recommends.push(name),
+            }
+        }
+
+        let mut all = BTreeSet::new();
+        all.extend(Self::depends(runner, &with_recommends, true)?);
+        all.extend(Self::depends(runner, &without_recommends, false)?);
+
+        /* 指定了版本的软件包替换掉同名的候选版本 */
+        for (name, version) in pinned {
+            all.remove(&name);
+            all.insert(format!("{name}={version}"));
+        }
+
+        info!(target: "Bundle", "Downloading {} packages into {}...", all.len(), dir.to_string_lossy());
+        let mut cmd = Command::new("apt-get");
+        cmd.arg("download").args(&all).current_dir(dir);
+        runner.run(cmd).context("Fail to download apt packages")?;
+
+        Self::index(runner, dir)
+    }
+
+    /// .deb文件的包名，以及`Depends`和`Pre-Depends`中的依赖，`a | b`中的`b`作为`a`的备选
+    pub fn inspect_deb(
+        runner: &dyn CommandRunner,
+        deb: &Path,
+    ) -> Result<(String, Vec<AptPackage>)> {
+        let mut cmd = Command::new("dpkg-deb");
+        cmd.arg("-f").arg(deb).arg("Package");
+        let name = runner
+            .run(cmd)
+            .context(format!(
+                "{} is not a valid deb package",
+                deb.to_string_lossy()
+            ))?
+            .trim()
+            .to_string();
+
+        let mut depends = Vec::new();
+        for field in ["Pre-Depends", "Depends"] {
+            let mut cmd = Command::new("dpkg-deb");
+            cmd.arg("-f").arg(deb).arg(field);
+            let content = runner
+                .run(cmd)
+                .context(format!("Fail to read {}", deb.to_string_lossy()))?;
+
+            depends.extend(Self::parse_depends(&content));
+        }
+
+        Ok((name, depends))
+    }
+
+    /// 解析`Depends`字段，格式: `libc6 (>= 2.34), libfoo:any | libbar`，长的字段会折行
+    fn parse_depends(content: &str) -> Vec<AptPackage> {
+        content
+            .split(',')
+            .filter_map(|clause| {
+                let mut names = clause
+                    .split('|')
+                    .filter_map(|alternative| {
+                        alternative
+                            .split(|c: char| c.is_whitespace() || c == '(' || c == ':')
+                            .find(|s| !s.is_empty())
+                    })
+                    .map(String::from);
+
+                let name = names.next()?;
+                let alternatives: Vec<_> = names.collect();
+                Some(AptPackage {
+                    name,
+                    alternatives: (!alternatives.is_empty()).then_some(alternatives),
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
+
+    /// 软件包的递归依赖，包括软件包本身
+    fn depends(
+        runner: &dyn CommandRunner,
+        names: &[String],
+        recommends: bool,
+    ) -> Result<Vec<String>> {
+        if names.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut args = vec![
+            "depends",
+            "--recurse",
+            "--no-suggests",
+            "--no-conflicts",
+            "--no-breaks",
+            "--no-replaces",
+            "--no-enhances",
+        ];
+        if !recommends {
+            args.push("--no-recommends");
+        }
+        args.extend(names.iter().map(String::as_str));
+
+        let output = Self::apt_cache(runner, &args).context("Fail to resolve apt dependencies")?;
+
+        /* 依赖关系行以空格开头，虚包以`<`开头，不能直接下载 */
+        Ok(output
+            .lines()
+            .filter(|line| !line.starts_with([' ', '<']))
+            .map(|line| line.trim().to_string())
+            .collect())
+    }
+
+    fn apt_cache(runner: &dyn CommandRunner, args: &[&str]) -> Result<String> {
+        let mut cmd = Command::new("apt-cache");
+        cmd.args(args);
+        runner.run(cmd)
+    }
+
+    /// 生成flat仓库的`Packages`和`Release`文件
+    fn index(runner: &dyn CommandRunner, dir: &Path) -> Result<()> {
+        let mut debs: Vec<PathBuf> = fs::read_dir(dir)?
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "deb"))
+            .collect();
+        debs.sort();
+
+        let mut index = String::new();
+        for deb in &debs {
+            let mut control = Command::new("dpkg-deb");
+            control.arg("-f").arg(deb);
+            let control = runner
+                .run(control)
+                .context(format!("Fail to read {}", deb.to_string_lossy()))?;
+
+            let file_name = deb
+                .file_name()
+                .ok_or(anyhow!("{} has no file name", deb.to_string_lossy()))?;
+
+            index.push_str(control.trim_end());
+            index.push_str(&format!(
+                "\nFilename: ./{}\nSize: {}\nSHA256: {}\n\n",
+                file_name.to_string_lossy(),
+                fs::metadata(deb)?.len(),
+                Self::sha256(runner, deb)?
+            ));
+        }
+
+        let packages = dir.join("Packages");
+        fs::write(&packages, index)?;
+
+        let release = format!(
+            "Origin: {origin}\nLabel: {origin}\nSHA256:\n {} {} Packages\n",
+            Self::sha256(runner, &packages)?,
+            fs::metadata(&packages)?.len(),
+            origin = Self::ORIGIN,
+        );
+        fs::write(dir.join("Release"), release)?;
+
+        Ok(())
+    }
+
+    fn sha256(runner: &dyn CommandRunner, file: &Path) -> Result<String> {
+        let mut cmd = Command::new("sha256sum");
+        cmd.arg(file);
+        let output = runner.run(cmd)?;
+
+        let hash = output.split_whitespace().next().unwrap_or_default();
+        ensure!(!hash.is_empty(), "Fail to hash {}", file.to_string_lossy());
+        Ok(hash.to_string())
+    }
+
+    /// 指向`dir`的deb822软件源
+    pub fn sources(dir: &Path) -> String {
+        format!(
+            "Types: deb\nURIs: file:{}\nSuites: ./\nTrusted: yes\n",
+            dir.to_string_lossy()
+        )
+    }
+
+    /// 让bundle中的软件包优先于其他软件源
+    pub fn preferences() -> String {
+        format!(
+            "Package: *\nPin: release o={}\nPin-Priority: {}\n",
+            Self::ORIGIN,
+            Self::PRIORITY
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(package: &AptPackage) -> (&str, Vec<&str>) {
+        (
+            package.name.as_str(),
+            package
+                .alternatives
+                .iter()
+                .flatten()
+                .map(String::as_str)
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn parse_depends() {
+        let depends = AptBundle::parse_depends(
+            "libc6 (>= 2.34), libgcc-s1 (>= 4.2), python3:any,\n default-mta | mail-transport-agent, zlib1g\n",
+        );
+
+        assert_eq!(
+            depends.iter().map(names).collect::<Vec<_>>(),
+            [
+                ("libc6", vec![]),
+                ("libgcc-s1", vec![]),
+                ("python3", vec![]),
+                ("default-mta", vec!["mail-transport-agent"]),
+                ("zlib1g", vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_empty_depends() {
+        assert!(AptBundle::parse_depends("").is_empty());
+        assert!(AptBundle::parse_depends("\n").is_empty());
+    }
+}
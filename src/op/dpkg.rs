@@ -0,0 +1,232 @@
+use crate::runner::CommandRunner;
+use anyhow::{Result, bail};
+use log::{info, warn};
+use std::fmt::Display;
+use std::fs;
+use std::io::{IsTerminal, Write};
+use std::os::unix::fs::MetadataExt;
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// 持有dpkg/apt锁的进程
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockHolder {
+    pub lock: &'static str,
+    pub pid: u32,
+    /// 进程的命令行，进程已经退出时为空
+    pub command: String,
+}
+
+impl Display for LockHolder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.command.is_empty() {
+            true => write!(f, "{} is held by pid {}", self.lock, self.pid),
+            false => write!(
+                f,
+                "{} is held by {} (pid {})",
+                self.lock, self.command, self.pid
+            ),
+        }
+    }
+}
+
+/// dpkg/apt的锁，`unattended-upgrades`等后台任务运行时会持有这些锁
+pub struct DpkgLock;
+
+impl DpkgLock {
+    const LOCKS: [&str; 4] = [
+        "/var/lib/dpkg/lock-frontend",
+        "/var/lib/dpkg/lock",
+        "/var/lib/apt/lists/lock",
+        "/var/cache/apt/archives/lock",
+    ];
+    const PROC_LOCKS: &str = "/proc/locks";
+    /// dpkg中断时这个目录里会留下未处理完的状态更新
+    const DPKG_UPDATES: &str = "/var/lib/dpkg/updates";
+    const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+    /// 根据`/proc/locks`找出持有锁的进程，不需要root权限，也不会抢占锁
+    pub fn holders() -> Vec<LockHolder> {
+        let Ok(locks) = fs::read_to_string(Self::PROC_LOCKS) else {
+            return Vec::new();
+        };
+
+        let inodes: Vec<_> = Self::LOCKS
+            .iter()
+            .filter_map(|lock| fs::metadata(lock).ok().map(|meta| (*lock, meta.ino())))
+            .collect();
+
+        Self::parse_locks(&locks, &inodes)
+            .into_iter()
+            .map(|(lock, pid)| LockHolder {
+                lock,
+                pid,
+                command: Self::command_of(pid),
+            })
+            .collect()
+    }
+
+    /// 在`/proc/locks`的内容中找出`inodes`中的锁以及持有它们的进程
+    ///
+    /// 格式: `1: POSIX  ADVISORY  WRITE 1234 08:01:123456 0 EOF`，等待者的行带有`->`，
+    /// OFD锁的pid为-1，不属于某个进程
+    fn parse_locks(locks: &str, inodes: &[(&'static str, u64)]) -> Vec<(&'static str, u32)> {
+        locks
+            .lines()
+            .filter(|line| !line.contains("->"))
+            .filter_map(|line| {
+                let fields: Vec<_> = line.split_whitespace().collect();
+                let pid = fields.get(4)?.parse().ok()?;
+                let inode: u64 = fields.get(5)?.rsplit(':').next()?.parse().ok()?;
+                let (lock, _) = inodes.iter().find(|(_, ino)| *ino == inode)?;
+
+                Some((*lock, pid))
+            })
+            .collect()
+    }
+
+    fn command_of(pid: u32) -> String {
+        fs::read(format!("/proc/{pid}/cmdline"))
+            .map(|cmdline| {
+                cmdline
+                    .split(|byte| *byte == 0)
+                    .filter(|arg| !arg.is_empty())
+                    .map(String::from_utf8_lossy)
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .unwrap_or_default()
+    }
+
+    /// 等待其他进程释放锁，超过`deadline`仍被占用时报错
+    pub fn wait(runner: &dyn CommandRunner, deadline: Instant, timeout: Duration) -> Result<()> {
+        let mut reported = Vec::new();
+
+        loop {
+            let holders = runner.dpkg_lock_holders();
+            if holders.is_empty() {
+                return Ok(());
+            }
+
+            if Instant::now() >= deadline {
+                bail!(
+                    "Timeout after waiting {}s for dpkg locks: {}",
+                    timeout.as_secs(),
+                    holders
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+            }
+
+            for holder in holders.iter().filter(|holder| !reported.contains(*holder)) {
+                info!(target: "APT", "{holder}, waiting for it to finish...");
+            }
+            reported = holders;
+
+            thread::sleep(Self::POLL_INTERVAL.min(deadline - Instant::now()));
+        }
+    }
+
+    /// apt/dpkg的错误输出是否说明锁被占用
+    pub fn is_lock_error(err: &anyhow::Error) -> bool {
+        let message = format!("{err:#}");
+        message.contains("Could not get lock") || message.contains("Unable to acquire the dpkg")
+    }
+
+    /// dpkg上次运行是否被中断，此时apt会拒绝安装，需要`dpkg --configure -a`
+    pub fn interrupted() -> bool {
+        fs::read_dir(Self::DPKG_UPDATES)
+            .into_iter()
+            .flatten()
+            .filter_map(Result::ok)
+            .any(|entry| {
+                entry
+                    .file_name()
+                    .to_string_lossy()
+                    .bytes()
+                    .all(|byte| byte.is_ascii_digit())
+            })
+    }
+
+    /// dpkg被中断时，在终端中询问是否运行`dpkg --configure -a`恢复，返回要运行的命令
+    pub fn recovery(runner: &dyn CommandRunner) -> Result<Option<Command>> {
+        if !runner.dpkg_interrupted() {
+            return Ok(None);
+        }
+
+        warn!(target: "APT", "dpkg was interrupted, packages are left half configured");
+
+        if !std::io::stdin().is_terminal() {
+            bail!("dpkg was interrupted, run `sudo dpkg --configure -a` to recover");
+        }
+
+        eprint!("Run `dpkg --configure -a` to recover now? [y/N] ");
+        std::io::stderr().flush()?;
+
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+
+        if !matches!(answer.trim(), "y" | "Y" | "yes") {
+            bail!("dpkg was interrupted, run `sudo dpkg --configure -a` to recover");
+        }
+
+        let mut cmd = Command::new("dpkg");
+        cmd.arg("--configure")
+            .arg("-a")
+            .env("DEBIAN_FRONTEND", "noninteractive");
+        Ok(Some(cmd))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROC_LOCKS: &str = "\
+1: POSIX  ADVISORY  WRITE 4242 08:01:1311 0 EOF
+1: -> POSIX  ADVISORY  WRITE 5151 08:01:1311 0 EOF
+2: POSIX  ADVISORY  WRITE 4242 08:01:1309 0 EOF
+3: FLOCK  ADVISORY  WRITE 777 00:1a:2048 0 EOF
+4: OFDLCK ADVISORY  READ  -1 08:01:1310 0 EOF
+5: POSIX  ADVISORY  READ  888 fd:00:1312 128 128
+";
+
+    #[test]
+    fn parse_proc_locks() {
+        let inodes = [
+            ("/var/lib/dpkg/lock-frontend", 1311),
+            ("/var/lib/dpkg/lock", 1309),
+            ("/var/lib/apt/lists/lock", 1310),
+        ];
+
+        /* 等待者、OFD锁和其他文件的锁都被忽略 */
+        assert_eq!(
+            DpkgLock::parse_locks(PROC_LOCKS, &inodes),
+            [
+                ("/var/lib/dpkg/lock-frontend", 4242),
+                ("/var/lib/dpkg/lock", 4242)
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_proc_locks_without_dpkg_locks() {
+        assert!(DpkgLock::parse_locks(PROC_LOCKS, &[]).is_empty());
+        assert!(DpkgLock::parse_locks("", &[("/var/lib/dpkg/lock", 1309)]).is_empty());
+    }
+
+    #[test]
+    fn lock_errors() {
+        let err = anyhow::anyhow!(
+            "E: Could not get lock /var/lib/dpkg/lock-frontend. It is held by process 4242 (apt)"
+        )
+        .context("apt install fail");
+        assert!(DpkgLock::is_lock_error(&err));
+        assert!(!DpkgLock::is_lock_error(&anyhow::anyhow!(
+            "E: Unable to locate package tpyo"
+        )));
+    }
+}
@@ -1,8 +1,11 @@
+use crate::runner::CommandRunner;
 use anyhow::{Context, Ok, Result, ensure};
 use std::{
     fs::{self},
-    os::unix::fs::PermissionsExt,
+    io::Write,
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
     path::{Path, PathBuf},
+    process::Command,
 };
 
 #[derive(Debug)]
@@ -74,6 +77,32 @@ impl FileOp {
         Ok(dst_file.to_path_buf())
     }
 
+    /// 以root身份把文件安装到目标位置，用于写入/etc等需要权限的目录
+    pub fn install_as_root<P1, P2>(
+        runner: &dyn CommandRunner,
+        file: P1,
+        target: P2,
+        mode: u32,
+    ) -> Result<PathBuf>
+    where
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        let mut cmd = Command::new("install");
+        cmd.arg("-D")
+            .arg("-m")
+            .arg(format!("{mode:o}"))
+            .arg(file.as_ref())
+            .arg(target.as_ref());
+
+        runner.run_as_root(cmd).context(format!(
+            "Fail to install {}",
+            target.as_ref().to_string_lossy()
+        ))?;
+
+        Ok(target.as_ref().to_path_buf())
+    }
+
     pub fn write<P, B>(file: P, content: B, mode: Option<u32>) -> Result<PathBuf>
     where
         P: AsRef<Path>,
@@ -100,4 +129,35 @@ impl FileOp {
 
         Ok(file.as_ref().to_path_buf())
     }
+
+    /// 写入只有当前用户可读写(0600)的文件，用于解密后的secret，不会短暂地以默认权限存在
+    pub fn write_private<P, B>(file: P, content: B) -> Result<PathBuf>
+    where
+        P: AsRef<Path>,
+        B: AsRef<[u8]>,
+    {
+        let file = file.as_ref();
+
+        if let Some(dir) = file.parent()
+            && !dir.exists()
+        {
+            fs::create_dir_all(dir)
+                .context(format!("Fail to create dir {}", dir.to_string_lossy()))?;
+        }
+
+        /* 已存在的文件可能权限更宽，先删除再创建 */
+        if file.exists() {
+            fs::remove_file(file).context(format!("Fail to remove {}", file.to_string_lossy()))?;
+        }
+
+        fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(file)
+            .and_then(|mut opened| opened.write_all(content.as_ref()))
+            .context(format!("Fail to write file {}", file.to_string_lossy()))?;
+
+        Ok(file.to_path_buf())
+    }
 }
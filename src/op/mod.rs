@@ -1,3 +1,7 @@
 pub mod apt;
+pub mod bundle;
+pub mod dpkg;
 pub mod file;
 pub mod http;
+pub mod os;
+pub mod package;
@@ -0,0 +1,58 @@
+use anyhow::{Context, Result};
+use std::{collections::HashMap, fs};
+
+/// `/etc/os-release`中与发行版识别相关的字段
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OsRelease {
+    pub id: String,
+    pub id_like: Vec<String>,
+    pub version_id: Option<String>,
+    pub version_codename: Option<String>,
+}
+
+impl OsRelease {
+    const OS_RELEASE: [&str; 2] = ["/etc/os-release", "/usr/lib/os-release"];
+
+    pub fn current() -> Result<Self> {
+        let content = Self::OS_RELEASE
+            .iter()
+            .find_map(|path| fs::read_to_string(path).ok())
+            .context("Fail to read os-release")?;
+
+        Ok(Self::parse(&content))
+    }
+
+    pub fn parse(content: &str) -> Self {
+        let fields: HashMap<_, _> = content
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| {
+                (
+                    key.trim(),
+                    value
+                        .trim()
+                        .trim_matches('"')
+                        .trim_matches('\'')
+                        .to_string(),
+                )
+            })
+            .collect();
+
+        let field = |key: &str| fields.get(key).filter(|value| !value.is_empty()).cloned();
+
+        Self {
+            id: field("ID").unwrap_or_else(|| "linux".into()),
+            id_like: field("ID_LIKE")
+                .map(|like| like.split_whitespace().map(String::from).collect())
+                .unwrap_or_default(),
+            version_id: field("VERSION_ID"),
+            /* 部分发行版(如旧版Ubuntu)只有UBUNTU_CODENAME */
+            version_codename: field("VERSION_CODENAME").or_else(|| field("UBUNTU_CODENAME")),
+        }
+    }
+
+    /// 自身id及其`ID_LIKE`中的所有id
+    pub fn ids(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.id.as_str()).chain(self.id_like.iter().map(String::as_str))
+    }
+}
@@ -0,0 +1,590 @@
+use crate::op::apt::{AptOp, AptOptions, RefreshPolicy};
+use crate::op::os::OsRelease;
+use crate::runner::CommandRunner;
+use anyhow::{Context, Ok, Result, anyhow, ensure};
+use log::info;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Debug, Display};
+use std::path::PathBuf;
+use std::process::Command;
+use std::rc::Rc;
+use std::time::Duration;
+
+/// 支持的包管理器
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Backend {
+    Apt,
+    Dnf,
+    Pacman,
+    Apk,
+    Zypper,
+}
+
+impl Backend {
+    const ALL: [Backend; 5] = [
+        Backend::Apt,
+        Backend::Dnf,
+        Backend::Pacman,
+        Backend::Apk,
+        Backend::Zypper,
+    ];
+
+    /// 优先根据`/etc/os-release`识别，识别不出时按顺序在PATH中查找包管理器
+    pub fn detect(runner: &dyn CommandRunner) -> Option<Self> {
+        let from_os = runner
+            .os_release()
+            .ok()
+            .and_then(|os| os.ids().find_map(Self::from_distro_id));
+
+        from_os.or_else(|| {
+            Self::ALL
+                .into_iter()
+                .find(|backend| runner.which(backend.program()).is_ok())
+        })
+    }
+
+    pub fn from_distro_id(id: &str) -> Option<Self> {
+        match id {
+            "debian" | "ubuntu" => Some(Backend::Apt),
+            "fedora" | "rhel" | "centos" | "rocky" | "almalinux" => Some(Backend::Dnf),
+            "arch" | "archlinux" | "manjaro" => Some(Backend::Pacman),
+            "alpine" => Some(Backend::Apk),
+            "opensuse" | "suse" | "sles" | "opensuse-leap" | "opensuse-tumbleweed" => {
+                Some(Backend::Zypper)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn program(&self) -> &'static str {
+        match self {
+            Backend::Apt => "apt",
+            Backend::Dnf => "dnf",
+            Backend::Pacman => "pacman",
+            Backend::Apk => "apk",
+            Backend::Zypper => "zypper",
+        }
+    }
+}
+
+impl Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.program())
+    }
+}
+
+pub trait PackageManager: Debug {
+    /// 检查软件包是否可以安装
+    fn check(&self, name: &str) -> Result<()>;
+
+    fn install(&self, name: &str) -> Result<()>;
+
+    fn remove(&self, name: &str) -> Result<()>;
+}
+
+/// 除apt以外的包管理器，只是命令行参数不同
+pub struct GenericOp {
+    runner: Rc<dyn CommandRunner>,
+    backend: Backend,
+    program: PathBuf,
+}
+
+impl GenericOp {
+    pub fn new(backend: Backend, runner: Rc<dyn CommandRunner>) -> Result<Self> {
+        let op = Self {
+            backend,
+            program: runner.which(backend.program())?,
+            runner,
+        };
+
+        op.refresh()
+            .context(format!("{backend} fail to refresh package index"))?;
+
+        Ok(op)
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let mut cmd = Command::new(&self.program);
+        cmd.args(args);
+        cmd
+    }
+
+    /// pacman不刷新数据库: `-Sy`之后只安装部分软件包是Arch不支持的部分升级，
+    /// 数据库过期时安装会失败并提示先升级系统
+    fn refresh(&self) -> Result<()> {
+        let args: &[&str] = match self.backend {
+            Backend::Apt => unreachable!("apt is handled by AptOp"),
+            Backend::Dnf => &["-q", "makecache"],
+            Backend::Pacman => return Ok(()),
+            Backend::Apk => &["update"],
+            Backend::Zypper => &["--non-interactive", "refresh"],
+        };
+
+        self.runner.run_as_root(self.command(args))?;
+        Ok(())
+    }
+}
+
+impl PackageManager for GenericOp {
+    fn check(&self, name: &str) -> Result<()> {
+        let args: &[&str] = match self.backend {
+            Backend::Apt => unreachable!("apt is handled by AptOp"),
+            Backend::Dnf => &["-q", "info"],
+            Backend::Pacman => &["-Si"],
+            Backend::Apk => &["search", "-e"],
+            Backend::Zypper => &["--non-interactive", "search", "-x"],
+        };
+
+        let mut cmd = self.command(args);
+        cmd.arg(name);
+
+        let output = self
+            .runner
+            .run(cmd)
+            .map_err(|_| anyhow!("Con not find package: {name}"))?;
+
+        /* apk search找不到包时也会返回0 */
+        if self.backend == Backend::Apk && output.trim().is_empty() {
+            return Err(anyhow!("Con not find package: {name}"));
+        }
+
+        Ok(())
+    }
+
+    fn install(&self, name: &str) -> Result<()> {
+        let args: &[&str] = match self.backend {
+            Backend::Apt => unreachable!("apt is handled by AptOp"),
+            Backend::Dnf => &["install", "-y"],
+            Backend::Pacman => &["-S", "--noconfirm", "--needed"],
+            Backend::Apk => &["add"],
+            Backend::Zypper => &["--non-interactive", "install"],
+        };
+
+        let mut cmd = self.command(args);
+        cmd.arg(name);
+
+        match self.runner.run_as_root(cmd) {
+            /* 数据库中的版本已经从镜像中删除 */
+            Err(err)
+                if self.backend == Backend::Pacman
+                    && format!("{err:#}").contains("failed retrieving file") =>
+            {
+                Err(err.context(format!(
+                    "pacman database is out of date, run `sudo pacman -Syu` before installing {name}"
+                )))
+            }
+            result => {
+                result.context(format!("{} install fail", self.backend))?;
+                Ok(())
+            }
+        }
+    }
+
+    fn remove(&self, name: &str) -> Result<()> {
+        let args: &[&str] = match self.backend {
+            Backend::Apt => unreachable!("apt is handled by AptOp"),
+            Backend::Dnf => &["remove", "-y"],
+            Backend::Pacman => &["-Rns", "--noconfirm"],
+            Backend::Apk => &["del"],
+            Backend::Zypper => &["--non-interactive", "remove"],
+        };
+
+        let mut cmd = self.command(args);
+        cmd.arg(name);
+
+        self.runner
+            .run_as_root(cmd)
+            .context(format!("Fail to remove package: {name}"))?;
+        Ok(())
+    }
+}
+
+impl Debug for GenericOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GenericOp")
+            .field("backend", &self.backend)
+            .field("program", &self.program)
+            .finish()
+    }
+}
+
+/// 包管理器的集合，由部署器持有，各个包管理器在第一次使用时才初始化(刷新软件源)
+#[derive(Debug)]
+pub struct Packages {
+    host: Option<Backend>,
+    os: Option<OsRelease>,
+    runner: Rc<dyn CommandRunner>,
+    apt: RefCell<Option<AptOp>>,
+    /// `with_apt`正在运行，AptOp已从`apt`中取出
+    apt_in_use: Cell<bool>,
+    apt_options: RefCell<AptOptions>,
+    /// 将在安装阶段添加的apt软件源，以及同一配置中可能来自这些软件源的软件包
+    apt_sources_pending: RefCell<HashMap<String, Vec<String>>>,
+    /// 添加软件源后软件包列表需要更新，在下一次使用apt之前更新
+    apt_dirty: Cell<bool>,
+    others: RefCell<HashMap<Backend, GenericOp>>,
+    /// `with`正在使用的其它包管理器，已从`others`中取出
+    others_in_use: RefCell<HashSet<Backend>>,
+}
+
+impl Packages {
+    pub fn detect(runner: Rc<dyn CommandRunner>) -> Self {
+        let host = Backend::detect(runner.as_ref());
+        info!(target: "Packages", "host package manager: {host:?}");
+
+        Self::new(host, runner)
+    }
+
+    pub fn new(host: Option<Backend>, runner: Rc<dyn CommandRunner>) -> Self {
+        Self {
+            host,
+            os: runner.os_release().ok(),
+            runner,
+            apt: Default::default(),
+            apt_in_use: Default::default(),
+            apt_options: Default::default(),
+            apt_sources_pending: Default::default(),
+            apt_dirty: Default::default(),
+            others: Default::default(),
+            others_in_use: Default::default(),
+        }
+    }
+
+    pub fn runner(&self) -> &dyn CommandRunner {
+        self.runner.as_ref()
+    }
+
+    /// 当前系统使用的包管理器
+    pub fn host(&self) -> Option<Backend> {
+        self.host
+    }
+
+    /// 当前系统中软件包可能的名字，按优先级排序
+    ///
+    /// `distro`中匹配当前发行版的名字优先，依次尝试`<id>:<版本代号>`、`<id>:<版本号>`、`<id>`，
+    /// id先匹配自身的`ID`再匹配`ID_LIKE`；之后是`name`和`alternatives`
+    pub fn candidates(
+        &self,
+        name: &str,
+        alternatives: Option<&Vec<String>>,
+        distro: Option<&HashMap<String, String>>,
+    ) -> Vec<String> {
+        let mapped = self.os.as_ref().zip(distro).and_then(|(os, distro)| {
+            os.ids().find_map(|id| {
+                [&os.version_codename, &os.version_id]
+                    .into_iter()
+                    .flatten()
+                    .map(|version| format!("{id}:{version}"))
+                    .chain(std::iter::once(id.to_string()))
+                    .find_map(|key| distro.get(&key))
+            })
+        });
+
+        let mut candidates: Vec<String> = Vec::new();
+        for candidate in mapped
+            .map(String::as_str)
+            .into_iter()
+            .chain(std::iter::once(name))
+            .chain(alternatives.into_iter().flatten().map(String::as_str))
+        {
+            if !candidates.iter().any(|c| c == candidate) {
+                candidates.push(candidate.to_string());
+            }
+        }
+
+        candidates
+    }
+
+    /// 当前系统的发行版描述，用于日志
+    pub fn distro(&self) -> String {
+        match &self.os {
+            Some(OsRelease {
+                id,
+                version_codename: Some(codename),
+                ..
+            }) => format!("{id} {codename}"),
+            Some(os) => os.id.clone(),
+            None => "unknown distro".into(),
+        }
+    }
+
+    /// 在`f`中使用apt，`f`运行期间AptOp从`Packages`中取出，`f`中仍然可以调用`Packages`的其它方法，
+    /// 但不能再次使用apt
+    pub fn with_apt<R>(&self, f: impl FnOnce(&mut AptOp) -> Result<R>) -> Result<R> {
+        ensure!(!self.apt_in_use.get(), "apt is already in use");
+        let dirty = self.apt_dirty.replace(false);
+
+        let mut apt = match self.apt.take() {
+            Some(mut apt) => {
+                if dirty && let Err(err) = apt.refresh() {
+                    self.apt.replace(Some(apt));
+                    return Err(err.context("Fail to update apt after adding repositories"));
+                }
+                apt
+            }
+            None => {
+                let mut options = self.apt_options.borrow().clone();
+                /* 新添加的软件源还没有软件包列表，即使其他列表是新的也要更新 */
+                if dirty {
+                    options.refresh = RefreshPolicy::Always;
+                }
+                AptOp::new(options, self.runner.clone())?
+            }
+        };
+
+        self.apt_in_use.set(true);
+        let result = f(&mut apt);
+        self.apt_in_use.set(false);
+        self.apt.replace(Some(apt));
+
+        result
+    }
+
+    /// 设置apt初始化时是否需要apt update
+    pub fn set_apt_refresh(&self, policy: RefreshPolicy) {
+        self.apt_options.borrow_mut().refresh = policy;
+    }
+
+    /// 设置apt等待dpkg锁的最长时间
+    pub fn set_apt_lock_timeout(&self, timeout: Duration) {
+        self.apt_options.borrow_mut().lock_timeout = timeout;
+    }
+
+    /// 设置离线软件包目录，apt初始化时会把它添加为优先的软件源
+    pub fn set_apt_bundle(&self, dir: PathBuf) {
+        self.apt_options.borrow_mut().bundle = Some(dir);
+    }
+
+    /// 标记软件源`repo`将在安装阶段添加，`packages`中的软件包可能来自这个软件源，在检查阶段还找不到
+    pub fn expect_apt_source(&self, repo: &str, packages: Vec<String>) {
+        self.apt_sources_pending
+            .borrow_mut()
+            .insert(repo.to_string(), packages);
+    }
+
+    /// 可能提供软件包`name`且还未添加的软件源
+    pub fn pending_apt_source(&self, name: &str) -> Option<String> {
+        self.apt_sources_pending
+            .borrow()
+            .iter()
+            .find(|(_, packages)| packages.iter().any(|package| package == name))
+            .map(|(repo, _)| repo.clone())
+    }
+
+    /// 软件源`repo`已经添加，软件包列表在下一次使用apt之前统一更新，连续添加多个软件源时只更新一次
+    pub fn apt_source_added(&self, repo: &str) {
+        self.apt_sources_pending.borrow_mut().remove(repo);
+        self.apt_dirty.set(true);
+    }
+
+    /// 安装apt队列中的软件包，apt未初始化时说明没有需要安装的apt软件包
+    pub fn commit_apt(&self) -> Result<()> {
+        if self.apt.borrow().is_none() && !self.apt_in_use.get() {
+            return Ok(());
+        }
+
+        self.with_apt(|apt| apt.commit())
+    }
+
+    /// 在`f`中使用`backend`，与`with_apt`一样，`f`运行期间包管理器从`Packages`中取出，
+    /// 不能再次使用同一个包管理器
+    pub fn with<R>(
+        &self,
+        backend: Backend,
+        f: impl FnOnce(&mut dyn PackageManager) -> Result<R>,
+    ) -> Result<R> {
+        if backend == Backend::Apt {
+            return self.with_apt(|apt| f(apt));
+        }
+
+        ensure!(
+            !self.others_in_use.borrow().contains(&backend),
+            "{backend} is already in use"
+        );
+
+        let taken = self.others.borrow_mut().remove(&backend);
+        let mut manager = match taken {
+            Some(manager) => manager,
+            None => GenericOp::new(backend, self.runner.clone())?,
+        };
+
+        self.others_in_use.borrow_mut().insert(backend);
+        let result = f(&mut manager);
+        self.others_in_use.borrow_mut().remove(&backend);
+        self.others.borrow_mut().insert(backend, manager);
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::fake::FakeApt;
+    use tempfile::TempDir;
+
+    #[test]
+    fn packages_are_usable_while_apt_is_in_use() {
+        let home = TempDir::new().unwrap();
+        let fake = Rc::new(
+            FakeApt::new()
+                .with_package("tree", "2.1.0")
+                .with_home(home.path()),
+        );
+        let packages = Packages::detect(fake);
+
+        packages
+            .with_apt(|apt| {
+                packages.expect_apt_source("repo", vec!["from-repo".into()]);
+                assert_eq!(
+                    packages.pending_apt_source("from-repo").as_deref(),
+                    Some("repo")
+                );
+                assert!(packages.with_apt(|_| Ok(())).is_err());
+                apt.check("tree")
+            })
+            .unwrap();
+
+        /* 嵌套的调用失败后apt仍然可用 */
+        packages
+            .with(Backend::Apt, |apt| apt.check("tree"))
+            .unwrap();
+    }
+
+    #[test]
+    fn candidates_prefer_the_most_specific_distro_name() {
+        let packages = Packages::new(None, Rc::new(FakeApt::new()));
+        let alternatives = vec!["fd".to_string()];
+        let candidates = |distro: &[(&str, &str)]| {
+            let distro: HashMap<_, _> = distro
+                .iter()
+                .map(|(key, name)| (key.to_string(), name.to_string()))
+                .collect();
+            packages.candidates("fd-find", Some(&alternatives), Some(&distro))
+        };
+
+        assert_eq!(
+            candidates(&[
+                ("debian", "fd-debian"),
+                ("debian:12", "fd-12"),
+                ("debian:bookworm", "fd-bookworm"),
+            ]),
+            ["fd-bookworm", "fd-find", "fd"]
+        );
+        assert_eq!(
+            candidates(&[("debian", "fd-debian"), ("debian:12", "fd-12")]),
+            ["fd-12", "fd-find", "fd"]
+        );
+        assert_eq!(
+            candidates(&[("debian", "fd-debian"), ("fedora", "fd-fedora")]),
+            ["fd-debian", "fd-find", "fd"]
+        );
+        /* 映射的名字与`name`相同时不重复 */
+        assert_eq!(candidates(&[("debian", "fd-find")]), ["fd-find", "fd"]);
+        assert_eq!(candidates(&[("fedora", "fd-fedora")]), ["fd-find", "fd"]);
+        assert_eq!(packages.candidates("fd-find", None, None), ["fd-find"]);
+    }
+
+    #[test]
+    fn backend_is_detected_from_os_release() {
+        let detect =
+            |os_release: &str| Backend::detect(&FakeApt::new().with_os_release(os_release));
+
+        assert_eq!(detect("ID=debian\n"), Some(Backend::Apt));
+        assert_eq!(detect("ID=fedora\n"), Some(Backend::Dnf));
+        assert_eq!(
+            detect("ID=linuxmint\nID_LIKE=\"ubuntu debian\"\n"),
+            Some(Backend::Apt)
+        );
+        assert_eq!(
+            detect("ID=endeavouros\nID_LIKE=arch\n"),
+            Some(Backend::Pacman)
+        );
+        assert_eq!(
+            detect("ID=opensuse-microos\nID_LIKE=\"suse opensuse\"\n"),
+            Some(Backend::Zypper)
+        );
+        /* 不认识的发行版在PATH中查找，FakeApt能找到所有程序 */
+        assert_eq!(detect("ID=nixos\n"), Some(Backend::Apt));
+        assert_eq!(Backend::from_distro_id("nixos"), None);
+    }
+
+    #[test]
+    fn generic_commands_per_backend() {
+        for (backend, found, expected) in [
+            (
+                Backend::Dnf,
+                true,
+                vec![
+                    "dnf -q makecache",
+                    "dnf -q info jq",
+                    "dnf install -y jq",
+                    "dnf remove -y jq",
+                ],
+            ),
+            (
+                Backend::Pacman,
+                true,
+                vec![
+                    "pacman -Si jq",
+                    "pacman -S --noconfirm --needed jq",
+                    "pacman -Rns --noconfirm jq",
+                ],
+            ),
+            /* FakeApt的输出为空，apk search找不到 */
+            (
+                Backend::Apk,
+                false,
+                vec!["apk update", "apk search -e jq", "apk add jq", "apk del jq"],
+            ),
+            (
+                Backend::Zypper,
+                true,
+                vec![
+                    "zypper --non-interactive refresh",
+                    "zypper --non-interactive search -x jq",
+                    "zypper --non-interactive install jq",
+                    "zypper --non-interactive remove jq",
+                ],
+            ),
+        ] {
+            let fake = Rc::new(FakeApt::new());
+            let packages = Packages::new(None, fake.clone());
+
+            let checked = packages.with(backend, |manager| manager.check("jq"));
+            assert_eq!(checked.is_ok(), found, "{backend}");
+            packages
+                .with(backend, |manager| {
+                    manager.install("jq")?;
+                    manager.remove("jq")
+                })
+                .unwrap();
+
+            assert_eq!(fake.commands(), expected, "{backend}");
+        }
+    }
+
+    #[test]
+    fn generic_manager_is_not_reentrant() {
+        let fake = Rc::new(FakeApt::new());
+        let packages = Packages::new(None, fake.clone());
+
+        packages
+            .with(Backend::Dnf, |dnf| {
+                assert!(packages.with(Backend::Dnf, |_| Ok(())).is_err());
+                packages.with(Backend::Zypper, |zypper| zypper.check("jq"))?;
+                dnf.check("jq")
+            })
+            .unwrap();
+
+        /* 嵌套的调用失败后dnf仍然可用，也不会重新刷新 */
+        packages.with(Backend::Dnf, |dnf| dnf.check("jq")).unwrap();
+        let refreshes = fake
+            .commands()
+            .iter()
+            .filter(|cmd| cmd.as_str() == "dnf -q makecache")
+            .count();
+        assert_eq!(refreshes, 1);
+    }
+}
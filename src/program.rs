@@ -1,6 +1,9 @@
 use anyhow::{Ok, Result, ensure};
 use log::info;
-use std::{collections::HashMap, ffi::OsStr, fs, io::Read, path::PathBuf, process::Command};
+use std::{
+    collections::HashMap, ffi::OsStr, fs, io::Read, os::unix::fs::MetadataExt, path::PathBuf,
+    process::Command,
+};
 use tempfile::NamedTempFile;
 use which::which;
 
@@ -83,6 +86,31 @@ pub fn run_or_sudo(mut cmd: Command) -> Result<String> {
     Ok(stdout)
 }
 
+/// 以root身份运行命令，当前用户不是root时通过sudo运行
+pub fn run_as_root(cmd: Command) -> Result<String> {
+    if is_root() {
+        return run_command(cmd);
+    }
+
+    let mut sudo_cmd = Command::new("sudo");
+    sudo_cmd
+        .arg("--")
+        .arg(cmd.get_program())
+        .args(cmd.get_args());
+
+    let cmd_envs: HashMap<_, _> = cmd
+        .get_envs()
+        .filter_map(|(k, v)| v.map(|v| (k, v)))
+        .collect();
+    sudo_cmd.envs(cmd_envs);
+
+    run_command(sudo_cmd)
+}
+
+pub fn is_root() -> bool {
+    fs::metadata("/proc/self").is_ok_and(|meta| meta.uid() == 0)
+}
+
 pub fn run_command(mut cmd: Command) -> Result<String> {
     let mut stdout_file = NamedTempFile::new()?;
     let mut stderr_file = NamedTempFile::new()?;
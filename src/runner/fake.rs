@@ -0,0 +1,431 @@
+use crate::op::dpkg::LockHolder;
+use crate::op::os::OsRelease;
+use crate::runner::CommandRunner;
+use anyhow::{Result, anyhow, ensure};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// 假的软件包
+#[derive(Debug, Clone, Default)]
+pub struct FakePackage {
+    pub version: String,
+    pub installed: bool,
+    pub held: bool,
+    /// 该软件包提供的虚包
+    pub provides: Vec<String>,
+    /// 提供该软件包的软件源，软件源添加并apt update之后才能找到
+    pub source: Option<String>,
+}
+
+/// 用内存中的软件包数据库模拟apt、apt-cache、dpkg、dpkg-query、dpkg-deb和apt-mark，`install`到
+/// sources.list.d的文件视为添加软件源，其它命令都只记录不执行，直接返回成功
+///
+/// 用于在没有root、apt和网络的环境下完整地运行部署流程，主目录和缓存目录都在`home`中，
+/// 不会读写真实系统的状态
+#[derive(Debug)]
+pub struct FakeApt {
+    arch: String,
+    /// /etc/os-release的内容
+    os_release: String,
+    home: Option<PathBuf>,
+    /// 上一次apt update的时间
+    lists_updated: Cell<Option<SystemTime>>,
+    /// 每次安装或移除软件包后增加，作为dpkg状态的修改时间
+    dpkg_changes: Cell<u64>,
+    packages: RefCell<BTreeMap<String, FakePackage>>,
+    commands: RefCell<Vec<String>>,
+    failing: RefCell<Vec<String>>,
+    /// debconf数据库: question -> value
+    debconf: RefCell<BTreeMap<String, String>>,
+    /// 已添加的软件源，以及其中已经apt update过的软件源
+    sources: RefCell<Vec<String>>,
+    fetched: RefCell<Vec<String>>,
+}
+
+impl Default for FakeApt {
+    fn default() -> Self {
+        Self {
+            arch: "amd64".into(),
+            os_release: "ID=debian\nVERSION_ID=\"12\"\nVERSION_CODENAME=bookworm\n".into(),
+            home: None,
+            lists_updated: Default::default(),
+            dpkg_changes: Default::default(),
+            packages: Default::default(),
+            commands: Default::default(),
+            failing: Default::default(),
+            debconf: Default::default(),
+            sources: Default::default(),
+            fetched: Default::default(),
+        }
+    }
+}
+
+impl FakeApt {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 添加一个可以安装的软件包
+    pub fn with_package<N, V>(self, name: N, version: V) -> Self
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        self.packages.borrow_mut().insert(
+            name.into(),
+            FakePackage {
+                version: version.into(),
+                ..Default::default()
+            },
+        );
+        self
+    }
+
+    /// 添加一个已经安装的软件包
+    pub fn with_installed<N, V>(self, name: N, version: V) -> Self
+    where
+        N: Into<String>,
+        V: Into<String>,
+    {
+        self.packages.borrow_mut().insert(
+            name.into(),
+            FakePackage {
+                version: version.into(),
+                installed: true,
+                ..Default::default()
+            },
+        );
+        self
+    }
+
+    /// 添加一个提供虚包`virtual_name`的软件包
+    pub fn with_provider<N, V, P>(self, name: N, version: V, virtual_name: P) -> Self
+    where
+        N: Into<String>,
+        V: Into<String>,
+        P: Into<String>,
+    {
+        self.packages.borrow_mut().insert(
+            name.into(),
+            FakePackage {
+                version: version.into(),
+                provides: vec![virtual_name.into()],
+                ..Default::default()
+            },
+        );
+        self
+    }
+
+    /// 添加一个由软件源`source`提供的软件包，添加该软件源之前找不到它
+    pub fn with_source_package<S, N, V>(self, source: S, name: N, version: V) -> Self
+    where
+        S: Into<String>,
+        N: Into<String>,
+        V: Into<String>,
+    {
+        self.packages.borrow_mut().insert(
+            name.into(),
+            FakePackage {
+                version: version.into(),
+                source: Some(source.into()),
+                ..Default::default()
+            },
+        );
+        self
+    }
+
+    /// 使用`home`作为主目录，部署目录、.bashrc和缓存都写在其中
+    pub fn with_home<P: AsRef<Path>>(mut self, home: P) -> Self {
+        self.home = Some(home.as_ref().to_path_buf());
+        self
+    }
+
+    /// 使用其它发行版的/etc/os-release，默认是Debian 12
+    pub fn with_os_release<S: Into<String>>(mut self, content: S) -> Self {
+        self.os_release = content.into();
+        self
+    }
+
+    /// 上一次apt update的时间，默认从未更新过
+    pub fn with_lists_updated(self, updated: SystemTime) -> Self {
+        self.lists_updated.set(Some(updated));
+        self
+    }
+
+    /// 让包含该软件包的apt install失败，用于模拟有问题的软件包
+    pub fn with_failing<N: Into<String>>(self, name: N) -> Self {
+        self.failing.borrow_mut().push(name.into());
+        self
+    }
+
+    /// 运行过的所有命令，格式为`program arg1 arg2`
+    pub fn commands(&self) -> Vec<String> {
+        self.commands.borrow().clone()
+    }
+
+    pub fn package(&self, name: &str) -> Option<FakePackage> {
+        self.packages.borrow().get(name).cloned()
+    }
+
+    pub fn is_installed(&self, name: &str) -> bool {
+        self.package(name).is_some_and(|package| package.installed)
+    }
+
+    /// debconf数据库中问题的答案
+    pub fn debconf(&self, question: &str) -> Option<String> {
+        self.debconf.borrow().get(question).cloned()
+    }
+
+    /// 软件包是否在已更新的软件包列表中
+    fn available(&self, package: &FakePackage) -> bool {
+        package
+            .source
+            .as_ref()
+            .is_none_or(|source| self.fetched.borrow().contains(source))
+    }
+
+    fn program(cmd: &Command) -> String {
+        Path::new(cmd.get_program())
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    fn args(cmd: &Command) -> Vec<String> {
+        cmd.get_args()
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect()
+    }
+
+    fn dpkg_query(&self) -> String {
+        self.packages
+            .borrow()
+            .iter()
+            .filter(|(_, package)| package.installed)
+            .map(|(name, package)| {
+                format!("{name}\t{}\t{}\tinstalled\n", self.arch, package.version)
+            })
+            .collect()
+    }
+
+    fn policy(&self, name: &str) -> String {
+        let packages = self.packages.borrow();
+        let Some(package) = packages
+            .get(name.split(':').next().unwrap_or(name))
+            .filter(|package| self.available(package))
+        else {
+            return String::new();
+        };
+
+        let installed = match package.installed {
+            true => package.version.as_str(),
+            false => "(none)",
+        };
+
+        format!(
+            "{name}:\n  Installed: {installed}\n  Candidate: {0}\n  Version table:\n     {0} 500\n        500 fake://archive stable/main {1} Packages\n",
+            package.version, self.arch
+        )
+    }
+
+    fn providers(&self, name: &str) -> Vec<String> {
+        self.packages
+            .borrow()
+            .iter()
+            .filter(|(_, package)| self.available(package))
+            .filter(|(_, package)| package.provides.iter().any(|p| p == name))
+            .map(|(provider, _)| provider.clone())
+            .collect()
+    }
+
+    fn showpkg(&self, name: &str) -> String {
+        let providers: String = self
+            .providers(name)
+            .iter()
+            .map(|provider| format!("{provider} (= )\n"))
+            .collect();
+
+        format!("Package: {name}\nReverse Provides: \n{providers}")
+    }
+
+    fn apt(&self, args: &[String]) -> Result<String> {
+        match args.first().map(String::as_str) {
+            Some("update") => {
+                *self.fetched.borrow_mut() = self.sources.borrow().clone();
+                self.lists_updated.set(Some(SystemTime::now()));
+                Ok(String::new())
+            }
+            Some("install") => {
+                let mut skip_value = false;
+                let mut names = Vec::new();
+                for arg in &args[1..] {
+                    if skip_value {
+                        skip_value = false;
+                    } else if arg == "-t" {
+                        skip_value = true;
+                    } else if !arg.starts_with('-') {
+                        names.push(arg.split('=').next().unwrap_or(arg).to_string());
+                    }
+                }
+
+                /* 只有一个软件包提供的虚包由apt自动选择该软件包 */
+                let names: Vec<String> = names
+                    .into_iter()
+                    .map(|name| {
+                        let providers = self.providers(&name);
+                        match providers[..] {
+                            [ref provider] if !self.packages.borrow().contains_key(&name) => {
+                                provider.clone()
+                            }
+                            _ => name,
+                        }
+                    })
+                    .collect();
+
+                for name in &names {
+                    ensure!(
+                        !self.failing.borrow().contains(name),
+                        "E: fake failure of {name}"
+                    );
+                    ensure!(
+                        self.packages
+                            .borrow()
+                            .get(name)
+                            .is_some_and(|package| self.available(package)),
+                        "E: Unable to locate package {name}"
+                    );
+                }
+
+                for name in &names {
+                    if let Some(package) = self.packages.borrow_mut().get_mut(name) {
+                        package.installed = true;
+                    }
+                }
+                self.dpkg_changes.set(self.dpkg_changes.get() + 1);
+
+                Ok(String::new())
+            }
+            Some("remove") => {
+                for name in args[1..].iter().filter(|arg| !arg.starts_with('-')) {
+                    if let Some(package) = self.packages.borrow_mut().get_mut(name) {
+                        package.installed = false;
+                    }
+                }
+                self.dpkg_changes.set(self.dpkg_changes.get() + 1);
+                Ok(String::new())
+            }
+            _ => Err(anyhow!("fake apt does not support {args:?}")),
+        }
+    }
+}
+
+impl CommandRunner for FakeApt {
+    fn run(&self, cmd: Command) -> Result<String> {
+        let (program, args) = (Self::program(&cmd), Self::args(&cmd));
+        self.commands
+            .borrow_mut()
+            .push(format!("{program} {}", args.join(" ")).trim().to_string());
+
+        let arg = |index: usize| args.get(index).map(String::as_str).unwrap_or_default();
+
+        match program.as_str() {
+            "apt" => self.apt(&args),
+            "dpkg" if arg(0) == "--print-architecture" => Ok(format!("{}\n", self.arch)),
+            "dpkg-query" => Ok(self.dpkg_query()),
+            /* .deb文件的内容就是它的控制字段 */
+            "dpkg-deb" if arg(0) == "-f" => Ok(fs::read_to_string(arg(1))?),
+            "apt-cache" if arg(0) == "policy" => Ok(self.policy(arg(1))),
+            "apt-cache" if arg(0) == "showpkg" => Ok(self.showpkg(arg(1))),
+            "apt-mark" if arg(0) == "showhold" => Ok(self
+                .packages
+                .borrow()
+                .iter()
+                .filter(|(_, package)| package.held)
+                .map(|(name, _)| format!("{name}\n"))
+                .collect()),
+            "apt-mark" if arg(0) == "hold" => {
+                for name in &args[1..] {
+                    if let Some(package) = self.packages.borrow_mut().get_mut(name) {
+                        package.held = true;
+                    }
+                }
+                Ok(String::new())
+            }
+            "install" if arg(args.len().saturating_sub(1)).ends_with(".sources") => {
+                let sources = Path::new(arg(args.len() - 1));
+                if let Some(name) = sources.file_stem() {
+                    self.sources
+                        .borrow_mut()
+                        .push(name.to_string_lossy().to_string());
+                }
+                Ok(String::new())
+            }
+            "debconf-set-selections" => {
+                for line in fs::read_to_string(arg(0))?.lines() {
+                    if let [_, question, _, value @ ..] =
+                        &line.split_whitespace().collect::<Vec<_>>()[..]
+                    {
+                        self.debconf
+                            .borrow_mut()
+                            .insert(question.to_string(), value.join(" "));
+                    }
+                }
+                Ok(String::new())
+            }
+            "debconf-show" => Ok(self
+                .debconf
+                .borrow()
+                .iter()
+                .filter(|(question, _)| question.starts_with(&format!("{}/", arg(0))))
+                .map(|(question, value)| format!("* {question}: {value}\n"))
+                .collect()),
+            _ => Ok(String::new()),
+        }
+    }
+
+    fn run_as_root(&self, cmd: Command) -> Result<String> {
+        self.run(cmd)
+    }
+
+    fn run_or_sudo(&self, cmd: Command) -> Result<String> {
+        self.run(cmd)
+    }
+
+    fn which(&self, program: &str) -> Result<PathBuf> {
+        Ok(PathBuf::from(program))
+    }
+
+    fn os_release(&self) -> Result<OsRelease> {
+        Ok(OsRelease::parse(&self.os_release))
+    }
+
+    fn home_dir(&self) -> Result<PathBuf> {
+        self.home
+            .clone()
+            .ok_or(anyhow!("Home dir of fake apt is not set"))
+    }
+
+    fn cache_dir(&self) -> Option<PathBuf> {
+        self.home.as_ref().map(|home| home.join(".cache"))
+    }
+
+    fn apt_lists_updated(&self) -> Option<SystemTime> {
+        self.lists_updated.get()
+    }
+
+    fn dpkg_status_modified(&self) -> Option<SystemTime> {
+        Some(UNIX_EPOCH + Duration::from_secs(self.dpkg_changes.get()))
+    }
+
+    fn dpkg_lock_holders(&self) -> Vec<LockHolder> {
+        Vec::new()
+    }
+
+    fn dpkg_interrupted(&self) -> bool {
+        false
+    }
+}
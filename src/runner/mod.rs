@@ -0,0 +1,90 @@
+use crate::op::apt::AptOp;
+use crate::op::dpkg::{DpkgLock, LockHolder};
+use crate::op::os::OsRelease;
+use crate::program::{run_as_root, run_command, run_or_sudo};
+use anyhow::{Result, anyhow};
+use std::fmt::Debug;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::SystemTime;
+
+pub mod fake;
+
+/// 外部命令的执行方式，部署器和包管理器都通过它来运行命令，便于在测试中替换
+pub trait CommandRunner: Debug {
+    /// 运行命令，返回标准输出，命令失败时返回错误
+    fn run(&self, cmd: Command) -> Result<String>;
+
+    /// 以root身份运行命令
+    fn run_as_root(&self, cmd: Command) -> Result<String>;
+
+    /// 运行命令，apt因权限不足失败时通过sudo重试
+    fn run_or_sudo(&self, cmd: Command) -> Result<String>;
+
+    /// 只关心命令是否成功，如`install_while`的检查
+    fn success(&self, cmd: Command) -> bool {
+        self.run(cmd).is_ok()
+    }
+
+    /// 在PATH中查找程序
+    fn which(&self, program: &str) -> Result<PathBuf> {
+        Ok(which::which(program)?)
+    }
+
+    /// 当前系统的发行版信息
+    fn os_release(&self) -> Result<OsRelease> {
+        OsRelease::current()
+    }
+
+    /// 当前用户的主目录，部署目录和.bashrc都在其中
+    fn home_dir(&self) -> Result<PathBuf> {
+        dirs::home_dir().ok_or(anyhow!("Fail to get home dir"))
+    }
+
+    /// 缓存目录，为None时不使用磁盘缓存
+    fn cache_dir(&self) -> Option<PathBuf> {
+        dirs::cache_dir()
+    }
+
+    /// apt软件包列表最后一次更新的时间，从未更新过时为None
+    fn apt_lists_updated(&self) -> Option<SystemTime> {
+        AptOp::lists_updated()
+    }
+
+    /// dpkg状态最后一次变化的时间，用于判断已安装软件包的缓存是否失效
+    fn dpkg_status_modified(&self) -> Option<SystemTime> {
+        AptOp::status_modified()
+    }
+
+    /// 当前持有dpkg/apt锁的进程
+    fn dpkg_lock_holders(&self) -> Vec<LockHolder> {
+        DpkgLock::holders()
+    }
+
+    /// dpkg上次运行是否被中断
+    fn dpkg_interrupted(&self) -> bool {
+        DpkgLock::interrupted()
+    }
+}
+
+/// 直接在当前系统上运行命令
+#[derive(Debug, Default)]
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn run(&self, cmd: Command) -> Result<String> {
+        run_command(cmd)
+    }
+
+    fn run_as_root(&self, cmd: Command) -> Result<String> {
+        run_as_root(cmd)
+    }
+
+    fn run_or_sudo(&self, cmd: Command) -> Result<String> {
+        run_or_sudo(cmd)
+    }
+
+    fn success(&self, mut cmd: Command) -> bool {
+        cmd.status().is_ok_and(|status| status.success())
+    }
+}
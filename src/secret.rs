@@ -0,0 +1,272 @@
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use derive_more::Debug;
+use ring::aead::{Aad, CHACHA20_POLY1305, LessSafeKey, NONCE_LEN, Nonce, UnboundKey};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::cell::RefCell;
+use std::fs;
+use std::io::{IsTerminal, Write};
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// 加密和解密配置中的`secret`内容，密钥是口令或`pioneer keygen`生成的密钥文件
+///
+/// 密钥依次从密钥文件、环境变量`PIONEER_SECRET_KEY`中读取，都没有时在终端中询问，只在第一次用到时读取
+///
+/// 密文格式为`pioneer:v1:<base64(salt | nonce | 密文)>`，用PBKDF2-SHA256从口令派生ChaCha20-Poly1305的密钥
+#[derive(Debug, Default)]
+pub struct SecretKey {
+    key_file: RefCell<Option<PathBuf>>,
+
+    #[debug(ignore)]
+    passphrase: RefCell<Option<String>>,
+}
+
+impl SecretKey {
+    const PREFIX: &str = "pioneer:v1:";
+    const ENV: &str = "PIONEER_SECRET_KEY";
+    const SALT_LEN: usize = 16;
+    const KEY_LEN: usize = 32;
+    const ITERATIONS: NonZeroU32 = NonZeroU32::new(200_000).unwrap();
+
+    pub fn set_key_file(&self, file: PathBuf) {
+        *self.key_file.borrow_mut() = Some(file);
+    }
+
+    /// 生成随机的密钥，保存到文件后用`--key-file`使用
+    pub fn generate() -> Result<String> {
+        let mut key = [0u8; Self::KEY_LEN];
+        SystemRandom::new()
+            .fill(&mut key)
+            .map_err(|_| anyhow!("Fail to generate random key"))?;
+        Ok(STANDARD.encode(key))
+    }
+
+    pub fn encrypt<B: AsRef<[u8]>>(&self, plain: B) -> Result<String> {
+        let random = SystemRandom::new();
+        let mut salt = [0u8; Self::SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        random
+            .fill(&mut salt)
+            .and_then(|_| random.fill(&mut nonce))
+            .map_err(|_| anyhow!("Fail to generate random salt"))?;
+
+        let key = self.derive(&salt, true)?;
+        let mut sealed = plain.as_ref().to_vec();
+        key.seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::empty(),
+            &mut sealed,
+        )
+        .map_err(|_| anyhow!("Fail to encrypt secret"))?;
+
+        Ok(format!(
+            "{}{}",
+            Self::PREFIX,
+            STANDARD.encode([&salt[..], &nonce[..], &sealed[..]].concat())
+        ))
+    }
+
+    pub fn decrypt(&self, secret: &str) -> Result<Vec<u8>> {
+        let data = Self::parse(secret)?;
+        let (salt, rest) = data.split_at(Self::SALT_LEN);
+        let (nonce, sealed) = rest.split_at(NONCE_LEN);
+
+        let key = self.derive(salt, false)?;
+        let mut sealed = sealed.to_vec();
+        let plain = key
+            .open_in_place(
+                Nonce::try_assume_unique_for_key(nonce).unwrap(),
+                Aad::empty(),
+                &mut sealed,
+            )
+            .map_err(|_| {
+                anyhow!("Fail to decrypt secret, the key is wrong or the secret is broken")
+            })?;
+
+        Ok(plain.to_vec())
+    }
+
+    /// 只检查密文的格式，不需要密钥
+    pub fn check_format(secret: &str) -> Result<()> {
+        Self::parse(secret).map(|_| ())
+    }
+
+    fn parse(secret: &str) -> Result<Vec<u8>> {
+        let encoded = secret
+            .trim()
+            .strip_prefix(Self::PREFIX)
+            .ok_or(anyhow!("Secret must start with {}", Self::PREFIX))?;
+        let data = STANDARD
+            .decode(encoded)
+            .context("Secret is not valid base64")?;
+
+        ensure!(
+            data.len() >= Self::SALT_LEN + NONCE_LEN + CHACHA20_POLY1305.tag_len(),
+            "Secret is too short"
+        );
+        Ok(data)
+    }
+
+    fn derive(&self, salt: &[u8], confirm: bool) -> Result<LessSafeKey> {
+        let passphrase = self.passphrase(confirm)?;
+
+        let mut key = [0u8; Self::KEY_LEN];
+        pbkdf2::derive(
+            pbkdf2::PBKDF2_HMAC_SHA256,
+            Self::ITERATIONS,
+            salt,
+            passphrase.as_bytes(),
+            &mut key,
+        );
+
+        Ok(LessSafeKey::new(
+            UnboundKey::new(&CHACHA20_POLY1305, &key).unwrap(),
+        ))
+    }
+
+    fn passphrase(&self, confirm: bool) -> Result<String> {
+        if let Some(passphrase) = self.passphrase.borrow().as_ref() {
+            return Ok(passphrase.clone());
+        }
+
+        let passphrase = match (self.key_file.borrow().as_ref(), std::env::var(Self::ENV)) {
+            (Some(file), _) => fs::read_to_string(file)
+                .context(format!("Fail to read {}", file.to_string_lossy()))?
+                .trim()
+                .to_string(),
+            (None, Ok(passphrase)) => passphrase,
+            (None, Err(_)) => Self::prompt(confirm)?,
+        };
+
+        ensure!(!passphrase.is_empty(), "Secret key is empty");
+
+        *self.passphrase.borrow_mut() = Some(passphrase.clone());
+        Ok(passphrase)
+    }
+
+    /// 在终端中不回显地读取口令
+    fn prompt(confirm: bool) -> Result<String> {
+        if !std::io::stdin().is_terminal() {
+            bail!(
+                "Secret key is required, provide it by --key-file or ${}",
+                Self::ENV
+            );
+        }
+
+        let read = |message: &str| -> Result<String> {
+            eprint!("{message}: ");
+            std::io::stderr().flush()?;
+
+            Command::new("stty").arg("-echo").status()?;
+            let mut passphrase = String::new();
+            let read = std::io::stdin().read_line(&mut passphrase);
+            Command::new("stty").arg("echo").status()?;
+            eprintln!();
+
+            read?;
+            Ok(passphrase.trim_end_matches(['\r', '\n']).to_string())
+        };
+
+        let passphrase = read("Secret passphrase")?;
+        if confirm {
+            ensure!(
+                read("Confirm passphrase")? == passphrase,
+                "Passphrases do not match"
+            );
+        }
+
+        Ok(passphrase)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    /// 使用密钥文件的SecretKey，返回的文件需要在使用期间保留
+    fn secret_key(passphrase: &str) -> (SecretKey, NamedTempFile) {
+        let file = NamedTempFile::new().unwrap();
+        fs::write(file.path(), format!("{passphrase}\n")).unwrap();
+        let key = SecretKey::default();
+        key.set_key_file(file.path().to_path_buf());
+        (key, file)
+    }
+
+    #[test]
+    fn round_trip() {
+        let (key, _file) = secret_key("correct horse");
+        let secret = key.encrypt("token=42").unwrap();
+
+        assert!(secret.starts_with(SecretKey::PREFIX), "{secret}");
+        assert!(!secret.contains("token"));
+        assert_eq!(key.decrypt(&secret).unwrap(), b"token=42");
+        /* 每次加密使用新的salt和nonce */
+        assert_ne!(key.encrypt("token=42").unwrap(), secret);
+        /* 配置中的密文前后可能有空白 */
+        assert_eq!(key.decrypt(&format!(" {secret}\n")).unwrap(), b"token=42");
+    }
+
+    #[test]
+    fn wrong_key_is_an_error() {
+        let (key, _file) = secret_key("correct horse");
+        let secret = key.encrypt("token=42").unwrap();
+
+        let (wrong, _wrong_file) = secret_key("battery staple");
+        let err = wrong.decrypt(&secret).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Fail to decrypt secret, the key is wrong or the secret is broken"
+        );
+    }
+
+    #[test]
+    fn tampered_secret_is_an_error() {
+        let (key, _file) = secret_key("correct horse");
+        let secret = key.encrypt("token=42").unwrap();
+
+        let mut data = SecretKey::parse(&secret).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        let tampered = format!("{}{}", SecretKey::PREFIX, STANDARD.encode(data));
+
+        assert!(key.decrypt(&tampered).is_err());
+    }
+
+    #[test]
+    fn empty_value_round_trip() {
+        let (key, _file) = secret_key("correct horse");
+        let secret = key.encrypt("").unwrap();
+
+        assert_eq!(key.decrypt(&secret).unwrap(), b"");
+    }
+
+    #[test]
+    fn parse_format() {
+        /* 空值的密文只有salt、nonce和tag */
+        let encode =
+            |len: usize| format!("{}{}", SecretKey::PREFIX, STANDARD.encode(vec![0u8; len]));
+        let min_len = SecretKey::SALT_LEN + NONCE_LEN + 16;
+        assert!(SecretKey::check_format(&encode(min_len)).is_ok());
+        assert_eq!(
+            SecretKey::check_format(&encode(min_len - 1))
+                .unwrap_err()
+                .to_string(),
+            "Secret is too short"
+        );
+
+        for (secret, message) in [
+            ("plain text", "Secret must start with pioneer:v1:"),
+            ("pioneer:v2:AAAA", "Secret must start with pioneer:v1:"),
+            ("pioneer:v1:not base64!", "Secret is not valid base64"),
+            ("pioneer:v1:AAAA", "Secret is too short"),
+        ] {
+            let err = SecretKey::check_format(secret).unwrap_err();
+            assert_eq!(err.to_string(), message, "{secret}");
+        }
+    }
+}
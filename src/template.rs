@@ -0,0 +1,337 @@
+use crate::condition::Host;
+use crate::types::config::{
+    AliasSpec, CommandEntry, Completion, Config, Content, EnvEntry, EnvrcEntry, FileEntry, StringOr,
+};
+use anyhow::{Result, bail};
+use regex::{Captures, Regex};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// 渲染配置中的`{{ 变量名 }}`，变量包括内置变量、配置中的输入和`[vars]`定义的变量
+///
+/// 渲染的范围: 环境变量的值、别名的命令、命令和envrc的内容、文件的安装路径和内容，
+/// 内容为文件或url时只渲染路径，加密的内容不渲染。不是合法变量名的`{{ }}`保持原样
+#[derive(Debug)]
+pub struct Template {
+    config_file: PathBuf,
+    vars: HashMap<String, String>,
+}
+
+impl Template {
+    pub const BUILTINS: [&str; 8] = [
+        "home",
+        "user",
+        "deploy_dir",
+        "config_name",
+        "config_dir",
+        "arch",
+        "distro",
+        "distro_version",
+    ];
+    const PATTERN: &str = r"\{\{\s*([A-Za-z_][A-Za-z0-9_]*)\s*\}\}";
+
+    /// `config_file`中的配置`config`可用的变量，部署目录为`deploy_dir`，`inputs`是配置中输入的值
+    pub fn from_config<P1, P2>(
+        config: &Config,
+        config_file: P1,
+        deploy_dir: P2,
+        host: &Host,
+        inputs: &HashMap<String, String>,
+    ) -> Result<Self>
+    where
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        let config_file = config_file.as_ref().to_path_buf();
+        let config_dir = config_file
+            .canonicalize()
+            .ok()
+            .and_then(|file| file.parent().map(Path::to_path_buf))
+            .unwrap_or_default();
+        let home = host.home_dir()?;
+        let user = std::env::var("USER").unwrap_or_else(|_| {
+            home.file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string()
+        });
+
+        let builtins = [
+            home.to_string_lossy().to_string(),
+            user,
+            deploy_dir.as_ref().to_string_lossy().to_string(),
+            config.infomation.name.clone(),
+            config_dir.to_string_lossy().to_string(),
+            host.arch().to_string(),
+            host.os()
+                .map(|os| os.id.clone())
+                .unwrap_or("unknown".to_string()),
+            host.os()
+                .and_then(|os| os.version_id.clone())
+                .unwrap_or_default(),
+        ];
+
+        let mut template = Self {
+            config_file,
+            vars: Self::BUILTINS
+                .iter()
+                .map(|name| name.to_string())
+                .zip(builtins)
+                .collect(),
+        };
+
+        for (name, value) in inputs {
+            if Self::BUILTINS.contains(&name.as_str()) {
+                bail!(
+                    "Input {name} in {} overrides a built-in variable",
+                    template.config_file.to_string_lossy()
+                );
+            }
+            template.vars.insert(name.clone(), value.clone());
+        }
+
+        /* 自定义变量只能引用内置变量和输入 */
+        let mut vars = HashMap::new();
+        for (name, value) in config.vars.iter().flatten() {
+            if Self::BUILTINS.contains(&name.as_str()) {
+                bail!(
+                    "vars.{name} in {} overrides a built-in variable",
+                    template.config_file.to_string_lossy()
+                );
+            }
+            if inputs.contains_key(name) {
+                bail!(
+                    "vars.{name} in {} has the same name as an input",
+                    template.config_file.to_string_lossy()
+                );
+            }
+            vars.insert(
+                name.clone(),
+                template.render(value, &format!("vars.{name}"))?,
+            );
+        }
+        template.vars.extend(vars);
+
+        Ok(template)
+    }
+
+    /// 渲染一段文本，`location`用于报告未知的变量，如`install.env.PATH`
+    pub fn render(&self, text: &str, location: &str) -> Result<String> {
+        let re = Regex::new(Self::PATTERN).unwrap();
+        let mut unknown = None;
+
+        let rendered = re.replace_all(text, |caps: &Captures| match self.vars.get(&caps[1]) {
+            Some(value) => value.clone(),
+            None => {
+                unknown.get_or_insert(caps[1].to_string());
+                caps[0].to_string()
+            }
+        });
+
+        if let Some(name) = unknown {
+            bail!(
+                "Unknown variable {name} in {} at {location}",
+                self.config_file.to_string_lossy()
+            );
+        }
+
+        Ok(rendered.into_owned())
+    }
+
+    /// 渲染整个配置，返回渲染后的副本
+    pub fn render_config(&self, config: &Config) -> Result<Config> {
+        let mut config = config.clone();
+        let install = &mut config.install;
+
+        for (name, entry) in install.env.iter_mut().flatten() {
+            let value = match entry {
+                EnvEntry::Value(value) => value,
+                EnvEntry::Spec(spec) => &mut spec.value,
+            };
+            if let StringOr::String(value) = value {
+                *value = self.render(value, &format!("install.env.{name}"))?;
+            }
+        }
+
+        for (name, alias) in install.alias.iter_mut().flatten() {
+            let location = format!("install.alias.{name}");
+            match alias {
+                StringOr::String(command) | StringOr::Object(AliasSpec { command, .. }) => {
+                    *command = self.render(command, &location)?
+                }
+            }
+        }
+
+        for (name, entry) in install.command.iter_mut().flatten() {
+            let location = format!("install.command.{name}");
+            match entry {
+                CommandEntry::Content(content) => self.render_string_or(content, &location)?,
+                CommandEntry::Spec(spec) => {
+                    self.render_string_or(&mut spec.content, &location)?;
+                    if let Some(Completion::Script(script)) = &mut spec.completion {
+                        self.render_string_or(script, &format!("{location}.completion"))?;
+                    }
+                }
+            }
+        }
+
+        for (index, entry) in install.envrc.iter_mut().flatten().enumerate() {
+            let location = format!("install.envrc[{index}]");
+            match entry {
+                EnvrcEntry::Content(content) => self.render_content(content, &location)?,
+                EnvrcEntry::Spec(spec) => self.render_content(&mut spec.content, &location)?,
+            }
+        }
+
+        if let Some(files) = install.files.take() {
+            let mut rendered = HashMap::new();
+            for (path, mut entry) in files {
+                let location = format!("install.files.{}", path.to_string_lossy());
+                match &mut entry {
+                    FileEntry::Content(content) => self.render_string_or(content, &location)?,
+                    FileEntry::Spec(spec) => self.render_string_or(&mut spec.content, &location)?,
+                }
+                rendered.insert(
+                    PathBuf::from(self.render(&path.to_string_lossy(), &location)?),
+                    entry,
+                );
+            }
+            install.files = Some(rendered);
+        }
+
+        Ok(config)
+    }
+
+    fn render_string_or(&self, content: &mut StringOr<Content>, location: &str) -> Result<()> {
+        match content {
+            StringOr::String(text) => *text = self.render(text, location)?,
+            StringOr::Object(content) => self.render_content(content, location)?,
+        }
+        Ok(())
+    }
+
+    fn render_content(&self, content: &mut Content, location: &str) -> Result<()> {
+        match content {
+            Content::Raw(text) | Content::Url(text) => *text = self.render(text, location)?,
+            Content::File(path) => {
+                *path = PathBuf::from(self.render(&path.to_string_lossy(), location)?)
+            }
+            /* 密文不渲染 */
+            Content::Secret(_) => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::fake::FakeApt;
+    use std::rc::Rc;
+    use tempfile::TempDir;
+
+    fn parse(toml: &str) -> Config {
+        toml::from_str(toml).unwrap()
+    }
+
+    fn template(config: &Config, home: &Path, inputs: &[(&str, &str)]) -> Result<Template> {
+        let host = Host::fake(Rc::new(FakeApt::new().with_home(home)));
+        let inputs = inputs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+
+        Template::from_config(config, "demo.toml", home.join(".distro"), &host, &inputs)
+    }
+
+    #[test]
+    fn builtins_vars_and_inputs() {
+        let home = TempDir::new().unwrap();
+        let config = parse(
+            r#"
+[vars]
+bin = "{{ home }}/bin"
+
+[infomation]
+name = "demo"
+
+[install.env]
+BIN = "{{ bin }}"
+MAIL = "{{email}}"
+WHERE = "{{ distro }} {{ distro_version }} {{ arch }} {{ config_name }}"
+
+[install.files]
+"{{ config_name }}.conf" = "deployed to {{ deploy_dir }}"
+"#,
+        );
+
+        let rendered = template(&config, home.path(), &[("email", "me@example.com")])
+            .unwrap()
+            .render_config(&config)
+            .unwrap();
+
+        let env = rendered.install.env.unwrap();
+        let value = |name: &str| env[name].value().clone();
+        let home = home.path().to_string_lossy();
+        assert_eq!(value("BIN"), StringOr::String(format!("{home}/bin")));
+        assert_eq!(value("MAIL"), StringOr::String("me@example.com".into()));
+        assert_eq!(
+            value("WHERE"),
+            StringOr::String("debian 12 x86_64 demo".into())
+        );
+
+        let files = rendered.install.files.unwrap();
+        assert_eq!(
+            files[Path::new("demo.conf")].content(),
+            &StringOr::String(format!("deployed to {home}/.distro"))
+        );
+    }
+
+    #[test]
+    fn unknown_variable_is_an_error() {
+        let home = TempDir::new().unwrap();
+        let config = parse(
+            "[infomation]\nname = \"demo\"\n[install.alias]\nll = \"ls {{ nope }} {{ not a var }}\"\n",
+        );
+
+        let err = template(&config, home.path(), &[])
+            .unwrap()
+            .render_config(&config)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown variable nope in demo.toml at install.alias.ll"
+        );
+
+        /* 变量只能引用内置变量和输入，不能引用其他变量 */
+        let config =
+            parse("[vars]\na = \"x\"\nb = \"{{ a }}\"\n[infomation]\nname = \"demo\"\n[install]\n");
+        let err = template(&config, home.path(), &[]).unwrap_err();
+        assert_eq!(err.to_string(), "Unknown variable a in demo.toml at vars.b");
+    }
+
+    #[test]
+    fn builtins_can_not_be_overridden() {
+        let home = TempDir::new().unwrap();
+        let config = parse("[vars]\nhome = \"/tmp\"\n[infomation]\nname = \"demo\"\n[install]\n");
+        let err = template(&config, home.path(), &[]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "vars.home in demo.toml overrides a built-in variable"
+        );
+
+        let config = parse("[infomation]\nname = \"demo\"\n[install]\n");
+        let err = template(&config, home.path(), &[("user", "root")]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Input user in demo.toml overrides a built-in variable"
+        );
+
+        let config = parse("[vars]\nemail = \"x\"\n[infomation]\nname = \"demo\"\n[install]\n");
+        let err = template(&config, home.path(), &[("email", "y")]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "vars.email in demo.toml has the same name as an input"
+        );
+    }
+}
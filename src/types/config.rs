@@ -12,6 +12,18 @@ pub struct ConfigList {
 /// 配置列表的格式
 #[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 pub struct Config {
+    /// 继承的基础配置，可以是相对于当前配置文件的路径，也可以是同目录下的配置文件名(省略`.toml`)，当前配置中的内容优先
+    pub extends: Option<String>,
+
+    /// 合并其他配置文件的安装列表，路径相对于当前配置文件，按顺序合并在基础配置之后、当前配置之前
+    pub include: Option<Vec<PathBuf>>,
+
+    /// 自定义变量: <变量名> <值>，可以在环境变量、别名、命令、envrc和文件中用`{{ 变量名 }}`引用，值中可以引用内置变量
+    pub vars: Option<HashMap<String, String>>,
+
+    /// 安装时需要用户输入的值，如git的邮箱，可以像自定义变量一样引用
+    pub inputs: Option<Vec<Input>>,
+
     /// 基本信息
     pub infomation: Info,
 
@@ -30,28 +42,498 @@ pub struct Info {
 
     /// 部署条件，默认为true，shell命令运行为true时，配置列表才会部署
     pub install_while: Option<String>,
+
+    /// 结构化的部署条件，不需要运行shell，与`install_while`同时存在时两者都需要满足
+    pub when: Option<Condition>,
+
+    /// 依赖的其他配置名称，如`["bash", "rust"]`，依赖的配置会先部署，依赖的配置不满足部署条件时该配置也不会部署
+    pub requires: Option<Vec<String>>,
+}
+
+/// 部署条件，所有给出的条件都满足时才满足
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub struct Condition {
+    /// 发行版ID，匹配`/etc/os-release`中的`ID`或`ID_LIKE`，满足其中一个即可，如`["ubuntu", "debian"]`
+    pub distro: Option<Vec<String>>,
+
+    /// 发行版版本号或版本代号，支持通配符，如`"22.*"`、`"jammy"`
+    pub version: Option<String>,
+
+    /// CPU架构，满足其中一个即可，如`["x86_64", "aarch64"]`，也可以使用`amd64`、`arm64`等dpkg的写法
+    pub arch: Option<Vec<String>>,
+
+    /// 内核版本(`uname -r`)，支持通配符，如`"*WSL2*"`
+    pub kernel: Option<String>,
+
+    /// 主机名，支持通配符，如`"lab-*"`
+    pub hostname: Option<String>,
+
+    /// 环境变量: <变量名> <值>，值支持通配符，`"*"`表示只要求变量存在
+    pub env: Option<HashMap<String, String>>,
+
+    /// PATH中需要存在的命令
+    pub command: Option<Vec<String>>,
+
+    /// 需要存在的文件或目录，支持`~/`开头的路径
+    pub file: Option<Vec<PathBuf>>,
+
+    /// 是否在容器(docker、podman、lxc等)中运行
+    pub container: Option<bool>,
+
+    /// 所有条件都满足
+    pub all: Option<Vec<Condition>>,
+
+    /// 任一条件满足
+    pub any: Option<Vec<Condition>>,
+
+    /// 条件不满足
+    pub not: Option<Box<Condition>>,
+}
+
+/// 安装时需要用户输入的值，依次从应答文件、环境变量`PIONEER_<配置名>_<输入名>`、上次部署保存的值中读取，都没有时在终端中询问
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub struct Input {
+    /// 输入名，也是引用时的变量名
+    pub name: String,
+
+    /// 询问时显示的说明
+    pub description: Option<String>,
+
+    /// 值的类型，默认为字符串
+    #[serde(rename = "type")]
+    pub kind: Option<InputType>,
+
+    /// 默认值，非交互运行时没有提供值则使用默认值
+    pub default: Option<String>,
+
+    /// 值需要完整匹配的正则表达式，如`".+@.+"`
+    pub pattern: Option<String>,
+}
+
+/// 输入值的类型
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum InputType {
+    #[default]
+    String,
+    /// `true`或`false`，输入时也接受`yes`、`no`、`y`、`n`
+    Bool,
+    Integer,
 }
 
 /// 安装文件的列表
 #[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
 pub struct InstallList {
-    /// 需要apt软件源安装的软件列表
-    pub apt: Option<Vec<String>>,
+    /// 需要安装的软件列表，使用当前系统的包管理器安装: <包名|包名定义>
+    pub packages: Option<Vec<StringOr<PackageName>>>,
 
-    /// 命令别名，与`alias mycmd="echo mycmd run"`效果类似: <命令别名> <实际命令>
-    pub alias: Option<HashMap<String, String>>,
+    /// 需要apt软件源安装的软件列表，仅在使用apt的系统上安装: <包名|安装选项>
+    pub apt: Option<Vec<StringOr<AptPackage>>>,
 
-    /// 额外自定义命令，可以是脚本内容或者二进制文件: <命令名> <脚本内容|二进制文件路径>
-    pub command: Option<HashMap<String, StringOr<Content>>>,
+    /// 第三方apt软件源: <软件源名称> <软件源定义>，会在安装apt软件之前添加并更新软件源
+    pub apt_repo: Option<HashMap<String, AptRepo>>,
 
-    /// 环境变量
-    pub env: Option<HashMap<String, String>>,
+    /// debconf问题的预设答案: <问题> <类型 值|答案定义>，如`"wireshark-common/install-setuid" = "boolean true"`，在安装apt软件包之前设置
+    pub debconf: Option<HashMap<String, StringOr<DebconfSpec>>>,
+
+    /// 需要安装的.deb软件包，可以是文件或url，通过apt安装以解决依赖，卸载时会被移除
+    pub deb: Option<Vec<DebEntry>>,
+
+    /// 需要dnf安装的软件列表，仅在使用dnf的系统上安装: <包名|包名定义>
+    pub dnf: Option<Vec<StringOr<PackageName>>>,
+
+    /// 需要pacman安装的软件列表，仅在使用pacman的系统上安装: <包名|包名定义>
+    pub pacman: Option<Vec<StringOr<PackageName>>>,
+
+    /// 需要apk安装的软件列表，仅在使用apk的系统上安装: <包名|包名定义>
+    pub apk: Option<Vec<StringOr<PackageName>>>,
+
+    /// 需要zypper安装的软件列表，仅在使用zypper的系统上安装: <包名|包名定义>
+    pub zypper: Option<Vec<StringOr<PackageName>>>,
+
+    /// 命令别名，与`alias mycmd="echo mycmd run"`效果类似: <命令别名> <实际命令|别名定义>
+    pub alias: Option<HashMap<String, StringOr<AliasSpec>>>,
+
+    /// 额外自定义命令，可以是脚本内容或者二进制文件，也可以附带补全定义: <命令名> <脚本内容|二进制文件路径|命令定义>
+    pub command: Option<HashMap<String, CommandEntry>>,
+
+    /// 环境变量: <变量名> <值|加密的值|环境变量定义>
+    pub env: Option<HashMap<String, EnvEntry>>,
 
     /// 额外rc脚本，可以在里面定义函数，或者做一些初始化的操作，会在.bashrc里source
-    pub envrc: Option<Vec<Content>>,
+    pub envrc: Option<Vec<EnvrcEntry>>,
+
+    /// 需要复制的文件，默认会安装在固定目录下，局限较大，不建议使用，优先使用以上列表: <路径> <内容|文件定义>
+    pub files: Option<HashMap<PathBuf, FileEntry>>,
+}
+
+/// apt软件包的安装选项
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub struct AptPackage {
+    /// 包名
+    pub name: String,
+
+    /// 指定版本，支持通配符，如`5:24.*`
+    pub version: Option<String>,
+
+    /// 安装后执行`apt-mark hold`，避免被升级
+    pub hold: Option<bool>,
+
+    /// 安装时不安装推荐的软件包(`--no-install-recommends`)
+    pub no_install_recommends: Option<bool>,
+
+    /// 从指定的发行版本中安装(`-t`)，如`bookworm-backports`
+    pub target_release: Option<String>,
+
+    /// 备选包名，`name`在当前系统中找不到时按顺序尝试，如`fd-find`的备选为`fd`
+    pub alternatives: Option<Vec<String>>,
+
+    /// 不同发行版中的包名: <发行版ID[:版本代号|:版本号]> <包名>，如`"ubuntu:focal" = "python3.8-venv"`
+    pub distro: Option<HashMap<String, String>>,
+
+    /// 安装条件，不满足时跳过该软件包
+    pub when: Option<Condition>,
+}
+
+impl From<&StringOr<AptPackage>> for AptPackage {
+    fn from(value: &StringOr<AptPackage>) -> Self {
+        match value {
+            StringOr::String(name) => AptPackage {
+                name: name.clone(),
+                ..Default::default()
+            },
+            StringOr::Object(package) => package.clone(),
+        }
+    }
+}
+
+/// 在不同发行版中名字不同的软件包
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub struct PackageName {
+    /// 包名
+    pub name: String,
+
+    /// 备选包名，`name`在当前系统中找不到时按顺序尝试
+    pub alternatives: Option<Vec<String>>,
+
+    /// 不同发行版中的包名: <发行版ID[:版本代号|:版本号]> <包名>，如`"fedora" = "fd-find"`
+    pub distro: Option<HashMap<String, String>>,
+
+    /// 安装条件，不满足时跳过该软件包
+    pub when: Option<Condition>,
+}
+
+impl From<&StringOr<PackageName>> for PackageName {
+    fn from(value: &StringOr<PackageName>) -> Self {
+        match value {
+            StringOr::String(name) => PackageName {
+                name: name.clone(),
+                ..Default::default()
+            },
+            StringOr::Object(package) => package.clone(),
+        }
+    }
+}
+
+/// 命令别名的完整定义
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub struct AliasSpec {
+    /// 实际命令
+    pub command: String,
+
+    /// 安装条件，不满足时跳过该别名
+    pub when: Option<Condition>,
+}
+
+impl From<&StringOr<AliasSpec>> for AliasSpec {
+    fn from(value: &StringOr<AliasSpec>) -> Self {
+        match value {
+            StringOr::String(command) => AliasSpec {
+                command: command.clone(),
+                ..Default::default()
+            },
+            StringOr::Object(alias) => alias.clone(),
+        }
+    }
+}
+
+impl From<&String> for PackageName {
+    fn from(name: &String) -> Self {
+        PackageName {
+            name: name.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+/// deb822格式的apt软件源
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub struct AptRepo {
+    /// 软件源地址，如`https://download.docker.com/linux/ubuntu`
+    pub uris: Vec<String>,
+
+    /// 发行版本，如`jammy`
+    pub suites: Vec<String>,
+
+    /// 组件，如`stable`、`main`
+    pub components: Option<Vec<String>>,
+
+    /// 限定架构，如`amd64`
+    pub architectures: Option<Vec<String>>,
+
+    /// 软件源的签名公钥，ascii armor格式的公钥会先被dearmor
+    pub key: Content,
+
+    /// 添加条件，不满足时跳过该软件源
+    pub when: Option<Condition>,
+}
+
+/// debconf问题答案的完整定义
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub struct DebconfSpec {
+    /// 答案: <类型 值>，如`boolean true`
+    pub answer: String,
+
+    /// 设置条件，不满足时跳过该答案
+    pub when: Option<Condition>,
+}
+
+impl From<&StringOr<DebconfSpec>> for DebconfSpec {
+    fn from(value: &StringOr<DebconfSpec>) -> Self {
+        match value {
+            StringOr::String(answer) => DebconfSpec {
+                answer: answer.clone(),
+                ..Default::default()
+            },
+            StringOr::Object(spec) => spec.clone(),
+        }
+    }
+}
+
+/// .deb软件包，可以只给出文件或url，也可以带上安装条件
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum DebEntry {
+    /// .deb文件或url
+    Content(Content),
+
+    /// 带安装条件的.deb软件包
+    Spec(Box<DebSpec>),
+}
+
+impl DebEntry {
+    pub fn content(&self) -> &Content {
+        match self {
+            DebEntry::Content(content) => content,
+            DebEntry::Spec(spec) => &spec.content,
+        }
+    }
+
+    pub fn when(&self) -> Option<&Condition> {
+        match self {
+            DebEntry::Content(_) => None,
+            DebEntry::Spec(spec) => spec.when.as_ref(),
+        }
+    }
+}
+
+/// .deb软件包的完整定义
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub struct DebSpec {
+    /// .deb文件或url
+    pub content: Content,
+
+    /// 安装条件，不满足时跳过该软件包
+    pub when: Option<Condition>,
+}
+
+/// 自定义命令，可以只给出命令内容，也可以带上补全定义
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum CommandEntry {
+    /// 命令内容: <脚本内容|二进制文件路径>
+    Content(StringOr<Content>),
+
+    /// 带补全定义的命令
+    Spec(Box<CommandSpec>),
+}
+
+impl CommandEntry {
+    pub fn content(&self) -> &StringOr<Content> {
+        match self {
+            CommandEntry::Content(content) => content,
+            CommandEntry::Spec(spec) => &spec.content,
+        }
+    }
+
+    pub fn completion(&self) -> Option<&Completion> {
+        match self {
+            CommandEntry::Content(_) => None,
+            CommandEntry::Spec(spec) => spec.completion.as_ref(),
+        }
+    }
+
+    pub fn when(&self) -> Option<&Condition> {
+        match self {
+            CommandEntry::Content(_) => None,
+            CommandEntry::Spec(spec) => spec.when.as_ref(),
+        }
+    }
+}
 
-    /// 需要复制的文件，默认会安装在固定目录下，局限较大，不建议使用，优先使用以上列表
-    pub files: Option<HashMap<PathBuf, StringOr<Content>>>,
+/// 命令的完整定义
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub struct CommandSpec {
+    /// 命令内容: <脚本内容|二进制文件路径>
+    pub content: StringOr<Content>,
+
+    /// 命令的补全定义，会安装到bash-completion的用户目录下
+    pub completion: Option<Completion>,
+
+    /// 安装条件，不满足时跳过该命令及其补全
+    pub when: Option<Condition>,
+}
+
+/// 命令补全，可以是bash-completion脚本，也可以是简单的子命令和参数列表
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Completion {
+    /// bash-completion脚本: <脚本内容|脚本文件路径>
+    Script(StringOr<Content>),
+
+    /// 声明式的补全列表
+    Words(CompletionWords),
+}
+
+/// 声明式的补全列表，子命令只在第一个参数处补全，参数在任意位置补全
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub struct CompletionWords {
+    /// 子命令列表
+    pub subcommands: Option<Vec<String>>,
+
+    /// 参数列表，如`--help`
+    pub flags: Option<Vec<String>>,
+}
+
+/// rc脚本，可以只给出脚本内容，也可以标记为延迟加载
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum EnvrcEntry {
+    /// rc脚本内容，每次启动shell时加载
+    Content(Content),
+
+    /// 带加载选项的rc脚本
+    Spec(Box<EnvrcSpec>),
+}
+
+impl EnvrcEntry {
+    pub fn content(&self) -> &Content {
+        match self {
+            EnvrcEntry::Content(content) => content,
+            EnvrcEntry::Spec(spec) => &spec.content,
+        }
+    }
+
+    pub fn lazy(&self) -> &[String] {
+        match self {
+            EnvrcEntry::Content(_) => &[],
+            EnvrcEntry::Spec(spec) => spec.lazy.as_deref().unwrap_or_default(),
+        }
+    }
+
+    pub fn when(&self) -> Option<&Condition> {
+        match self {
+            EnvrcEntry::Content(_) => None,
+            EnvrcEntry::Spec(spec) => spec.when.as_ref(),
+        }
+    }
+}
+
+/// rc脚本的完整定义
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub struct EnvrcSpec {
+    /// rc脚本内容
+    pub content: Content,
+
+    /// 延迟加载的触发命令，如`["nvm", "node"]`，第一次调用其中任一命令时才会source该脚本
+    pub lazy: Option<Vec<String>>,
+
+    /// 加载条件，不满足时跳过该脚本
+    pub when: Option<Condition>,
+}
+
+/// 环境变量，可以只给出值，也可以带上设置条件
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum EnvEntry {
+    /// 值或加密的值
+    Value(StringOr<Secret>),
+
+    /// 带设置条件的环境变量
+    Spec(Box<EnvSpec>),
+}
+
+impl EnvEntry {
+    pub fn value(&self) -> &StringOr<Secret> {
+        match self {
+            EnvEntry::Value(value) => value,
+            EnvEntry::Spec(spec) => &spec.value,
+        }
+    }
+
+    pub fn when(&self) -> Option<&Condition> {
+        match self {
+            EnvEntry::Value(_) => None,
+            EnvEntry::Spec(spec) => spec.when.as_ref(),
+        }
+    }
+}
+
+/// 环境变量的完整定义
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub struct EnvSpec {
+    /// 值或加密的值
+    pub value: StringOr<Secret>,
+
+    /// 设置条件，不满足时跳过该环境变量
+    pub when: Option<Condition>,
+}
+
+/// 需要复制的文件，可以只给出内容，也可以带上安装条件
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum FileEntry {
+    /// 文件内容: <内容|文件路径|url|加密的内容>
+    Content(StringOr<Content>),
+
+    /// 带安装条件的文件
+    Spec(Box<FileSpec>),
+}
+
+impl FileEntry {
+    pub fn content(&self) -> &StringOr<Content> {
+        match self {
+            FileEntry::Content(content) => content,
+            FileEntry::Spec(spec) => &spec.content,
+        }
+    }
+
+    pub fn when(&self) -> Option<&Condition> {
+        match self {
+            FileEntry::Content(_) => None,
+            FileEntry::Spec(spec) => spec.when.as_ref(),
+        }
+    }
+}
+
+/// 文件的完整定义
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq)]
+pub struct FileSpec {
+    /// 文件内容: <内容|文件路径|url|加密的内容>
+    pub content: StringOr<Content>,
+
+    /// 安装条件，不满足时跳过该文件
+    pub when: Option<Condition>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq, PartialOrd, Ord)]
@@ -67,4 +549,43 @@ pub enum Content {
     Raw(String),
     File(PathBuf),
     Url(String),
+    /// `pioneer encrypt`加密后的内容，安装时才解密，只能用于环境变量和文件
+    Secret(String),
+}
+
+/// 加密的值，与`{ secret = "..." }`形式的`Content`相同
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq, PartialOrd, Ord)]
+/* 不允许其它字段，否则`{ secret = "...", when = ... }`会被当作值，条件被悄悄丢掉 */
+#[serde(deny_unknown_fields)]
+pub struct Secret {
+    /// `pioneer encrypt`输出的密文
+    pub secret: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(value: &str) -> Result<EnvEntry, toml::de::Error> {
+        toml::from_str::<HashMap<String, EnvEntry>>(&format!("TOKEN = {value}"))
+            .map(|mut env| env.remove("TOKEN").unwrap())
+    }
+
+    #[test]
+    fn conditional_secret_env_keeps_its_condition() {
+        let secret = StringOr::Object(Secret {
+            secret: "pioneer:v1:AAAA".into(),
+        });
+
+        let plain = env(r#"{ secret = "pioneer:v1:AAAA" }"#).unwrap();
+        assert_eq!(plain, EnvEntry::Value(secret.clone()));
+
+        let spec =
+            env(r#"{ value = { secret = "pioneer:v1:AAAA" }, when = { arch = ["x86_64"] } }"#)
+                .unwrap();
+        assert_eq!(spec.value(), &secret);
+        assert!(spec.when().is_some());
+
+        assert!(env(r#"{ secret = "pioneer:v1:AAAA", when = { arch = ["x86_64"] } }"#).is_err());
+    }
 }